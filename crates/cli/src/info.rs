@@ -0,0 +1,23 @@
+use clap::Args;
+use hac::crypto::keyset::KeySet;
+use hac::formats::nca::Nca;
+use hac::info::nca_info_json;
+use hac::snafu::{ResultExt, Whatever};
+use hac::storage::FileRoStorage;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct Opts {
+    /// Path to the NCA file
+    file: PathBuf,
+}
+
+pub fn main(opts: Opts) -> Result<(), Whatever> {
+    let keys = KeySet::from_system(None).whatever_context("Could not load the keyset")?;
+    let storage = FileRoStorage::open(&opts.file).whatever_context("Opening the file")?;
+    let nca = Nca::new(&keys, storage).whatever_context("Could not parse the NCA")?;
+
+    println!("{:#}", nca_info_json(&nca));
+
+    Ok(())
+}
@@ -6,8 +6,7 @@ use hac::filesystem::{
 use hac::formats::nca::{IntegrityCheckLevel, Nca};
 use hac::formats::pfs::PartitionFileSystem;
 use hac::formats::ticket::Ticket;
-use hac::snafu::{ResultExt, Snafu, Whatever};
-use hac::storage::ReadableStorageExt;
+use hac::snafu::{ResultExt, Whatever};
 use hac::switch_fs::content_set::AnyContentInfo;
 use hac::switch_fs::SwitchFs;
 use itertools::Itertools;
@@ -31,29 +30,7 @@ fn walk_fs(root_dir: impl ReadableDirectory, depth: usize) {
 }
 
 fn extract_fs(root_dir: impl ReadableDirectory, path: &Path) {
-    std::fs::create_dir_all(path).unwrap();
-    for entry in root_dir.entries() {
-        match entry {
-            Entry::Directory(dir) => {
-                let path = path.join(dir.name());
-                std::fs::create_dir_all(&path).unwrap();
-                extract_fs(dir, &path);
-            }
-            Entry::File(file) => {
-                let path = path.join(file.name());
-                let storage = file.storage().unwrap();
-                // println!("Extracting {}...", path.display());
-                storage.save_to_file(path).unwrap();
-            }
-        }
-    }
-}
-
-#[derive(Snafu, Debug)]
-#[snafu(crate_root(hac::snafu))]
-struct Error {
-    message: String,
-    source: Whatever,
+    root_dir.extract_to_parallel(path).unwrap();
 }
 
 #[allow(unused)]
@@ -68,7 +45,10 @@ pub fn test_nca() -> Result<(), Whatever> {
     println!("{:#?}", nca);
 
     let start = std::time::Instant::now();
-    let fs0 = nca.get_section_fs(0, IntegrityCheckLevel::Full).unwrap();
+    let fs0 = nca
+        .get_section_fs(0, IntegrityCheckLevel::Full)
+        .unwrap()
+        .unwrap();
     extract_fs(fs0.root(), &PathBuf::from(base_name.clone() + ".0dir"));
     let duration = start.elapsed();
 
@@ -76,7 +56,10 @@ pub fn test_nca() -> Result<(), Whatever> {
 
     // measure time it took us to write the file
     let start = std::time::Instant::now();
-    let fs1 = nca.get_section_fs(1, IntegrityCheckLevel::Full).unwrap();
+    let fs1 = nca
+        .get_section_fs(1, IntegrityCheckLevel::Full)
+        .unwrap()
+        .unwrap();
     extract_fs(fs1.root(), &PathBuf::from(base_name.clone() + ".1dir"));
     let duration = start.elapsed();
 
@@ -84,7 +67,10 @@ pub fn test_nca() -> Result<(), Whatever> {
 
     // measure time it took us to write the file
     let start = std::time::Instant::now();
-    let fs2 = nca.get_section_fs(2, IntegrityCheckLevel::Full).unwrap();
+    let fs2 = nca
+        .get_section_fs(2, IntegrityCheckLevel::Full)
+        .unwrap()
+        .unwrap();
     extract_fs(fs2.root(), &PathBuf::from(base_name.clone() + ".2dir"));
     let duration = start.elapsed();
 
@@ -106,7 +92,10 @@ pub fn test_ncz() -> Result<(), Whatever> {
     println!("{:#?}", nca);
 
     let start = std::time::Instant::now();
-    let fs0 = nca.get_section_fs(0, IntegrityCheckLevel::Full).unwrap();
+    let fs0 = nca
+        .get_section_fs(0, IntegrityCheckLevel::Full)
+        .unwrap()
+        .unwrap();
     extract_fs(fs0.root(), &PathBuf::from(base_name.clone() + ".0dir"));
     let duration = start.elapsed();
 
@@ -114,7 +103,10 @@ pub fn test_ncz() -> Result<(), Whatever> {
 
     // measure time it took us to write the file
     let start = std::time::Instant::now();
-    let fs1 = nca.get_section_fs(1, IntegrityCheckLevel::Full).unwrap();
+    let fs1 = nca
+        .get_section_fs(1, IntegrityCheckLevel::Full)
+        .unwrap()
+        .unwrap();
     extract_fs(fs1.root(), &PathBuf::from(base_name.clone() + ".1dir"));
     let duration = start.elapsed();
 
@@ -122,7 +114,10 @@ pub fn test_ncz() -> Result<(), Whatever> {
 
     // measure time it took us to write the file
     let start = std::time::Instant::now();
-    let fs2 = nca.get_section_fs(2, IntegrityCheckLevel::Full).unwrap();
+    let fs2 = nca
+        .get_section_fs(2, IntegrityCheckLevel::Full)
+        .unwrap()
+        .unwrap();
     extract_fs(fs2.root(), &PathBuf::from(base_name.clone() + ".2dir"));
     let duration = start.elapsed();
 
@@ -221,11 +216,7 @@ pub fn test_switch_fs() -> Result<(), Whatever> {
 
     println!(
         "Files in the merged FS:\n{:#?}",
-        merged_fs
-            .root()
-            .entries_recursive()
-            .flat_map(|(n, e)| e.file().map(|_| n))
-            .collect::<Vec<_>>()
+        merged_fs.file_map().keys().collect::<Vec<_>>()
     );
 
     let switch_fs =
@@ -244,7 +235,7 @@ pub fn test_switch_fs() -> Result<(), Whatever> {
                 );
 
                 for program in app.programs.iter() {
-                    let program_title = program.control.any_title().unwrap();
+                    let program_title = program.control.as_ref().unwrap().any_title().unwrap();
                     println!(
                         "    Program {}: {:?} by {:?}",
                         program.id, program_title.name, program_title.publisher
@@ -259,7 +250,7 @@ pub fn test_switch_fs() -> Result<(), Whatever> {
                 );
 
                 for program in patch.programs.iter() {
-                    let program_title = program.control.any_title().unwrap();
+                    let program_title = program.control.as_ref().unwrap().any_title().unwrap();
                     println!(
                         "    Program {}: {:?} by {:?}",
                         program.id, program_title.name, program_title.publisher
@@ -272,7 +263,9 @@ pub fn test_switch_fs() -> Result<(), Whatever> {
                     id, version, data.application_id
                 );
             }
-            AnyContentInfo::DataPatch(_) => todo!(),
+            AnyContentInfo::DataPatch(patch) => {
+                println!("DataPatch   {} v{} for {}", id, version, patch.data_id);
+            }
         }
     }
 
@@ -288,6 +281,8 @@ pub fn test_switch_fs() -> Result<(), Whatever> {
             .next()
             .unwrap()
             .control
+            .as_ref()
+            .unwrap()
             .any_title()
             .unwrap();
         println!(
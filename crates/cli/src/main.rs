@@ -1,6 +1,6 @@
 use clap::{Parser, Subcommand};
-use hac::snafu::ErrorCompat;
 
+mod info;
 mod junk;
 mod nsp;
 
@@ -14,6 +14,8 @@ struct Opts {
 #[derive(Subcommand)]
 enum Action {
     Nsp(nsp::Opts),
+    /// Dump the metadata of an NCA file as JSON
+    Info(info::Opts),
     Junk,
 }
 
@@ -24,14 +26,11 @@ fn main() {
 
     let result = match opts.action {
         Action::Nsp(opts) => nsp::main(opts),
+        Action::Info(opts) => info::main(opts),
         Action::Junk => junk::main(),
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        eprintln!("Caused by:");
-        for cause in e.iter_chain().skip(1) {
-            eprintln!(" - {}", cause);
-        }
+        eprintln!("{}", hac::format_error_chain(&e));
     }
 }
@@ -2,7 +2,8 @@ use camino::{Utf8Path, Utf8PathBuf};
 use clap::{Args, Subcommand};
 use hac::crypto::keyset::KeySet;
 use hac::formats::pfs::PartitionFileSystem;
-use hac::snafu::{ErrorCompat, OptionExt, ResultExt, Whatever};
+use hac::snafu::{whatever, OptionExt, ResultExt, Whatever};
+use hac::switch_fs::content_set::AnyContentInfo;
 use hac::switch_fs::SwitchFs;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
@@ -34,6 +35,7 @@ fn rename_one(opts: &RenameOpts, keys: &KeySet, path: &Utf8Path) -> Result<(), W
     static PREFIX_REX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\[[^]]+]").unwrap());
 
     let pfs = PartitionFileSystem::from_path(path).whatever_context("Opening NSP")?;
+    pfs.validate().whatever_context("Validating NSP")?;
     let switch_fs = SwitchFs::new(keys, &pfs).whatever_context("Could not open Switch FS")?;
 
     let title = switch_fs
@@ -43,6 +45,14 @@ fn rename_one(opts: &RenameOpts, keys: &KeySet, path: &Utf8Path) -> Result<(), W
         .ok()
         .whatever_context("Could not find exactly one title (some weird NSP?)")?;
 
+    let title_name = match title {
+        AnyContentInfo::Application(app) => app.any_title(),
+        AnyContentInfo::Patch(patch) => patch.any_title(),
+        _ => whatever!("Only applications and patches can be renamed"),
+    }
+    .whatever_context("Could not find the title name")?;
+    let content_meta_key = title.content_meta_key();
+
     let old_filename = path
         .file_name()
         .whatever_context("Could not get filename")?;
@@ -57,9 +67,9 @@ fn rename_one(opts: &RenameOpts, keys: &KeySet, path: &Utf8Path) -> Result<(), W
         "{}{}{} [{}][v{}].nsp",
         prefix,
         if prefix.is_empty() { "" } else { " " },
-        todo!("title.any_title().unwrap().name"),
-        todo!("title.title_id()"),
-        todo!("title.version()"),
+        title_name.name,
+        content_meta_key.id,
+        content_meta_key.version,
     );
 
     if new_filename == old_filename {
@@ -97,9 +107,7 @@ fn rename(opts: RenameOpts) -> Result<(), Whatever> {
         if let Err(e) = rename_one(&opts, &keys, &file) {
             if opts.verbose_errors {
                 eprintln!("Error renaming {}:", file);
-                for e in e.iter_chain() {
-                    eprintln!(" - {}", e);
-                }
+                eprintln!("{}", hac::format_error_chain(&e));
             } else {
                 eprintln!("Error renaming {}: {}", file, e);
             }
@@ -3,6 +3,8 @@ use crate::formats::ticket::Ticket;
 use crate::ids::{IdParseError, RightsId};
 use binrw::{BinRead, BinWrite};
 use ini::Properties;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
@@ -20,6 +22,7 @@ pub struct KeySet {
     title_keys: BTreeMap<RightsId, TitleKey>,
 }
 
+#[derive(Clone)]
 pub struct KeyName {
     pub key_name: &'static str,
     pub index: Option<u8>,
@@ -41,7 +44,7 @@ impl Display for KeyName {
     }
 }
 
-#[derive(Snafu, Debug)]
+#[derive(Snafu, Debug, Clone)]
 pub struct MissingKeyError {
     pub key_name: KeyName,
 }
@@ -85,13 +88,34 @@ pub enum SystemKeysetError {
     Io { source: std::io::Error },
 }
 
-#[derive(Snafu, Debug)]
+#[derive(Snafu, Debug, Clone)]
 /// Missing title key for RightsId {rights_id}
 pub struct MissingTitleKeyError {
     pub rights_id: RightsId,
 }
 
+/// A source of title keys that are not in the [`KeySet`], consulted when a title key is missing from it
+///
+/// Allows resolving the title keys lazily, e.g. by asking the user or fetching them.
+/// Implemented for closures and for [`KeySet`] references (to use a secondary key set).
+pub trait TitleKeyProvider {
+    fn title_key(&mut self, rights_id: &RightsId) -> Option<TitleKey>;
+}
+
+impl<F: FnMut(&RightsId) -> Option<TitleKey>> TitleKeyProvider for F {
+    fn title_key(&mut self, rights_id: &RightsId) -> Option<TitleKey> {
+        self(rights_id)
+    }
+}
+
+impl TitleKeyProvider for &KeySet {
+    fn title_key(&mut self, rights_id: &RightsId) -> Option<TitleKey> {
+        KeySet::title_key(self, rights_id).ok()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum KeyAreaKeyIndex {
     Application = 0,
@@ -189,7 +213,7 @@ impl KeySet {
                     index: Some(i as u8),
                 };
                 let key = props
-                    .get(&key_name.to_string())
+                    .get(key_name.to_string().as_str())
                     .map(|s| s.parse())
                     .transpose()
                     .map_err(|source| KeySetParseError::KeyParse { key_name, source })?;
@@ -280,4 +304,13 @@ impl KeySet {
                 rights_id: *rights_id,
             })
     }
+
+    pub fn has_title_key(&self, rights_id: &RightsId) -> bool {
+        self.title_keys.contains_key(rights_id)
+    }
+
+    /// All the title keys loaded from `title.keys` or imported from tickets, ordered by rights id
+    pub fn title_keys(&self) -> impl Iterator<Item = (&RightsId, &TitleKey)> {
+        self.title_keys.iter()
+    }
 }
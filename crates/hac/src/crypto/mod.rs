@@ -4,6 +4,7 @@ use binrw::{BinRead, BinWrite};
 use cipher::generic_array::GenericArray;
 use ctr::Ctr128BE;
 use hex::FromHexError;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use std::str::FromStr;
@@ -17,17 +18,22 @@ pub enum KeyParseError {
     InvalidChar { char: char, index: usize },
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, BinRead, BinWrite)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EncryptedAesKey(HexData<0x10>);
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, BinRead, BinWrite)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EncryptedAesXtsKey(HexData<0x20>);
 
 /// Represents an encrypted AES-128 title key.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TitleKey(HexData<0x10>);
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AesKey(HexData<0x10>);
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AesXtsKey(HexData<0x20>);
 
 fn parse_key(s: &str, result: &mut [u8]) -> Result<(), KeyParseError> {
@@ -84,7 +90,20 @@ impl From<[u8; 0x10]> for TitleKey {
     }
 }
 
+impl From<[u8; 0x10]> for AesKey {
+    fn from(data: [u8; 0x10]) -> Self {
+        AesKey(HexData(data))
+    }
+}
+
 impl AesKey {
+    /// Returns a short fingerprint of the key (first bytes of its SHA-256), safe to show in logs.
+    pub fn fingerprint(&self) -> HexData<0x4> {
+        use digest::Digest;
+        let hash = sha2::Sha256::digest(self.0 .0);
+        HexData(hash[..0x4].try_into().unwrap())
+    }
+
     fn derive_key(&self, source: &[u8; 0x10]) -> AesKey {
         use cipher::{BlockDecrypt, KeyInit};
         let mut newkey = *source;
@@ -118,7 +137,7 @@ impl AesKey {
     pub fn decrypt_ctr(&self, buf: &mut [u8], ctr: &[u8; 0x10]) {
         use cipher::{KeyIvInit, StreamCipher};
 
-        if buf.len() % 16 != 0 {
+        if !buf.len().is_multiple_of(16) {
             panic!("Length must be multiple of sectors!")
         }
 
@@ -131,7 +150,7 @@ impl AesKey {
     pub fn encrypt_ctr(&self, buf: &mut [u8], ctr: &[u8; 0x10]) {
         use cipher::{KeyIvInit, StreamCipher};
 
-        if buf.len() % 16 != 0 {
+        if !buf.len().is_multiple_of(16) {
             panic!("Length must be multiple of sectors!")
         }
 
@@ -154,7 +173,7 @@ fn get_tweak(mut sector: usize) -> [u8; 0x10] {
 
 impl AesXtsKey {
     #[inline]
-    fn to_crypter(&self) -> Xts128<Aes128> {
+    fn crypter(&self) -> Xts128<Aes128> {
         use cipher::KeyInit;
 
         let key1 = Aes128::new(GenericArray::from_slice(&self.0 .0[0x00..0x10]));
@@ -163,11 +182,11 @@ impl AesXtsKey {
     }
 
     pub fn decrypt(&self, data: &mut [u8], mut sector: usize, sector_size: usize) {
-        if data.len() % sector_size != 0 {
+        if !data.len().is_multiple_of(sector_size) {
             panic!("Length must be multiple of sectors!")
         }
 
-        let crypter = self.to_crypter();
+        let crypter = self.crypter();
 
         for i in (0..data.len()).step_by(sector_size) {
             let tweak = get_tweak(sector);
@@ -178,11 +197,11 @@ impl AesXtsKey {
     }
 
     pub fn encrypt(&self, data: &mut [u8], mut sector: usize, sector_size: usize) {
-        if data.len() % sector_size != 0 {
+        if !data.len().is_multiple_of(sector_size) {
             panic!("Length must be multiple of sectors!")
         }
 
-        let crypter = self.to_crypter();
+        let crypter = self.crypter();
 
         for i in (0..data.len()).step_by(sector_size) {
             let tweak = get_tweak(sector);
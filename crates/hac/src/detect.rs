@@ -0,0 +1,120 @@
+//! Telling the container formats apart by their magics, without relying on the file extensions
+
+use crate::storage::{ReadableStorage, ReadableStorageExt};
+
+/// Offset of the NCZ section header, right after the (still encrypted) NCA headers
+const NCZ_MAGIC_OFFSET: u64 = 0x4000;
+/// Offset of the gamecard header magic, after the signature
+const XCI_MAGIC_OFFSET: u64 = 0x100;
+/// Offset of the NCA magic, after the two signatures
+const NCA_MAGIC_OFFSET: u64 = 0x200;
+const PFS0_HEADER_SIZE: u64 = 0x10;
+const PFS0_ENTRY_SIZE: u64 = 0x18;
+/// Anything larger can't be a real string table, don't bother reading it
+const MAX_PFS0_STRING_TABLE_SIZE: u64 = 0x100000;
+const ROMFS_HEADER_SIZE: u64 = 0x50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DetectedFormat {
+    /// A PFS0 containing NCAs or NCZs (also covers NSZs)
+    Nsp,
+    /// A gamecard image, without the key area
+    Xci,
+    /// A plaintext NCA, the encrypted ones can't be told apart without the header key
+    Nca,
+    /// An NCZ (a compressed NCA)
+    Ncz,
+    /// A PFS0 not containing NCAs, like an ExeFS
+    Pfs0,
+    /// A HFS0, like the partitions of an XCI
+    Hfs0,
+    /// A raw RomFS image
+    RomFs,
+    Unknown,
+}
+
+fn magic_at(storage: &impl ReadableStorage, offset: u64) -> Option<[u8; 8]> {
+    let mut magic = [0; 8];
+    match storage.read_partial(offset, &mut magic) {
+        Ok(8) => Some(magic),
+        _ => None,
+    }
+}
+
+/// Whether the PFS0 at the start of the storage has any NCA or NCZ file
+fn pfs0_has_ncas(storage: &impl ReadableStorage, header: [u8; 8]) -> bool {
+    let file_count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+    let Some(sizes) = magic_at(storage, 8) else {
+        return false;
+    };
+    let string_table_size = u32::from_le_bytes(sizes[..4].try_into().unwrap()) as u64;
+    if string_table_size > MAX_PFS0_STRING_TABLE_SIZE {
+        return false;
+    }
+
+    let mut string_table = vec![0; string_table_size as usize];
+    let offset = PFS0_HEADER_SIZE + file_count * PFS0_ENTRY_SIZE;
+    if storage.read(offset, &mut string_table).is_err() {
+        return false;
+    }
+
+    string_table
+        .split(|&b| b == 0)
+        .any(|name| name.ends_with(b".nca") || name.ends_with(b".ncz"))
+}
+
+/// Guesses the format of the file in `storage` from its magics
+///
+/// Storages that can't be read are reported as [`DetectedFormat::Unknown`].
+pub fn detect_format(storage: &impl ReadableStorage) -> DetectedFormat {
+    // the NCA headers of an NCZ are encrypted, but the NCZ header following them is not
+    if magic_at(storage, NCZ_MAGIC_OFFSET).is_some_and(|magic| &magic == b"NCZSECTN") {
+        return DetectedFormat::Ncz;
+    }
+    if magic_at(storage, XCI_MAGIC_OFFSET).is_some_and(|magic| &magic[..4] == b"HEAD") {
+        return DetectedFormat::Xci;
+    }
+    if magic_at(storage, NCA_MAGIC_OFFSET)
+        .is_some_and(|magic| matches!(&magic[..4], b"NCA0" | b"NCA1" | b"NCA2" | b"NCA3"))
+    {
+        return DetectedFormat::Nca;
+    }
+
+    let Some(header) = magic_at(storage, 0) else {
+        return DetectedFormat::Unknown;
+    };
+    match &header[..4] {
+        b"PFS0" if pfs0_has_ncas(storage, header) => DetectedFormat::Nsp,
+        b"PFS0" => DetectedFormat::Pfs0,
+        b"HFS0" => DetectedFormat::Hfs0,
+        // RomFS has no magic, but the header size is fixed
+        _ if u64::from_le_bytes(header) == ROMFS_HEADER_SIZE => DetectedFormat::RomFs,
+        _ => DetectedFormat::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_format, DetectedFormat};
+    use crate::formats::nca::test_fixture::{make_pfs0, make_plaintext_nca};
+    use crate::formats::nca::NcaContentType;
+    use crate::storage::VecStorage;
+
+    #[test]
+    fn detect() {
+        let nsp = make_pfs0(&[("0123.cnmt.nca", b"nca"), ("0123.tik", b"ticket")]);
+        assert_eq!(detect_format(&VecStorage::new(nsp)), DetectedFormat::Nsp);
+
+        let exefs = make_pfs0(&[("main", b"nso"), ("main.npdm", b"npdm")]);
+        assert_eq!(detect_format(&VecStorage::new(exefs)), DetectedFormat::Pfs0);
+
+        let nca = make_plaintext_nca(NcaContentType::Meta, &[("a.cnmt", b"cnmt")], 0x200);
+        assert_eq!(detect_format(&nca), DetectedFormat::Nca);
+
+        assert_eq!(
+            detect_format(&VecStorage::new(vec![0; 0x10])),
+            DetectedFormat::Unknown
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt::Write;
+
+/// Formats an error along with all its causes, one per line:
+///
+/// ```text
+/// Error: X
+/// Caused by:
+///  - Y
+///  - Z
+/// ```
+pub fn format_error_chain(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut result = format!("Error: {}", error);
+    let mut causes = snafu::ChainCompat::new(error).skip(1).peekable();
+    if causes.peek().is_some() {
+        result.push_str("\nCaused by:");
+        for cause in causes {
+            write!(result, "\n - {}", cause).unwrap();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_error_chain;
+    use snafu::{ResultExt, Snafu};
+
+    #[derive(Snafu, Debug)]
+    enum TestError {
+        /// Outer
+        Outer { source: std::io::Error },
+        /// Leaf
+        Leaf {},
+    }
+
+    #[test]
+    fn chain() {
+        let error = Err::<(), _>(std::io::Error::other("inner"))
+            .context(OuterSnafu)
+            .unwrap_err();
+        assert_eq!(
+            format_error_chain(&error),
+            "Error: Outer\nCaused by:\n - inner"
+        );
+        assert_eq!(format_error_chain(&TestError::Leaf {}), "Error: Leaf");
+    }
+}
@@ -0,0 +1,52 @@
+//! Writing a whole filesystem into an archive, streaming the files without temporary copies
+
+#[cfg(feature = "tar")]
+mod tar;
+#[cfg(feature = "zip")]
+mod zip;
+
+use crate::filesystem::{ReadableDirectory, ReadableFile};
+use crate::storage::StorageError;
+use snafu::{AsErrorSource, Snafu};
+use std::fmt::{Debug, Display};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArchiveFormat {
+    /// A tar archive, with the GNU extensions for long names and large files
+    #[cfg(feature = "tar")]
+    Tar,
+    /// A zip archive with the files stored uncompressed, with the ZIP64 extensions for large files
+    #[cfg(feature = "zip")]
+    Zip,
+}
+
+#[derive(Snafu, Debug)]
+pub enum ArchiveError<E: Debug + Display + AsErrorSource + 'static> {
+    /// Failed to open file {path}
+    ArchiveOpenFile { path: String, source: E },
+    /// Failed to read file {path}
+    ArchiveRead { path: String, source: StorageError },
+    /// Failed to write {path} to the archive
+    ArchiveWrite {
+        path: String,
+        source: std::io::Error,
+    },
+    /// Failed to finish the archive
+    ArchiveFinish { source: std::io::Error },
+}
+
+/// Writes all the files and directories in `root` (recursively) to `writer` as an archive in the given `format`
+pub fn archive_fs<D: ReadableDirectory>(
+    root: &D,
+    writer: impl Write,
+    format: ArchiveFormat,
+) -> Result<(), ArchiveError<<D::File as ReadableFile>::Error>> {
+    match format {
+        #[cfg(feature = "tar")]
+        ArchiveFormat::Tar => tar::write_tar(root, writer),
+        #[cfg(feature = "zip")]
+        ArchiveFormat::Zip => zip::write_zip(root, writer),
+    }
+}
@@ -0,0 +1,123 @@
+use super::{ArchiveError, ArchiveFinishSnafu, ArchiveOpenFileSnafu, ArchiveWriteSnafu};
+use crate::filesystem::{Entry, ReadableDirectory, ReadableDirectoryExt, ReadableFile};
+use crate::storage::{ReadableStorage, ReadableStorageExt};
+use snafu::ResultExt;
+use std::io::Write;
+
+const BLOCK_SIZE: usize = 0x200;
+/// Longest name that fits in the header, longer ones need a GNU long name entry
+const NAME_SIZE: usize = 100;
+/// Sizes from this one up don't fit in the octal field and are stored in base-256 (a GNU extension)
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+pub(super) fn write_tar<D: ReadableDirectory>(
+    root: &D,
+    mut writer: impl Write,
+) -> Result<(), ArchiveError<<D::File as ReadableFile>::Error>> {
+    for (path, entry) in root.entries_recursive() {
+        let name = path.trim_start_matches('/');
+        match entry {
+            Entry::Directory(_) => {
+                write_tar_header(&mut writer, &format!("{}/", name), b'5', 0)
+                    .context(ArchiveWriteSnafu { path })?;
+            }
+            Entry::File(file) => {
+                let storage = file
+                    .storage()
+                    .context(ArchiveOpenFileSnafu { path: &path })?;
+                let size = storage.get_size();
+
+                write_tar_header(&mut writer, name, b'0', size)
+                    .and_then(|_| std::io::copy(&mut storage.io(), &mut writer))
+                    .and_then(|_| write_tar_padding(&mut writer, size))
+                    .context(ArchiveWriteSnafu { path })?;
+            }
+        }
+    }
+
+    // the end of the archive is marked by two empty blocks
+    writer
+        .write_all(&[0; BLOCK_SIZE * 2])
+        .and_then(|_| writer.flush())
+        .context(ArchiveFinishSnafu)
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    // the last byte is the terminating NUL
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn write_tar_header(writer: &mut impl Write, name: &str, ty: u8, size: u64) -> std::io::Result<()> {
+    if name.len() > NAME_SIZE {
+        // GNU long name: the name is stored as the data of a special entry preceding the real one
+        let long_name = [name.as_bytes(), &[0]].concat();
+        write_tar_header(writer, "././@LongLink", b'L', long_name.len() as u64)?;
+        writer.write_all(&long_name)?;
+        write_tar_padding(writer, long_name.len() as u64)?;
+    }
+
+    let mut header = [0; BLOCK_SIZE];
+
+    let name_len = std::cmp::min(name.len(), NAME_SIZE);
+    header[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+    let mode = if ty == b'5' { 0o755 } else { 0o644 };
+    write_octal(&mut header[100..108], mode);
+    write_octal(&mut header[108..116], 0); // uid
+    write_octal(&mut header[116..124], 0); // gid
+    if size <= MAX_OCTAL_SIZE {
+        write_octal(&mut header[124..136], size);
+    } else {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    write_octal(&mut header[136..148], 0); // mtime
+    header[156] = ty;
+    header[257..265].copy_from_slice(b"ustar  \0");
+
+    // the checksum is computed with the checksum field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|&b| b as u64).sum();
+    write_octal(&mut header[148..155], checksum);
+    header[154] = 0;
+
+    writer.write_all(&header)
+}
+
+fn write_tar_padding(writer: &mut impl Write, size: u64) -> std::io::Result<()> {
+    let padding = (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE;
+    writer.write_all(&[0; BLOCK_SIZE][..padding])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BLOCK_SIZE;
+    use crate::filesystem::archive::{archive_fs, ArchiveFormat};
+    use crate::filesystem::ReadableFileSystem;
+    use crate::formats::nca::test_fixture::make_pfs0;
+    use crate::formats::pfs::PartitionFileSystem;
+    use crate::storage::VecStorage;
+
+    #[test]
+    fn tar() {
+        let long_name = "a".repeat(150);
+        let files: &[(&str, &[u8])] = &[("short.bin", b"hello"), (&long_name, &[0x42; 0x300])];
+        let fs = PartitionFileSystem::new(VecStorage::new(make_pfs0(files))).unwrap();
+
+        let mut tar = Vec::new();
+        archive_fs(&fs.root(), &mut tar, ArchiveFormat::Tar).unwrap();
+
+        // header + 1 block of data, long name header + 1 block of name, header + 2 blocks of data, 2 end blocks
+        assert_eq!(tar.len(), BLOCK_SIZE * 9);
+        assert_eq!(&tar[..9], b"short.bin");
+        assert_eq!(&tar[124..135], b"00000000005");
+        assert_eq!(&tar[BLOCK_SIZE..BLOCK_SIZE + 5], b"hello");
+        assert_eq!(tar[BLOCK_SIZE * 2 + 156], b'L');
+        assert_eq!(
+            &tar[BLOCK_SIZE * 3..BLOCK_SIZE * 3 + 150],
+            long_name.as_bytes()
+        );
+        assert_eq!(&tar[BLOCK_SIZE * 5..BLOCK_SIZE * 5 + 0x300], &[0x42; 0x300]);
+        assert!(tar[BLOCK_SIZE * 7..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,377 @@
+use super::{
+    ArchiveError, ArchiveFinishSnafu, ArchiveOpenFileSnafu, ArchiveReadSnafu, ArchiveWriteSnafu,
+};
+use crate::filesystem::{Entry, ReadableDirectory, ReadableDirectoryExt, ReadableFile};
+use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
+use snafu::ResultExt;
+use std::io::Write;
+
+/// Sizes and offsets from this one up don't fit in the 32-bit fields and are stored in the ZIP64 extra field
+const ZIP64_THRESHOLD: u64 = 0xffff_ffff;
+/// Same for the entry count in the end of central directory record
+const ZIP64_ENTRY_COUNT_THRESHOLD: u64 = 0xffff;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Upper byte of the "version made by", tells the readers that the external attributes hold the unix mode
+const HOST_UNIX: u16 = 3 << 8;
+/// The names are UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+/// 1980-01-01, the earliest date the MS-DOS format can represent
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+const READ_BUFFER_SIZE: usize = 0x10000;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Feeds `data` to a running CRC32, which starts (and ends) inverted
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn storage_crc32(storage: &impl ReadableStorage) -> Result<u32, StorageError> {
+    let size = storage.get_size();
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    let mut crc = !0;
+    for offset in (0..size).step_by(READ_BUFFER_SIZE) {
+        let buf = &mut buf[..std::cmp::min(READ_BUFFER_SIZE as u64, size - offset) as usize];
+        storage.read(offset, buf)?;
+        crc = crc32_update(crc, buf);
+    }
+    Ok(!crc)
+}
+
+/// What is needed to write the central directory record of an entry once its data is written
+struct ZipEntry {
+    name: String,
+    is_dir: bool,
+    crc: u32,
+    size: u64,
+    /// Offset of the local header in the archive
+    offset: u64,
+}
+
+/// Keeps track of the offset in the archive, the writer is not required to be seekable
+struct CountingWriter<W> {
+    inner: W,
+    position: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(super) fn write_zip<D: ReadableDirectory>(
+    root: &D,
+    writer: impl Write,
+) -> Result<(), ArchiveError<<D::File as ReadableFile>::Error>> {
+    let mut writer = CountingWriter {
+        inner: writer,
+        position: 0,
+    };
+    let mut entries = Vec::new();
+
+    for (path, entry) in root.entries_recursive() {
+        let name = path.trim_start_matches('/');
+        match entry {
+            Entry::Directory(_) => {
+                let entry = ZipEntry {
+                    name: format!("{}/", name),
+                    is_dir: true,
+                    crc: 0,
+                    size: 0,
+                    offset: writer.position,
+                };
+                write_local_header(&mut writer, &entry).context(ArchiveWriteSnafu { path })?;
+                entries.push(entry);
+            }
+            Entry::File(file) => {
+                let storage = file
+                    .storage()
+                    .context(ArchiveOpenFileSnafu { path: &path })?;
+                // the CRC goes before the data and the writer can't seek back to it, so the file is read twice
+                let crc = storage_crc32(&storage).context(ArchiveReadSnafu { path: &path })?;
+                let entry = ZipEntry {
+                    name: name.to_string(),
+                    is_dir: false,
+                    crc,
+                    size: storage.get_size(),
+                    offset: writer.position,
+                };
+
+                write_local_header(&mut writer, &entry)
+                    .and_then(|_| std::io::copy(&mut storage.io(), &mut writer))
+                    .context(ArchiveWriteSnafu { path })?;
+                entries.push(entry);
+            }
+        }
+    }
+
+    let directory_offset = writer.position;
+    for entry in &entries {
+        write_central_header(&mut writer, entry).context(ArchiveFinishSnafu)?;
+    }
+    let directory_size = writer.position - directory_offset;
+
+    write_end_of_directory(
+        &mut writer,
+        entries.len() as u64,
+        directory_offset,
+        directory_size,
+    )
+    .and_then(|_| writer.flush())
+    .context(ArchiveFinishSnafu)
+}
+
+/// The value of a 32-bit field, the actual one being in the ZIP64 extra field if it doesn't fit
+fn field32(value: u64) -> u32 {
+    std::cmp::min(value, ZIP64_THRESHOLD) as u32
+}
+
+fn zip64_extra(fields: &[u64]) -> Vec<u8> {
+    if fields.is_empty() {
+        return Vec::new();
+    }
+
+    let mut extra = Vec::with_capacity(4 + fields.len() * 8);
+    extra.extend_from_slice(&ZIP64_EXTRA_ID.to_le_bytes());
+    extra.extend_from_slice(&(fields.len() as u16 * 8).to_le_bytes());
+    for field in fields {
+        extra.extend_from_slice(&field.to_le_bytes());
+    }
+    extra
+}
+
+/// The part shared by the local and the central headers: from the version needed to extract to the length of the extra field
+fn push_common_fields(header: &mut Vec<u8>, entry: &ZipEntry, extra: &[u8]) -> std::io::Result<()> {
+    let name_len = u16::try_from(entry.name.len()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the name is too long for a zip archive",
+        )
+    })?;
+    let version = if extra.is_empty() {
+        VERSION_DEFAULT
+    } else {
+        VERSION_ZIP64
+    };
+
+    header.extend_from_slice(&version.to_le_bytes());
+    header.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // method: stored
+    header.extend_from_slice(&0u16.to_le_bytes()); // time
+    header.extend_from_slice(&DOS_DATE.to_le_bytes());
+    header.extend_from_slice(&entry.crc.to_le_bytes());
+    header.extend_from_slice(&field32(entry.size).to_le_bytes()); // compressed size
+    header.extend_from_slice(&field32(entry.size).to_le_bytes()); // uncompressed size
+    header.extend_from_slice(&name_len.to_le_bytes());
+    header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+    Ok(())
+}
+
+fn write_local_header(writer: &mut impl Write, entry: &ZipEntry) -> std::io::Result<()> {
+    // the local header has either both sizes in the ZIP64 extra field or none of them
+    let extra = if entry.size >= ZIP64_THRESHOLD {
+        zip64_extra(&[entry.size, entry.size])
+    } else {
+        Vec::new()
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+    push_common_fields(&mut header, entry, &extra)?;
+    header.extend_from_slice(entry.name.as_bytes());
+    header.extend_from_slice(&extra);
+
+    writer.write_all(&header)
+}
+
+fn write_central_header(writer: &mut impl Write, entry: &ZipEntry) -> std::io::Result<()> {
+    // the central header only has the fields that don't fit, in this order
+    let mut zip64_fields = Vec::new();
+    if entry.size >= ZIP64_THRESHOLD {
+        zip64_fields.extend([entry.size, entry.size]);
+    }
+    if entry.offset >= ZIP64_THRESHOLD {
+        zip64_fields.push(entry.offset);
+    }
+    let extra = zip64_extra(&zip64_fields);
+
+    let mode: u32 = if entry.is_dir { 0o40755 } else { 0o100644 };
+    // the low byte has the MS-DOS attributes, 0x10 marking a directory
+    let external_attributes = mode << 16 | if entry.is_dir { 0x10 } else { 0 };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    header.extend_from_slice(&(HOST_UNIX | VERSION_ZIP64).to_le_bytes());
+    push_common_fields(&mut header, entry, &extra)?;
+    header.extend_from_slice(&0u16.to_le_bytes()); // comment length
+    header.extend_from_slice(&0u16.to_le_bytes()); // disk number
+    header.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+    header.extend_from_slice(&external_attributes.to_le_bytes());
+    header.extend_from_slice(&field32(entry.offset).to_le_bytes());
+    header.extend_from_slice(entry.name.as_bytes());
+    header.extend_from_slice(&extra);
+
+    writer.write_all(&header)
+}
+
+fn write_end_of_directory(
+    writer: &mut CountingWriter<impl Write>,
+    entry_count: u64,
+    directory_offset: u64,
+    directory_size: u64,
+) -> std::io::Result<()> {
+    let mut record = Vec::new();
+
+    if entry_count >= ZIP64_ENTRY_COUNT_THRESHOLD
+        || directory_offset >= ZIP64_THRESHOLD
+        || directory_size >= ZIP64_THRESHOLD
+    {
+        let zip64_record_offset = writer.position;
+
+        record.extend_from_slice(&0x06064b50u32.to_le_bytes());
+        record.extend_from_slice(&44u64.to_le_bytes()); // size of the rest of the record
+        record.extend_from_slice(&(HOST_UNIX | VERSION_ZIP64).to_le_bytes());
+        record.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+        record.extend_from_slice(&0u32.to_le_bytes()); // disk number
+        record.extend_from_slice(&0u32.to_le_bytes()); // disk with the central directory
+        record.extend_from_slice(&entry_count.to_le_bytes()); // on this disk
+        record.extend_from_slice(&entry_count.to_le_bytes());
+        record.extend_from_slice(&directory_size.to_le_bytes());
+        record.extend_from_slice(&directory_offset.to_le_bytes());
+
+        // the locator, so that the readers can find the record above from the end of the archive
+        record.extend_from_slice(&0x07064b50u32.to_le_bytes());
+        record.extend_from_slice(&0u32.to_le_bytes()); // disk with the record
+        record.extend_from_slice(&zip64_record_offset.to_le_bytes());
+        record.extend_from_slice(&1u32.to_le_bytes()); // total number of disks
+    }
+
+    let entry_count = std::cmp::min(entry_count, ZIP64_ENTRY_COUNT_THRESHOLD) as u16;
+    record.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    record.extend_from_slice(&0u16.to_le_bytes()); // disk number
+    record.extend_from_slice(&0u16.to_le_bytes()); // disk with the central directory
+    record.extend_from_slice(&entry_count.to_le_bytes()); // on this disk
+    record.extend_from_slice(&entry_count.to_le_bytes());
+    record.extend_from_slice(&field32(directory_size).to_le_bytes());
+    record.extend_from_slice(&field32(directory_offset).to_le_bytes());
+    record.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+    writer.write_all(&record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32_update, write_central_header, ZipEntry};
+    use crate::filesystem::archive::{archive_fs, ArchiveFormat};
+    use crate::filesystem::ReadableFileSystem;
+    use crate::formats::nca::test_fixture::make_pfs0;
+    use crate::formats::pfs::PartitionFileSystem;
+    use crate::storage::VecStorage;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        !crc32_update(!0, data)
+    }
+
+    #[test]
+    fn zip() {
+        assert_eq!(crc32(b"hello"), 0x3610a686);
+
+        let long_name = "a".repeat(150);
+        let files: &[(&str, &[u8])] = &[("short.bin", b"hello"), (&long_name, &[0x42; 0x300])];
+        let fs = PartitionFileSystem::new(VecStorage::new(make_pfs0(files))).unwrap();
+
+        let mut zip = Vec::new();
+        archive_fs(&fs.root(), &mut zip, ArchiveFormat::Zip).unwrap();
+
+        // read it back by following the central directory, like the unzip tools do
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(u32_at(end, 0), 0x06054b50);
+        assert_eq!(u16_at(end, 10), 2);
+        let mut central = u32_at(end, 16) as usize;
+        assert_eq!(central + u32_at(end, 12) as usize, zip.len() - 22);
+
+        for &(name, data) in files {
+            assert_eq!(u32_at(&zip, central), 0x02014b50);
+            let name_len = u16_at(&zip, central + 28) as usize;
+            let extra_len = u16_at(&zip, central + 30) as usize;
+            assert_eq!(extra_len, 0);
+            assert_eq!(&zip[central + 46..central + 46 + name_len], name.as_bytes());
+            assert_eq!(u32_at(&zip, central + 16), crc32(data));
+            assert_eq!(u32_at(&zip, central + 24), data.len() as u32);
+
+            let local = u32_at(&zip, central + 42) as usize;
+            assert_eq!(u32_at(&zip, local), 0x04034b50);
+            let data_start =
+                local + 30 + u16_at(&zip, local + 26) as usize + u16_at(&zip, local + 28) as usize;
+            assert_eq!(&zip[data_start..data_start + data.len()], data);
+
+            central += 46 + name_len;
+        }
+    }
+
+    #[test]
+    fn zip64_central_header() {
+        let entry = ZipEntry {
+            name: "huge.bin".to_string(),
+            is_dir: false,
+            crc: 0,
+            size: 5 << 30,
+            offset: 6 << 30,
+        };
+        let mut header = Vec::new();
+        write_central_header(&mut header, &entry).unwrap();
+
+        assert_eq!(u16_at(&header, 6), 45);
+        assert_eq!(u32_at(&header, 20), 0xffff_ffff);
+        assert_eq!(u32_at(&header, 24), 0xffff_ffff);
+        assert_eq!(u32_at(&header, 42), 0xffff_ffff);
+
+        let extra = &header[46 + entry.name.len()..];
+        assert_eq!(u16_at(extra, 0), 0x0001);
+        assert_eq!(u16_at(extra, 2), 24);
+        assert_eq!(extra[4..12], (5u64 << 30).to_le_bytes());
+        assert_eq!(extra[12..20], (5u64 << 30).to_le_bytes());
+        assert_eq!(extra[20..28], (6u64 << 30).to_le_bytes());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::filesystem::{Entry, ReadableDirectory, ReadableFile, ReadableFileSystem};
+use crate::filesystem::{
+    Entry, EntryMetadata, ReadableDirectory, ReadableFile, ReadableFileSystem,
+};
 use indexmap::IndexMap;
 
 pub struct MergeDirectory<'a, F: ReadableFileSystem + 'a> {
@@ -109,6 +111,11 @@ impl<F: ReadableFileSystem> ReadableFileSystem for MergeFilesystem<F> {
     fn open_file(&self, path: &str) -> Option<Self::File<'_>> {
         self.filesystems.iter().find_map(|fs| fs.open_file(path))
     }
+
+    // don't collect the directories of all the filesystems like `open_directory` does
+    fn metadata(&self, path: &str) -> Option<EntryMetadata> {
+        self.filesystems.iter().find_map(|fs| fs.metadata(path))
+    }
 }
 
 impl<F: ReadableFileSystem> MergeFilesystem<F> {
@@ -1,8 +1,16 @@
+#[cfg(any(feature = "tar", feature = "zip"))]
+mod archive;
 pub mod merge_filesystem;
 
-use crate::storage::ReadableStorage;
-use snafu::AsErrorSource;
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub use archive::{archive_fs, ArchiveError, ArchiveFormat};
+
+use crate::parallel::parallel_chunks;
+use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
+use indexmap::IndexMap;
+use snafu::{AsErrorSource, ResultExt, Snafu};
 use std::fmt::{Debug, Display};
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug)]
 pub enum Entry<F: ReadableFile, D: ReadableDirectory> {
@@ -26,13 +34,37 @@ impl<F: ReadableFile, D: ReadableDirectory> Entry<F, D> {
     }
 }
 
+#[derive(Snafu, Debug)]
+pub enum FileReadError<E: Debug + Display + AsErrorSource + 'static> {
+    /// Failed to open the file
+    FileOpen { source: E },
+    /// Failed to read the file
+    FileRead { source: StorageError },
+}
+
+/// What is known about an entry without opening it, see [`ReadableFileSystem::metadata`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub is_dir: bool,
+    /// Size of the file, 0 for directories
+    pub size: u64,
+}
+
 pub trait ReadableFile: Sized {
     type Storage: ReadableStorage;
-    type Error: Debug + Display;
+    type Error: Debug + Display + AsErrorSource + 'static;
 
     fn name(&self) -> &str;
     fn size(&self) -> u64;
     fn storage(&self) -> Result<Self::Storage, Self::Error>;
+
+    /// Reads a range of the file, without touching the rest of it
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), FileReadError<Self::Error>> {
+        self.storage()
+            .context(FileOpenSnafu)?
+            .read(offset, buf)
+            .context(FileReadSnafu)
+    }
 }
 
 pub trait ReadableDirectory: Sized {
@@ -41,6 +73,16 @@ pub trait ReadableDirectory: Sized {
 
     fn name(&self) -> &str;
     fn entries(&self) -> Self::Iter;
+
+    /// Only the files in this directory (non-recursive)
+    fn entries_files(&self) -> impl Iterator<Item = Self::File> {
+        self.entries().filter_map(Entry::file)
+    }
+
+    /// Only the subdirectories of this directory (non-recursive)
+    fn entries_directories(&self) -> impl Iterator<Item = Self> {
+        self.entries().filter_map(Entry::directory)
+    }
 }
 
 pub trait ReadableFileSystem: Sized {
@@ -58,6 +100,44 @@ pub trait ReadableFileSystem: Sized {
     // TODO: actually, those can be extension methods
     fn open_directory(&self, path: &str) -> Option<Self::Directory<'_>>;
     fn open_file(&self, path: &str) -> Option<Self::File<'_>>;
+
+    /// Whether a file or a directory exists at `path`
+    fn exists(&self, path: &str) -> bool {
+        self.metadata(path).is_some()
+    }
+
+    /// Looks up the file or directory at `path`, without opening it
+    fn metadata(&self, path: &str) -> Option<EntryMetadata> {
+        if let Some(file) = self.open_file(path) {
+            return Some(EntryMetadata {
+                is_dir: false,
+                size: file.size(),
+            });
+        }
+        self.open_directory(path).map(|_| EntryMetadata {
+            is_dir: true,
+            size: 0,
+        })
+    }
+
+    /// All the files in the filesystem (recursively), keyed by their full path
+    fn file_map(&self) -> IndexMap<String, Self::File<'_>> {
+        self.root()
+            .entries_recursive()
+            .filter_map(|(path, entry)| entry.file().map(|file| (path, file)))
+            .collect()
+    }
+
+    /// Lazily opens the storages of all the files in the filesystem (recursively), along with their full path
+    ///
+    /// Allows streaming the files somewhere (e.g. with [`ReadableStorageExt::io`]) without extracting them first.
+    fn stream_files(
+        &self,
+    ) -> impl Iterator<Item = (String, Result<Self::Storage, Self::OpenError>)> + '_ {
+        self.root()
+            .entries_recursive()
+            .filter_map(|(path, entry)| entry.file().map(|file| (path, file.storage())))
+    }
 }
 
 pub struct RecursiveDirectoryIter<D: ReadableDirectory> {
@@ -73,7 +153,9 @@ impl<D: ReadableDirectory> Iterator for RecursiveDirectoryIter<D> {
             if let Some(it) = self.inner.last_mut() {
                 match it.next() {
                     None => {
-                        self.inner.pop().unwrap();
+                        self.inner.pop();
+                        // leave the directory we were iterating over
+                        self.path.truncate(self.path.rfind('/').unwrap_or(0));
                     }
                     Some(Entry::File(f)) => {
                         break Some((format!("{}/{}", self.path, f.name()), Entry::File(f)))
@@ -95,6 +177,21 @@ impl<D: ReadableDirectory> Iterator for RecursiveDirectoryIter<D> {
     }
 }
 
+#[derive(Snafu, Debug)]
+pub enum ExtractError<E: Debug + Display + AsErrorSource + 'static> {
+    /// Refusing to extract {path}: it would end up outside of the destination directory
+    InvalidPath { path: String },
+    /// Failed to create directory {path:?}
+    CreateDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Failed to open file {path}
+    OpenFile { path: String, source: E },
+    /// Failed to save file {path:?}
+    SaveFile { path: PathBuf, source: StorageError },
+}
+
 pub trait ReadableDirectoryExt: ReadableDirectory {
     fn entries_recursive(&self) -> RecursiveDirectoryIter<Self> {
         RecursiveDirectoryIter {
@@ -102,6 +199,92 @@ pub trait ReadableDirectoryExt: ReadableDirectory {
             path: "".to_string(),
         }
     }
+
+    /// Extracts all the files in this directory (recursively) to `dest` on the host filesystem
+    ///
+    /// The files are saved one by one, see [`extract_to_parallel`](Self::extract_to_parallel) for the multithreaded version.
+    fn extract_to(
+        &self,
+        dest: &Path,
+    ) -> Result<(), ExtractError<<Self::File as ReadableFile>::Error>> {
+        std::fs::create_dir_all(dest).context(CreateDirectorySnafu { path: dest })?;
+
+        for (path, entry) in self.entries_recursive() {
+            let dest_path = extraction_path(dest, &path)?;
+
+            match entry {
+                Entry::Directory(_) => {
+                    std::fs::create_dir_all(&dest_path)
+                        .context(CreateDirectorySnafu { path: dest_path })?;
+                }
+                Entry::File(file) => {
+                    let storage = file.storage().context(OpenFileSnafu { path })?;
+                    storage
+                        .save_to_file(&dest_path)
+                        .context(SaveFileSnafu { path: dest_path })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as [`extract_to`](Self::extract_to), but saves the files on all the available cores
+    ///
+    /// The directories are created and the files are opened upfront, only the saving itself is spread across the threads.
+    fn extract_to_parallel(
+        &self,
+        dest: &Path,
+    ) -> Result<(), ExtractError<<Self::File as ReadableFile>::Error>> {
+        std::fs::create_dir_all(dest).context(CreateDirectorySnafu { path: dest })?;
+
+        let mut files = Vec::new();
+        for (path, entry) in self.entries_recursive() {
+            let dest_path = extraction_path(dest, &path)?;
+
+            match entry {
+                Entry::Directory(_) => {
+                    std::fs::create_dir_all(&dest_path)
+                        .context(CreateDirectorySnafu { path: dest_path })?;
+                }
+                Entry::File(file) => {
+                    let storage = file.storage().context(OpenFileSnafu { path })?;
+                    files.push((dest_path, storage));
+                }
+            }
+        }
+
+        let results = parallel_chunks(files, |chunk| {
+            chunk.into_iter().try_for_each(|(dest_path, storage)| {
+                storage.save_to_file(&dest_path).map_err(|e| (dest_path, e))
+            })
+        });
+
+        // the chunks are in the iteration order, so the error reported is the same as for `extract_to`
+        for result in results {
+            if let Err((path, source)) = result {
+                return Err(source).context(SaveFileSnafu { path });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the entry at `path` in the image goes when extracting to `dest`
+fn extraction_path<E: Debug + Display + AsErrorSource + 'static>(
+    dest: &Path,
+    path: &str,
+) -> Result<PathBuf, ExtractError<E>> {
+    let relative_path = Path::new(path.trim_start_matches('/'));
+    // the names come from the (untrusted) image, don't let them escape the destination
+    if !relative_path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return InvalidPathSnafu { path }.fail();
+    }
+    Ok(dest.join(relative_path))
 }
 
 impl<T: ReadableDirectory> ReadableDirectoryExt for T {}
@@ -1,13 +1,16 @@
 use crate::hexstring::HexData;
-use crate::ids::{AnyId, ApplicationId, ContentId, DataPatchId, PatchId};
+use crate::ids::{AnyId, ApplicationId, ContentId, DataId, DataPatchId, PatchId};
 use crate::version::Version;
 use binrw::{BinRead, BinWrite};
 use bitflags::bitflags;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use std::io::SeekFrom;
 
 pub mod patch_meta_extended_data;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum ContentMetaType {
     // Unknown = 0,
@@ -27,6 +30,7 @@ pub enum ContentMetaType {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum StorageId {
     None = 0,
@@ -39,6 +43,7 @@ pub enum StorageId {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum ContentInstallType {
     Full = 0,
@@ -47,6 +52,7 @@ pub enum ContentInstallType {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ContentMetaKey {
     pub id: AnyId,
     pub version: Version,
@@ -56,6 +62,7 @@ pub struct ContentMetaKey {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ContentMetaAttribute(u8);
 bitflags! {
     impl ContentMetaAttribute: u8 {
@@ -65,7 +72,23 @@ bitflags! {
     }
 }
 
+impl ContentMetaAttribute {
+    /// The content is the exFAT variant of a system component (as opposed to the FAT32-only one)
+    pub fn includes_exfat(&self) -> bool {
+        self.contains(Self::INCLUDES_EXFAT_DRIVER)
+    }
+
+    pub fn is_rebootless(&self) -> bool {
+        self.contains(Self::REBOOTLESS)
+    }
+
+    pub fn is_compacted(&self) -> bool {
+        self.contains(Self::COMPACTED)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ContentMetaInstallState(u8);
 bitflags! {
     impl ContentMetaInstallState: u8 {
@@ -73,7 +96,25 @@ bitflags! {
     }
 }
 
+/// Defines how an add-on content can be accessed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ContentAccessibilities(u8);
+bitflags! {
+    impl ContentAccessibilities: u8 {
+        const INDIVIDUAL = 0x01;
+    }
+}
+
+impl ContentAccessibilities {
+    /// The add-on content can be accessed on its own, as opposed to only as a part of a set
+    pub fn is_individual(&self) -> bool {
+        self.contains(Self::INDIVIDUAL)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum UpdateType {
     ApplyAsDelta = 0,
@@ -82,6 +123,7 @@ pub enum UpdateType {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[br(import(meta_type: ContentMetaType, extended_header_size: u16))]
 pub enum ExtendedMetaHeader {
     #[br(pre_assert(meta_type == ContentMetaType::SystemUpdate && extended_header_size != 0))]
@@ -106,7 +148,7 @@ pub enum ExtendedMetaHeader {
         application_id: ApplicationId,
         required_application_version: Version,
         #[brw(pad_after = 3)]
-        content_accessibilities: u8,
+        content_accessibilities: ContentAccessibilities,
         data_patch_id: DataPatchId,
     },
     #[br(pre_assert(meta_type == ContentMetaType::Delta))]
@@ -115,6 +157,14 @@ pub enum ExtendedMetaHeader {
         #[brw(pad_after = 4)]
         extended_data_size: u32,
     },
+    #[br(pre_assert(meta_type == ContentMetaType::DataPatch))]
+    DataPatch {
+        data_id: DataId,
+        application_id: ApplicationId,
+        required_application_version: Version,
+        #[brw(pad_after = 8)]
+        extended_data_size: u32,
+    },
     None,
 }
 
@@ -127,6 +177,9 @@ impl ExtendedMetaHeader {
             }
             | ExtendedMetaHeader::Delta {
                 extended_data_size, ..
+            }
+            | ExtendedMetaHeader::DataPatch {
+                extended_data_size, ..
             } => extended_data_size,
             ExtendedMetaHeader::Application { .. }
             | ExtendedMetaHeader::AddOnContent { .. }
@@ -136,6 +189,7 @@ impl ExtendedMetaHeader {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum NcmContentType {
     Meta = 0,
@@ -150,6 +204,7 @@ pub enum NcmContentType {
 // pub struct Digest {}
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ContentInfo {
     pub id: ContentId,
     #[br(parse_with = crate::brw_utils::read_u40)]
@@ -161,12 +216,14 @@ pub struct ContentInfo {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PackagedContentInfo {
     pub hash: HexData<0x20>,
     pub content_info: ContentInfo,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ContentMetaInfo {
     pub title_id: AnyId,
     pub version: Version,
@@ -176,15 +233,20 @@ pub struct ContentMetaInfo {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[br(import(meta_type: ContentMetaType, extended_data_size: u32))]
 pub enum ExtendedData {
     #[br(pre_assert(extended_data_size != 0 && meta_type == ContentMetaType::Patch))]
     Patch(patch_meta_extended_data::PatchMetaExtendedData),
+    /// Extended data of the other meta types, not parsed (yet)
+    #[br(pre_assert(extended_data_size != 0 && meta_type != ContentMetaType::Patch))]
+    Raw(#[br(count = extended_data_size)] Vec<u8>),
     #[br(pre_assert(extended_data_size == 0))]
     None,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(little)]
 pub struct PackagedContentMeta {
     pub id: AnyId,
@@ -208,9 +270,9 @@ pub struct PackagedContentMeta {
     pub extended_header: ExtendedMetaHeader,
 
     #[br(seek_before = SeekFrom::Start(0x20 + extended_header_size as u64))]
-    #[br(count = content_count)]
+    #[br(count = content_count as u32)]
     pub content_info: Vec<PackagedContentInfo>,
-    #[br(count = content_meta_count)]
+    #[br(count = content_meta_count as u32)]
     pub content_meta_info: Vec<ContentMetaInfo>,
     // TODO: be more robust by checking/enforcing extended data size (from extended header)
     #[br(args(ty, extended_header.extended_data_size()))]
@@ -228,4 +290,87 @@ impl PackagedContentMeta {
             install_ty: self.content_install_type,
         }
     }
+
+    /// Id, type and size of each content, without going through the nested [`PackagedContentInfo::content_info`]
+    pub fn contents_detailed(&self) -> impl Iterator<Item = (ContentId, NcmContentType, u64)> + '_ {
+        self.content_info.iter().map(|info| {
+            (
+                info.content_info.id,
+                info.content_info.ty,
+                info.content_info.size,
+            )
+        })
+    }
+
+    /// Lists the content metas referenced by a SystemUpdate, skipping the exFAT variants unless `exfat_supported`
+    pub fn system_update_content_metas(
+        &self,
+        exfat_supported: bool,
+    ) -> impl Iterator<Item = &ContentMetaInfo> {
+        self.content_meta_info
+            .iter()
+            .filter(move |info| exfat_supported || !info.attributes.includes_exfat())
+    }
+
+    /// Checks the trailing hash, which is the SHA-256 of everything preceding it
+    ///
+    /// The hashed bytes are reconstructed by serializing the metadata back
+    pub fn verify_hash(&self) -> bool {
+        use digest::Digest;
+
+        let mut data = std::io::Cursor::new(Vec::new());
+        self.write(&mut data)
+            .expect("Serializing the content meta into memory should not fail");
+        let data = data.into_inner();
+
+        let hashed = &data[..data.len() - self.hash.0.len()];
+        sha2::Sha256::digest(hashed).as_slice() == self.hash.0
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::PackagedContentMeta;
+    use binrw::BinRead;
+    use digest::Digest;
+    use std::io::Cursor;
+
+    /// An Application CNMT with a single Program content
+    pub(crate) fn make_application_cnmt() -> Vec<u8> {
+        let mut cnmt = Vec::new();
+        cnmt.extend_from_slice(&0x0100000000010000u64.to_le_bytes()); // id
+        cnmt.extend_from_slice(&0x10000u32.to_le_bytes()); // version
+        cnmt.extend_from_slice(&[0x80, 0]); // type, field_d
+        cnmt.extend_from_slice(&0x10u16.to_le_bytes()); // extended header size
+        cnmt.extend_from_slice(&1u16.to_le_bytes()); // content count
+        cnmt.extend_from_slice(&0u16.to_le_bytes()); // content meta count
+        cnmt.extend_from_slice(&[0, 4, 0, 0]); // attributes, storage id, install type, install state
+        cnmt.extend_from_slice(&0u32.to_le_bytes()); // required download system version
+        cnmt.extend_from_slice(&[0; 4]);
+
+        cnmt.extend_from_slice(&0x0100000000010800u64.to_le_bytes()); // patch id
+        cnmt.extend_from_slice(&0x0c000000u32.to_le_bytes()); // required system version
+        cnmt.extend_from_slice(&0u32.to_le_bytes()); // required application version
+
+        cnmt.extend_from_slice(&[0x11; 0x20]); // content hash
+        cnmt.extend_from_slice(&[0x22; 0x10]); // content id
+        cnmt.extend_from_slice(&[0x00, 0x10, 0, 0, 0]); // size
+        cnmt.extend_from_slice(&[0, 1, 0]); // attributes, type, id offset
+
+        let hash = sha2::Sha256::digest(&cnmt);
+        cnmt.extend_from_slice(&hash);
+        cnmt
+    }
+
+    #[test]
+    fn verify_hash() {
+        let mut data = make_application_cnmt();
+        let meta = PackagedContentMeta::read(&mut Cursor::new(&data)).unwrap();
+        assert!(meta.verify_hash());
+
+        // corrupt the content size
+        data[0x60] ^= 1;
+        let meta = PackagedContentMeta::read(&mut Cursor::new(&data)).unwrap();
+        assert!(!meta.verify_hash());
+    }
 }
@@ -3,9 +3,13 @@ use crate::formats::cnmt::{
 };
 use crate::hexstring::HexData;
 use crate::ids::{ContentId, PatchId};
+use crate::version::Version;
 use binrw::{BinRead, BinWrite};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PatchHistoryHeader {
     pub key: ContentMetaKey,
     pub hash: HexData<0x20>,
@@ -15,21 +19,23 @@ pub struct PatchHistoryHeader {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PatchDeltaHistory {
     pub title_id_old: PatchId,
     pub title_id_new: PatchId,
-    pub version_old: u32,
-    pub version_new: u32,
+    pub version_old: Version,
+    pub version_new: Version,
     #[brw(pad_after = 0x8)]
     pub download_size: u64,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PatchDeltaHeader {
     pub source_id: PatchId,
     pub destination_id: PatchId,
-    pub source_version: u32,
-    pub destination_version: u32,
+    pub source_version: Version,
+    pub destination_version: Version,
     #[brw(pad_after = 0x6)]
     pub fragment_set_count: u16,
     #[brw(pad_after = 0x6)]
@@ -37,6 +43,7 @@ pub struct PatchDeltaHeader {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct FragmentSet {
     pub source_content_id: ContentId,
     pub destination_content_id: ContentId,
@@ -54,12 +61,14 @@ pub struct FragmentSet {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct FragmentIndicator {
     pub content_index: u16,
     pub fragment_index: u16,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PatchMetaExtendedData {
     pub history_count: u32,
     pub delta_history_count: u32,
@@ -82,6 +91,6 @@ pub struct PatchMetaExtendedData {
     #[br(count = delta_content_total_count)]
     pub delta_contents: Vec<PackagedContentInfo>,
 
-    #[br(count = fragment_sets.iter().map(|x| x.fragment_count as usize).sum::<usize>())]
+    #[br(count = fragment_sets.iter().map(|x| x.fragment_count as u32).sum::<u32>())]
     pub fragment_indicators: Vec<FragmentIndicator>,
 }
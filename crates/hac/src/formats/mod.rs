@@ -1,6 +1,8 @@
 pub mod cnmt;
 pub mod nacp;
 pub mod nca;
+pub mod npdm;
 pub mod pfs;
 pub mod romfs;
 pub mod ticket;
+pub mod xci;
@@ -1,10 +1,13 @@
 use crate::hexstring::HexData;
-use crate::ids::AnyId;
+use crate::ids::{AnyId, ApplicationId};
 use binrw::{BinRead, BinWrite};
 use bitflags::bitflags;
 use enum_map::{Enum, EnumMap};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 #[derive(Debug, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ProgramTitle {
     #[brw(pad_size_to = 0x200)]
     #[br(try_map = |s: binrw::NullString| String::from_utf8(s.0))]
@@ -17,34 +20,40 @@ pub struct ProgramTitle {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ApplicationNeighborDetectionClientConfiguration {
     pub send_group_configuration: ApplicationNeighborDetectionGroupConfiguration,
     pub receivable_group_configurations: [ApplicationNeighborDetectionGroupConfiguration; 0x10],
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ApplicationNeighborDetectionGroupConfiguration {
     pub group_id: u64,
     pub key: HexData<0x10>,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ApplicationJitConfiguration {
     pub flags: JitConfigurationFlag,
     pub memory_size: u64,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct RequiredAddOnContentsSetBinaryDescriptor {
     pub descriptors: [u16; 0x20],
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct AccessibleLaunchRequiredVersionValue {
     pub application_id: [u64; 8],
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Enum)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum Language {
     AmericanEnglish = 0,
     BritishEnglish = 1,
@@ -65,6 +74,7 @@ pub enum Language {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Enum)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum Organization {
     CERO = 0,
     GRACGCRB = 1,
@@ -100,7 +110,71 @@ pub enum Organization {
     Unused31 = 31,
 }
 
+/// Region of the console, determines in which order the languages are tried when picking the title and icon to display
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Region {
+    Japan,
+    Usa,
+    Europe,
+    Australia,
+    HongKongTaiwanKorea,
+    China,
+}
+
+impl Region {
+    /// Languages in the order of preference for this region
+    pub fn language_priority(self) -> &'static [Language] {
+        use Language::*;
+
+        match self {
+            Region::Japan => &[Japanese, AmericanEnglish, BritishEnglish],
+            Region::Usa => &[
+                AmericanEnglish,
+                CanadianFrench,
+                LatinAmericanSpanish,
+                BrazilianPortuguese,
+                BritishEnglish,
+                French,
+                Spanish,
+                Portuguese,
+            ],
+            Region::Europe => &[
+                BritishEnglish,
+                French,
+                German,
+                Spanish,
+                Italian,
+                Dutch,
+                Portuguese,
+                Russian,
+                AmericanEnglish,
+                CanadianFrench,
+                LatinAmericanSpanish,
+                BrazilianPortuguese,
+            ],
+            Region::Australia => &[BritishEnglish, AmericanEnglish],
+            Region::HongKongTaiwanKorea => &[
+                TraditionalChinese,
+                Korean,
+                SimplifiedChinese,
+                AmericanEnglish,
+                BritishEnglish,
+                Japanese,
+            ],
+            Region::China => &[
+                SimplifiedChinese,
+                TraditionalChinese,
+                AmericanEnglish,
+                BritishEnglish,
+                Japanese,
+            ],
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum StartupUserAccountValue {
     None = 0,
@@ -109,6 +183,7 @@ pub enum StartupUserAccountValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum UserAccountSwitchLockValue {
     Disable = 0,
@@ -116,6 +191,7 @@ pub enum UserAccountSwitchLockValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum AddOnContentRegistrationTypeValue {
     AllOnLaunch = 0,
@@ -123,6 +199,7 @@ pub enum AddOnContentRegistrationTypeValue {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct AttributeFlagValue(u32);
 
 bitflags! {
@@ -134,6 +211,7 @@ bitflags! {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u32)]
 pub enum ParentalControlFlagValue {
     None = 0,
@@ -141,6 +219,7 @@ pub enum ParentalControlFlagValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum ScreenshotValue {
     Allow = 0,
@@ -148,6 +227,7 @@ pub enum ScreenshotValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum VideoCaptureValue {
     Disable = 0,
@@ -156,6 +236,7 @@ pub enum VideoCaptureValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum DataLossConfirmationValue {
     None = 0,
@@ -163,6 +244,7 @@ pub enum DataLossConfirmationValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum PlayLogPolicyValue {
     Open = 0,
@@ -172,6 +254,7 @@ pub enum PlayLogPolicyValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum LogoTypeValue {
     LicensedByNintendo = 0,
@@ -180,6 +263,7 @@ pub enum LogoTypeValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum LogoHandlingValue {
     Auto = 0,
@@ -187,6 +271,7 @@ pub enum LogoHandlingValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum RuntimeAddOnContentInstallValue {
     Deny = 0,
@@ -195,6 +280,7 @@ pub enum RuntimeAddOnContentInstallValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum RuntimeParameterDeliveryValue {
     Always = 0,
@@ -203,6 +289,7 @@ pub enum RuntimeParameterDeliveryValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum CrashReportValue {
     Deny = 0,
@@ -210,6 +297,7 @@ pub enum CrashReportValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum HdcpValue {
     None = 0,
@@ -217,6 +305,7 @@ pub enum HdcpValue {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct StartupUserAccountOptionFlagValue(u8);
 
 bitflags! {
@@ -227,6 +316,7 @@ bitflags! {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum PlayLogQueryCapabilityValue {
     None = 0,
@@ -235,6 +325,7 @@ pub enum PlayLogQueryCapabilityValue {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct RepairFlagValue(u8);
 
 bitflags! {
@@ -244,6 +335,7 @@ bitflags! {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct RequiredNetworkServiceLicenseOnLaunchValue(u8);
 
 bitflags! {
@@ -253,6 +345,7 @@ bitflags! {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct JitConfigurationFlag(u64);
 
 bitflags! {
@@ -262,6 +355,7 @@ bitflags! {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PlayReportPermissionValue(u8);
 
 bitflags! {
@@ -271,6 +365,7 @@ bitflags! {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum CrashScreenshotForProdValue {
     Deny = 0,
@@ -278,6 +373,7 @@ pub enum CrashScreenshotForProdValue {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum CrashScreenshotForDevValue {
     Deny = 0,
@@ -285,6 +381,7 @@ pub enum CrashScreenshotForDevValue {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(little)]
 pub struct ApplicationControlProperty {
     // titles, one for each language
@@ -361,4 +458,79 @@ impl ApplicationControlProperty {
     pub fn any_title(&self) -> Option<&ProgramTitle> {
         self.title.values().find(|x| !x.name.is_empty())
     }
+
+    /// Titles for all the languages that have one
+    pub fn titles(&self) -> impl Iterator<Item = (Language, &ProgramTitle)> {
+        self.title
+            .iter()
+            .filter(|(_, title)| !title.name.is_empty())
+    }
+
+    /// Whether the application supports the language, i.e. has a title and an icon for it
+    pub fn supports_language(&self, language: Language) -> bool {
+        if self.supported_language_flag == 0 {
+            // some homebrew doesn't fill the flags in, go by the titles then
+            !self.title[language].name.is_empty()
+        } else {
+            self.supported_language_flag & (1 << language as u32) != 0
+        }
+    }
+
+    /// Picks the language of the title and icon the console in `region` would display
+    ///
+    /// The languages are tried in the [`Region::language_priority`] order, then all the others.
+    /// If the application doesn't support any language, the preferred language of the region is returned.
+    pub fn resolve_for_region(&self, region: Region) -> Language {
+        let priority = region.language_priority();
+        priority
+            .iter()
+            .copied()
+            .chain((0..Language::LENGTH).map(Language::from_usize))
+            .find(|&language| self.supports_language(language))
+            .unwrap_or(priority[0])
+    }
+
+    /// Applications this one can communicate with locally, unused (zero) slots are skipped
+    pub fn local_communication_ids(&self) -> impl Iterator<Item = ApplicationId> + '_ {
+        self.local_communication_id
+            .iter()
+            .filter(|&&id| id != 0)
+            .map(|&id| ApplicationId::from(id))
+    }
+
+    /// Applications allowed to query the play log of this one, unused (zero) slots are skipped
+    pub fn play_log_queryable_application_ids(&self) -> impl Iterator<Item = ApplicationId> + '_ {
+        self.play_log_queryable_application_id
+            .iter()
+            .filter(|&&id| id != 0)
+            .map(|&id| ApplicationId::from(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApplicationControlProperty, Language, Region};
+    use binrw::BinRead;
+    use std::io::Cursor;
+
+    #[test]
+    fn resolve_for_region() {
+        let mut nacp = ApplicationControlProperty::read(&mut Cursor::new(vec![0; 0x4000])).unwrap();
+        assert_eq!(
+            nacp.resolve_for_region(Region::Europe),
+            Language::BritishEnglish
+        );
+
+        nacp.title[Language::Japanese].name = "タイトル".to_string();
+        nacp.title[Language::French].name = "Titre".to_string();
+        assert_eq!(nacp.resolve_for_region(Region::Japan), Language::Japanese);
+        assert_eq!(nacp.resolve_for_region(Region::Usa), Language::French);
+        assert_eq!(
+            nacp.resolve_for_region(Region::Australia),
+            Language::Japanese
+        );
+
+        nacp.supported_language_flag = 1 << Language::German as u32;
+        assert_eq!(nacp.resolve_for_region(Region::Europe), Language::German);
+    }
 }
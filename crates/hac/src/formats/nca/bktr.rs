@@ -0,0 +1,503 @@
+//! BKTR: the storages of the patch sections, which are applied over the corresponding section of the base NCA
+//!
+//! A patch section contains only the changed data, followed by two bucket trees:
+//! - the relocation tree, telling for each range of the patched section whether it comes from the base or the patch
+//! - the encryption tree, telling which AES-CTR counter generation each range of the patch data is encrypted with
+
+use crate::crypto::AesKey;
+use crate::formats::nca::crypt_storage::ctr_nonce;
+use crate::hexstring::HexData;
+use crate::storage::{ReadableStorage, StorageError};
+use binrw::meta::ReadEndian;
+use binrw::{BinRead, BinWrite};
+use num_integer::Integer;
+use snafu::{ensure, ResultExt, Snafu};
+use std::fmt::Debug;
+use std::io::Cursor;
+
+/// Both the offset nodes and the entry sets of a bucket tree are stored in blocks of this size
+const NODE_SIZE: u64 = 0x4000;
+const NODE_HEADER_SIZE: u64 = 0x10;
+const SUPPORTED_BUCKET_TREE_VERSION: u32 = 1;
+
+#[derive(Snafu, Debug)]
+pub enum BktrError {
+    /// The BKTR table at {offset:#x} ({size:#x} bytes) does not fit in the section
+    TableOutOfBounds { offset: u64, size: u64 },
+    /// Failed to read the BKTR table
+    TableRead { source: StorageError },
+    /// Failed to parse the BKTR table
+    TableParse { source: binrw::Error },
+    /// Unsupported bucket tree version {version}
+    UnsupportedBucketTreeVersion { version: u32 },
+    /// The bucket tree with {entry_count} entries needs {expected} bytes, but the table has only {actual}
+    TableTruncated {
+        entry_count: u32,
+        expected: u64,
+        actual: u64,
+    },
+    /// The bucket tree entry set {index} is malformed
+    MalformedEntrySet { index: u64 },
+    /// The bucket tree entry {index} is out of order
+    UnorderedEntry { index: usize },
+    /// The relocation entry {index} refers to an unknown storage {storage_index}
+    InvalidStorageIndex { index: usize, storage_index: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[brw(little, magic = b"BKTR")]
+struct BucketTreeHeader {
+    version: u32,
+    #[brw(pad_after = 4)]
+    entry_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[brw(little)]
+struct NodeHeader {
+    index: u32,
+    count: u32,
+    /// End of the range covered by the node
+    end_offset: u64,
+}
+
+pub(crate) trait BucketTreeEntry: for<'a> BinRead<Args<'a> = ()> + ReadEndian {
+    const SIZE: u64;
+
+    /// Start of the range covered by the entry, it ends where the next one starts
+    fn offset(&self) -> u64;
+}
+
+/// Entry of the relocation tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[brw(little)]
+pub struct IndirectEntry {
+    /// Offset in the patched section
+    pub virtual_offset: u64,
+    /// Offset in the storage the data comes from
+    pub physical_offset: u64,
+    /// 0 for the base section, 1 for the patch data
+    pub storage_index: u32,
+}
+
+impl BucketTreeEntry for IndirectEntry {
+    const SIZE: u64 = 0x14;
+
+    fn offset(&self) -> u64 {
+        self.virtual_offset
+    }
+}
+
+/// Entry of the encryption tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[brw(little)]
+pub struct AesCtrExEntry {
+    /// Offset in the patch section
+    pub offset: u64,
+    /// 0 if the range is encrypted, 1 if it is stored as is
+    #[brw(pad_after = 3)]
+    pub encryption_value: u8,
+    /// Replaces the lower 32 bits of the upper counter from the FS header
+    pub generation: u32,
+}
+
+impl BucketTreeEntry for AesCtrExEntry {
+    const SIZE: u64 = 0x10;
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Number of the second level offset nodes, only needed when the entry sets don't fit in the first one
+fn node_l2_count(entry_set_count: u64) -> u64 {
+    let offsets_per_node = (NODE_SIZE - NODE_HEADER_SIZE) / 8;
+    if entry_set_count <= offsets_per_node {
+        return 0;
+    }
+
+    let l2_count = Integer::div_ceil(&entry_set_count, &offsets_per_node);
+    Integer::div_ceil(
+        &(entry_set_count - (offsets_per_node - (l2_count - 1))),
+        &offsets_per_node,
+    )
+}
+
+/// All the entries of a bucket tree, sorted by their offset
+///
+/// The offset nodes are only used to find the entry sets on the console, so they are not kept.
+#[derive(Debug, Clone)]
+pub(crate) struct BucketTree<E> {
+    entries: Vec<E>,
+    end_offset: u64,
+}
+
+impl<E: BucketTreeEntry> BucketTree<E> {
+    /// Reads the tree at `offset` in the (decrypted) `section`, described by the `header` from the FS header
+    pub fn read(
+        header: &HexData<0x10>,
+        section: &impl ReadableStorage,
+        offset: u64,
+        size: u64,
+    ) -> Result<Self, BktrError> {
+        ensure!(
+            offset
+                .checked_add(size)
+                .is_some_and(|end| end <= section.get_size()),
+            TableOutOfBoundsSnafu { offset, size }
+        );
+        let mut table = vec![0; size as usize];
+        section.read(offset, &mut table).context(TableReadSnafu)?;
+
+        Self::parse(header, &table)
+    }
+
+    /// Parses the tree stored in `table` (decrypted), described by the `header` from the FS header
+    pub fn parse(header: &HexData<0x10>, table: &[u8]) -> Result<Self, BktrError> {
+        let header =
+            BucketTreeHeader::read(&mut Cursor::new(&header.0)).context(TableParseSnafu)?;
+        ensure!(
+            header.version <= SUPPORTED_BUCKET_TREE_VERSION,
+            UnsupportedBucketTreeVersionSnafu {
+                version: header.version
+            }
+        );
+        if header.entry_count == 0 {
+            return Ok(Self {
+                entries: Vec::new(),
+                end_offset: 0,
+            });
+        }
+
+        let entries_per_set = (NODE_SIZE - NODE_HEADER_SIZE) / E::SIZE;
+        let entry_set_count = Integer::div_ceil(&(header.entry_count as u64), &entries_per_set);
+        let entry_sets_offset = (1 + node_l2_count(entry_set_count)) * NODE_SIZE;
+        let expected = entry_sets_offset + entry_set_count * NODE_SIZE;
+        ensure!(
+            table.len() as u64 >= expected,
+            TableTruncatedSnafu {
+                entry_count: header.entry_count,
+                expected,
+                actual: table.len() as u64,
+            }
+        );
+
+        let mut cursor = Cursor::new(table);
+        let end_offset = NodeHeader::read(&mut cursor)
+            .context(TableParseSnafu)?
+            .end_offset;
+
+        let mut entries = Vec::with_capacity(header.entry_count as usize);
+        for index in 0..entry_set_count {
+            cursor.set_position(entry_sets_offset + index * NODE_SIZE);
+            let set_header = NodeHeader::read(&mut cursor).context(TableParseSnafu)?;
+            ensure!(
+                set_header.index as u64 == index && set_header.count as u64 <= entries_per_set,
+                MalformedEntrySetSnafu { index }
+            );
+            for _ in 0..set_header.count {
+                entries.push(E::read(&mut cursor).context(TableParseSnafu)?);
+            }
+        }
+        ensure!(
+            entries.len() == header.entry_count as usize,
+            MalformedEntrySetSnafu {
+                index: entry_set_count - 1
+            }
+        );
+
+        for (index, pair) in entries.windows(2).enumerate() {
+            ensure!(
+                pair[0].offset() < pair[1].offset(),
+                UnorderedEntrySnafu { index: index + 1 }
+            );
+        }
+        ensure!(
+            entries.last().unwrap().offset() < end_offset,
+            UnorderedEntrySnafu {
+                index: entries.len() - 1
+            }
+        );
+
+        Ok(Self {
+            entries,
+            end_offset,
+        })
+    }
+
+    /// The entry covering `offset` and the end of its range, `None` if the tree doesn't cover it
+    fn find(&self, offset: u64) -> Option<(&E, u64)> {
+        if offset >= self.end_offset {
+            return None;
+        }
+
+        let index = self
+            .entries
+            .partition_point(|e| e.offset() <= offset)
+            .checked_sub(1)?;
+        let end = self
+            .entries
+            .get(index + 1)
+            .map_or(self.end_offset, |e| e.offset());
+        Some((&self.entries[index], end))
+    }
+}
+
+/// Decrypts the patch section, each range using the counter generation from the encryption tree
+pub struct AesCtrExStorage<S: ReadableStorage> {
+    storage: S,
+    /// `None` for plaintext NCAs
+    key: Option<AesKey>,
+    upper_counter: u64,
+    /// Offset of the section in the NCA, the counter is based on the offset in the NCA
+    start_offset: u64,
+    tree: BucketTree<AesCtrExEntry>,
+}
+
+impl<S: ReadableStorage> AesCtrExStorage<S> {
+    pub(crate) fn new(
+        storage: S,
+        key: Option<AesKey>,
+        upper_counter: u64,
+        start_offset: u64,
+        tree: BucketTree<AesCtrExEntry>,
+    ) -> Self {
+        Self {
+            storage,
+            key,
+            upper_counter,
+            start_offset,
+            tree,
+        }
+    }
+}
+
+impl<S: ReadableStorage> Debug for AesCtrExStorage<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // don't print the key
+        f.debug_struct("AesCtrExStorage")
+            .field("entries", &self.tree.entries.len())
+            .field("size", &self.get_size())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: ReadableStorage> ReadableStorage for AesCtrExStorage<S> {
+    fn read(&self, mut offset: u64, mut buf: &mut [u8]) -> Result<(), StorageError> {
+        if offset + buf.len() as u64 > self.get_size() {
+            return Err(StorageError::OutOfBounds {});
+        }
+
+        while !buf.is_empty() {
+            let (encrypted, upper_counter, range_end) = match self.tree.find(offset) {
+                Some((entry, end)) => (
+                    entry.encryption_value == 0,
+                    (self.upper_counter & !0xffff_ffff) | entry.generation as u64,
+                    end,
+                ),
+                // past the encrypted data are the BKTR tables, which use the counter from the FS header as is
+                None => (true, self.upper_counter, self.get_size()),
+            };
+            let len = std::cmp::min(buf.len() as u64, range_end - offset) as usize;
+            let (chunk, rest) = std::mem::take(&mut buf).split_at_mut(len);
+
+            match self.key.filter(|_| encrypted) {
+                None => self.storage.read(offset, chunk)?,
+                Some(key) => {
+                    // CTR works on whole AES blocks
+                    let aligned_start = offset & !0xf;
+                    let aligned_end = (offset + len as u64 + 0xf) & !0xf;
+                    let mut blocks = vec![0; (aligned_end - aligned_start) as usize];
+                    self.storage.read(aligned_start, &mut blocks)?;
+                    key.decrypt_ctr(
+                        &mut blocks,
+                        &ctr_nonce(upper_counter, self.start_offset + aligned_start),
+                    );
+
+                    let skip = (offset - aligned_start) as usize;
+                    chunk.copy_from_slice(&blocks[skip..skip + len]);
+                }
+            }
+
+            offset += len as u64;
+            buf = rest;
+        }
+
+        Ok(())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.storage.get_size()
+    }
+}
+
+/// The patched section: each range is read either from the base section or from the patch data, as the relocation tree says
+#[derive(Debug)]
+pub struct IndirectStorage<B: ReadableStorage, P: ReadableStorage> {
+    base: B,
+    patch: P,
+    tree: BucketTree<IndirectEntry>,
+}
+
+impl<B: ReadableStorage, P: ReadableStorage> IndirectStorage<B, P> {
+    pub(crate) fn new(
+        base: B,
+        patch: P,
+        tree: BucketTree<IndirectEntry>,
+    ) -> Result<Self, BktrError> {
+        if let Some((index, entry)) = tree
+            .entries
+            .iter()
+            .enumerate()
+            .find(|(_, e)| e.storage_index > 1)
+        {
+            return InvalidStorageIndexSnafu {
+                index,
+                storage_index: entry.storage_index,
+            }
+            .fail();
+        }
+
+        Ok(Self { base, patch, tree })
+    }
+}
+
+impl<B: ReadableStorage, P: ReadableStorage> ReadableStorage for IndirectStorage<B, P> {
+    fn read(&self, mut offset: u64, mut buf: &mut [u8]) -> Result<(), StorageError> {
+        if offset + buf.len() as u64 > self.get_size() {
+            return Err(StorageError::OutOfBounds {});
+        }
+
+        while !buf.is_empty() {
+            let Some((entry, range_end)) = self.tree.find(offset) else {
+                // the tree doesn't start at 0
+                return Err(StorageError::Inaccessible { offset });
+            };
+            let len = std::cmp::min(buf.len() as u64, range_end - offset) as usize;
+            let (chunk, rest) = std::mem::take(&mut buf).split_at_mut(len);
+
+            let physical_offset = entry.physical_offset + (offset - entry.virtual_offset);
+            match entry.storage_index {
+                0 => self.base.read(physical_offset, chunk)?,
+                _ => self.patch.read(physical_offset, chunk)?,
+            }
+
+            offset += len as u64;
+            buf = rest;
+        }
+
+        Ok(())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.tree.end_offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AesCtrExEntry, AesCtrExStorage, BucketTree, IndirectEntry, IndirectStorage};
+    use crate::crypto::AesKey;
+    use crate::formats::nca::crypt_storage::ctr_nonce;
+    use crate::formats::nca::test_fixture::make_bucket_tree;
+    use crate::storage::{ReadableStorage, ReadableStorageExt, VecStorage};
+
+    #[test]
+    fn indirect() {
+        let entries = [
+            IndirectEntry {
+                virtual_offset: 0,
+                physical_offset: 0,
+                storage_index: 0,
+            },
+            IndirectEntry {
+                virtual_offset: 4,
+                physical_offset: 2,
+                storage_index: 1,
+            },
+            IndirectEntry {
+                virtual_offset: 6,
+                physical_offset: 6,
+                storage_index: 0,
+            },
+        ];
+        let (header, table) = make_bucket_tree(&entries, 8);
+        let tree = BucketTree::parse(&header, &table).unwrap();
+
+        let base = VecStorage::new(b"abcdefgh".to_vec());
+        let patch = VecStorage::new(b"..XY".to_vec());
+        let storage = IndirectStorage::new(base, patch, tree).unwrap();
+
+        assert_eq!(storage.get_size(), 8);
+        assert_eq!(storage.read_all().unwrap(), b"abcdXYgh");
+        let mut buf = [0; 3];
+        storage.read(3, &mut buf).unwrap();
+        assert_eq!(&buf, b"dXY");
+        assert!(storage.read(7, &mut buf).is_err());
+    }
+
+    #[test]
+    fn unordered_entries() {
+        let entries = [
+            AesCtrExEntry {
+                offset: 0x20,
+                encryption_value: 0,
+                generation: 0,
+            },
+            AesCtrExEntry {
+                offset: 0x10,
+                encryption_value: 0,
+                generation: 0,
+            },
+        ];
+        let (header, table) = make_bucket_tree(&entries, 0x40);
+        assert!(BucketTree::<AesCtrExEntry>::parse(&header, &table).is_err());
+    }
+
+    #[test]
+    fn aes_ctr_ex() {
+        let key: AesKey = "000102030405060708090a0b0c0d0e0f".parse().unwrap();
+        let upper_counter = 0x1122334400000000;
+        let start_offset = 0x4000;
+        let plaintext = (0..0x60).map(|i| i as u8).collect::<Vec<_>>();
+
+        // the first 0x20 bytes use generation 1, the next 0x20 are not encrypted, the rest are the "tables"
+        let mut data = plaintext.clone();
+        key.encrypt_ctr(
+            &mut data[..0x20],
+            &ctr_nonce(upper_counter | 1, start_offset),
+        );
+        key.encrypt_ctr(
+            &mut data[0x40..],
+            &ctr_nonce(upper_counter, start_offset + 0x40),
+        );
+
+        let entries = [
+            AesCtrExEntry {
+                offset: 0,
+                encryption_value: 0,
+                generation: 1,
+            },
+            AesCtrExEntry {
+                offset: 0x20,
+                encryption_value: 1,
+                generation: 0,
+            },
+        ];
+        let (header, table) = make_bucket_tree(&entries, 0x40);
+        let tree = BucketTree::parse(&header, &table).unwrap();
+        let storage = AesCtrExStorage::new(
+            VecStorage::new(data),
+            Some(key),
+            upper_counter,
+            start_offset,
+            tree,
+        );
+
+        assert_eq!(storage.read_all().unwrap(), plaintext);
+        // unaligned, across the ranges
+        let mut buf = [0; 0x30];
+        storage.read(0x13, &mut buf).unwrap();
+        assert_eq!(&buf[..], &plaintext[0x13..0x43]);
+    }
+}
@@ -1,14 +1,23 @@
 use crate::crypto::AesKey;
+use crate::formats::nca::bktr::{AesCtrExStorage, BucketTree, IndirectStorage};
+use crate::formats::nca::crypt_storage::ctr_nonce;
 use crate::formats::nca::filesystem::NcaFileSystem;
 use crate::formats::nca::ncz::NczBodyStorage;
-use crate::formats::nca::structs::{IntegrityInfo, NcaEncryptionType, NcaFormatType};
+use crate::formats::nca::structs::{
+    IntegrityInfo, NcaEncryptionType, NcaFormatType, NcaFsHeader, NcaHashType,
+};
 use crate::formats::nca::{
-    IntegrityCheckLevel, Nca, NcaContentKeys, NcaCryptStorage, NcaSectionType,
-    NcaVerificationStorage,
+    BktrSnafu, IntegrityCheckLevel, MissingSectionSnafu, Nca, NcaContentKeys, NcaCryptStorage,
+    NcaError, NcaSectionType, NcaVerificationStorage, NotPatchSectionSnafu, PatchInNczSnafu,
+    PatchSectionSnafu, PfsParsingSnafu, RomfsParsingSnafu, StorageSnafu,
+    UnsupportedFsHeaderVersionSnafu, SUPPORTED_FS_HEADER_VERSION,
 };
 use crate::storage::{
-    ReadableStorage, ReadableStorageExt, SharedStorage, SliceStorage, StorageError,
+    ReadableStorage, ReadableStorageExt, SharedStorage, SliceStorage, Storage, StorageError,
 };
+use snafu::{ensure, OptionExt, ResultExt};
+
+const ROMFS_HEADER_SIZE: u64 = 0x50;
 
 #[derive(Debug)]
 pub enum Body<S: ReadableStorage> {
@@ -16,6 +25,15 @@ pub enum Body<S: ReadableStorage> {
     Ncz(SharedStorage<NczBodyStorage<S>>),
 }
 
+impl<S: ReadableStorage> Clone for Body<S> {
+    fn clone(&self) -> Self {
+        match self {
+            Body::Nca(s) => Body::Nca(s.clone()),
+            Body::Ncz(s) => Body::Ncz(s.clone()),
+        }
+    }
+}
+
 impl<S: ReadableStorage> ReadableStorage for Body<S> {
     fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
         match self {
@@ -36,6 +54,9 @@ pub type RawEncryptedSectionStorage<S> = SliceStorage<SharedStorage<S>>;
 // pub type RawDecryptedSectionStorage<S> = NcaCryptStorage<RawEncryptedSectionStorage<S>>;
 pub type VerifiedSectionStorage<S> = NcaVerificationStorage<RawDecryptedSectionStorage<S>>;
 pub type SectionFileSystem<S> = NcaFileSystem<VerifiedSectionStorage<S>>;
+pub type PatchedSectionStorage<S> =
+    IndirectStorage<RawDecryptedSectionStorage<S>, AesCtrExStorage<RawEncryptedSectionStorage<S>>>;
+pub type VerifiedPatchedSectionStorage<S> = NcaVerificationStorage<PatchedSectionStorage<S>>;
 
 pub enum RawDecryptedSectionStorage<S: ReadableStorage> {
     Nca(NcaCryptStorage<RawEncryptedSectionStorage<S>>),
@@ -66,6 +87,21 @@ pub struct SectionRange {
     size: u64,
 }
 
+/// Layout of an NCA section, as described by its FS header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionInfo {
+    /// Offset of the section in the NCA
+    pub offset: u64,
+    pub size: u64,
+    pub format_type: NcaFormatType,
+    pub encryption_type: NcaEncryptionType,
+    pub hash_type: NcaHashType,
+    /// Number of levels in the hash tree (including the data level), not counting the master hash
+    pub level_count: u32,
+    /// Block size of each level in the hash tree, starting from the top one
+    pub block_sizes: Vec<u64>,
+}
+
 impl<S: ReadableStorage> Nca<S> {
     fn get_section_range(&self, index: usize) -> Option<SectionRange> {
         let section_entry = self.headers.nca_header.section_table[index];
@@ -80,6 +116,60 @@ impl<S: ReadableStorage> Nca<S> {
         })
     }
 
+    /// `(offset, size)` in the NCA of each of the 4 section slots, `None` for the disabled ones
+    pub fn section_ranges(&self) -> [Option<(u64, u64)>; 4] {
+        std::array::from_fn(|index| {
+            self.get_section_range(index)
+                .map(|range| (range.offset, range.size))
+        })
+    }
+
+    /// Upper 64 bits of the AES-CTR counter of the section, the lower ones being the offset in the NCA in AES blocks
+    ///
+    /// `None` if the section is not present or not AES-CTR encrypted
+    pub fn section_ctr_upper(&self, index: usize) -> Option<u64> {
+        let fs_header = self.fs_header(index)?;
+        matches!(
+            fs_header.encryption_type,
+            NcaEncryptionType::AesCtr | NcaEncryptionType::AesCtrEx
+        )
+        .then_some(fs_header.upper_counter)
+    }
+
+    /// `None` if the section is not present
+    pub fn section_info(&self, index: usize) -> Option<SectionInfo> {
+        let range = self.get_section_range(index)?;
+        let fs_header = self.fs_header(index)?;
+
+        let (hash_type, block_sizes) = match fs_header.integrity_info {
+            IntegrityInfo::None => (NcaHashType::None, Vec::new()),
+            IntegrityInfo::Sha256(s) => (
+                NcaHashType::Sha256,
+                // both the hash table and the data are hashed in blocks of the same size
+                vec![s.block_size as u64; 2],
+            ),
+            IntegrityInfo::Ivfc(s) => (
+                NcaHashType::Ivfc,
+                // -1 because the last level is the master hash
+                s.level_info
+                    .iter()
+                    .take(s.level_count.saturating_sub(1) as usize)
+                    .map(|l| 1 << l.block_size)
+                    .collect(),
+            ),
+        };
+
+        Some(SectionInfo {
+            offset: range.offset,
+            size: range.size,
+            format_type: fs_header.format_type,
+            encryption_type: fs_header.encryption_type,
+            hash_type,
+            level_count: block_sizes.len() as u32,
+            block_sizes,
+        })
+    }
+
     pub fn get_raw_encrypted_section_storage(
         &self,
         index: usize,
@@ -90,7 +180,7 @@ impl<S: ReadableStorage> Nca<S> {
             return None;
         }
 
-        let fs_header = self.headers.fs_headers[index].as_ref().unwrap();
+        let fs_header = self.fs_header(index)?;
         if fs_header.exists_sparse_layer() {
             todo!("Sparse layer is not supported yet");
         }
@@ -105,23 +195,28 @@ impl<S: ReadableStorage> Nca<S> {
         }
     }
 
-    fn get_ctr_key(&self) -> AesKey {
-        match self.content_key {
+    fn get_ctr_key(&self) -> Result<AesKey, NcaError> {
+        match &self.content_key {
             NcaContentKeys::Plaintext => panic!("Attempt to get CTR key for plaintext NCA"),
-            NcaContentKeys::KeyArea { ctr: key, .. } | NcaContentKeys::RightsId(key) => key,
+            NcaContentKeys::KeyArea { ctr: key, .. } | NcaContentKeys::RightsId(key) => Ok(*key),
+            NcaContentKeys::Missing(missing) => Err(missing.to_error()),
         }
     }
 
     pub fn get_raw_decrypted_section_storage(
         &self,
         index: usize,
-    ) -> Option<RawDecryptedSectionStorage<S>> {
+    ) -> Option<Result<RawDecryptedSectionStorage<S>, NcaError>> {
+        let fs_header = self.fs_header(index)?;
+        if fs_header.is_patch_section() || fs_header.encryption_type == NcaEncryptionType::AesCtrEx
+        {
+            return Some(PatchSectionSnafu { index }.fail());
+        }
+
         match &self.body {
             Body::Nca(_) => self
                 .get_raw_encrypted_section_storage(index)
                 .map(|storage| {
-                    let fs_header = self.headers.fs_headers[index].as_ref().unwrap();
-
                     let storage = if self.is_plaintext() {
                         NcaCryptStorage::Plaintext(storage)
                     } else {
@@ -132,7 +227,7 @@ impl<S: ReadableStorage> Nca<S> {
                                 todo!("XTS encryption")
                             }
                             NcaEncryptionType::AesCtr => {
-                                let key = self.get_ctr_key();
+                                let key = self.get_ctr_key()?;
                                 let start_offset =
                                     self.headers.nca_header.section_table[index].start.into();
 
@@ -144,19 +239,21 @@ impl<S: ReadableStorage> Nca<S> {
                                 )
                             }
                             NcaEncryptionType::AesCtrEx => {
-                                todo!("AES-CTR-EX encryption")
+                                unreachable!("BUG: patch sections are rejected above")
                             }
                         }
                     };
 
-                    RawDecryptedSectionStorage::Nca(storage)
+                    Ok(RawDecryptedSectionStorage::Nca(storage))
                 }),
+            // NCZ stores the sections decrypted, the crypto in the NCZ section headers
+            // is only needed to reconstruct the original NCA (see `Ncz::open`)
             Body::Ncz(body) => self.get_section_range(index).map(|range| {
-                RawDecryptedSectionStorage::Ncz(
+                Ok(RawDecryptedSectionStorage::Ncz(
                     body.clone()
                         .slice(range.offset, range.size)
                         .expect("BUG: invalid section slice"),
-                )
+                ))
             }),
         }
     }
@@ -165,62 +262,260 @@ impl<S: ReadableStorage> Nca<S> {
         &self,
         index: usize,
         integrity_level: IntegrityCheckLevel,
-    ) -> Option<VerifiedSectionStorage<S>> {
+    ) -> Option<Result<VerifiedSectionStorage<S>, NcaError>> {
+        let fs_header = self.fs_header(index)?;
         self.get_raw_decrypted_section_storage(index)
-            .map(|storage| {
-                let fs_header = self.headers.fs_headers[index].as_ref().unwrap();
+            .map(|storage| self.verify_section_storage(index, fs_header, storage?, integrity_level))
+    }
 
-                if fs_header.exists_compression_layer() {
-                    todo!("Compression layer is not supported yet");
-                }
+    /// Puts the integrity verification described by the FS header of section `index` over its decrypted `storage`
+    fn verify_section_storage<T: ReadableStorage>(
+        &self,
+        index: usize,
+        fs_header: &NcaFsHeader,
+        storage: T,
+        integrity_level: IntegrityCheckLevel,
+    ) -> Result<NcaVerificationStorage<T>, NcaError> {
+        ensure!(
+            fs_header.version == SUPPORTED_FS_HEADER_VERSION,
+            UnsupportedFsHeaderVersionSnafu {
+                index,
+                version: fs_header.version
+            }
+        );
+
+        if fs_header.exists_compression_layer() {
+            todo!("Compression layer is not supported yet");
+        }
 
-                match fs_header.integrity_info {
-                    IntegrityInfo::None => todo!("IntegrityInfo::None is not supported yet"),
-                    IntegrityInfo::Sha256(s) => {
-                        assert_eq!(s.level_count, 2);
-                        let levels = s.level_info[..2].try_into().unwrap();
-
-                        NcaVerificationStorage::new_pfs_verification_storage(
-                            storage,
-                            s.master_hash.0 .0,
-                            levels,
-                            s.block_size,
-                            integrity_level,
-                        )
-                            .expect("FS header specifies invalid hash level offsets for HierarchicalSha256 integrity verification")
-                    }
-                    IntegrityInfo::Ivfc(s) => {
-                        assert_eq!(s.master_hash_size, 0x20);
-                        let master_hash = s.master_hash.0[..0x20].try_into().unwrap();
+        Ok(match fs_header.integrity_info {
+            IntegrityInfo::None => NcaVerificationStorage::new_unverified(storage),
+            IntegrityInfo::Sha256(s) => {
+                assert_eq!(s.level_count, 2);
+                let levels = s.level_info[..2].try_into().unwrap();
+
+                NcaVerificationStorage::new_pfs_verification_storage(
+                    storage,
+                    s.master_hash.0 .0,
+                    levels,
+                    s.block_size,
+                    integrity_level,
+                    self.block_status_cache,
+                )
+                    .expect("FS header specifies invalid hash level offsets for HierarchicalSha256 integrity verification")
+            }
+            IntegrityInfo::Ivfc(s) => {
+                assert_eq!(s.master_hash_size, 0x20);
+                let master_hash = s.master_hash.0[..0x20].try_into().unwrap();
+
+                // -1 because the last level is the master hash
+                NcaVerificationStorage::new_ivfc_verification_storage(storage, master_hash, s.level_count - 1, s.level_info, integrity_level, self.block_status_cache)
+                    .expect("FS header specifies invalid hash level offsets for IVFC integrity verification")
+            }
+        })
+    }
 
-                        // -1 because the last level is the master hash
-                        NcaVerificationStorage::new_ivfc_verification_storage(storage, master_hash, s.level_count - 1, s.level_info, integrity_level)
-                            .expect("FS header specifies invalid hash level offsets for IVFC integrity verification")
-                    }
+    /// Applies the patch section `index` of this NCA over the same section of `base`, giving the updated section image
+    ///
+    /// The hashes in the FS header of the patch are the ones of the updated image, so the verification is done after the patching.
+    ///
+    /// `None` if the section is not present
+    pub fn get_patched_section_storage(
+        &self,
+        index: usize,
+        base: &Nca<S>,
+        integrity_level: IntegrityCheckLevel,
+    ) -> Option<Result<VerifiedPatchedSectionStorage<S>, NcaError>> {
+        let fs_header = self.fs_header(index)?;
+        if !fs_header.is_patch_section() || fs_header.encryption_type != NcaEncryptionType::AesCtrEx
+        {
+            return Some(NotPatchSectionSnafu { index }.fail());
+        }
+        if let Body::Ncz(_) = self.body {
+            return Some(PatchInNczSnafu { index }.fail());
+        }
+
+        self.get_raw_encrypted_section_storage(index).map(|patch| {
+            let key = if self.is_plaintext() {
+                None
+            } else {
+                Some(self.get_ctr_key()?)
+            };
+            let start_offset: u64 = self.headers.nca_header.section_table[index].start.into();
+
+            // the tables themselves are encrypted with the counter from the FS header, like a regular AES-CTR section
+            let section = self
+                .get_raw_encrypted_section_storage(index)
+                .expect("BUG: the section disappeared");
+            let section = match key {
+                None => NcaCryptStorage::new_plaintext(section),
+                Some(key) => {
+                    NcaCryptStorage::new_ctr(section, key, fs_header.upper_counter, start_offset)
                 }
-            })
+            };
+            let patch_info = &fs_header.patch_info;
+            let relocation_tree = BucketTree::read(
+                &patch_info.relocation_tree_header,
+                &section,
+                patch_info.relocation_tree_offset,
+                patch_info.relocation_tree_size,
+            )
+            .context(BktrSnafu { index })?;
+            let encryption_tree = BucketTree::read(
+                &patch_info.encryption_tree_header,
+                &section,
+                patch_info.encryption_tree_offset,
+                patch_info.encryption_tree_size,
+            )
+            .context(BktrSnafu { index })?;
+
+            let base = base
+                .get_raw_decrypted_section_storage(index)
+                .context(MissingSectionSnafu { index })??;
+            let patch = AesCtrExStorage::new(
+                patch,
+                key,
+                fs_header.upper_counter,
+                start_offset,
+                encryption_tree,
+            );
+            let storage =
+                IndirectStorage::new(base, patch, relocation_tree).context(BktrSnafu { index })?;
+
+            self.verify_section_storage(index, fs_header, storage, integrity_level)
+        })
+    }
+
+    /// Same as [`Nca::get_patched_section_storage`], but finds the section by its type
+    pub fn get_patched_storage(
+        &self,
+        ty: NcaSectionType,
+        base: &Nca<S>,
+        integrity_level: IntegrityCheckLevel,
+    ) -> Option<Result<VerifiedPatchedSectionStorage<S>, NcaError>> {
+        let index = (0..4).find(|&i| self.get_section_type(i) == Some(ty))?;
+
+        self.get_patched_section_storage(index, base, integrity_level)
+    }
+
+    /// Writes the decrypted (and verified according to `integrity_level`) section image to `out`, e.g. a raw RomFS blob
+    pub fn dump_section(
+        &self,
+        index: usize,
+        integrity_level: IntegrityCheckLevel,
+        out: &impl Storage,
+    ) -> Result<(), NcaError> {
+        let storage = self
+            .get_section_storage(index, integrity_level)
+            .context(MissingSectionSnafu { index })??;
+        storage.copy_to(out).context(StorageSnafu)
+    }
+
+    /// The decrypted AES-CTR key of the sections, `None` for plaintext NCAs and when the keys are missing
+    ///
+    /// This is the actual key protecting the content, take care not to leak it, log [`AesKey::fingerprint`] instead.
+    pub fn content_ctr_key(&self) -> Option<AesKey> {
+        match &self.content_key {
+            NcaContentKeys::KeyArea { ctr: key, .. } | NcaContentKeys::RightsId(key) => Some(*key),
+            NcaContentKeys::Plaintext | NcaContentKeys::Missing(_) => None,
+        }
+    }
+
+    /// Writes the decrypted section `index` encrypted with AES-CTR under `key` to `out`
+    ///
+    /// The counter is derived from the position of the section in the NCA, same as for the original,
+    /// so the result is meant to replace the section in place, e.g. when converting a RightsId NCA to the key area crypto.
+    pub fn encrypt_section(
+        &self,
+        index: usize,
+        key: AesKey,
+        out: &impl Storage,
+    ) -> Result<(), NcaError> {
+        const BUFFER_SIZE: usize = 0x10000;
+
+        let storage = self
+            .get_raw_decrypted_section_storage(index)
+            .context(MissingSectionSnafu { index })??;
+        let fs_header = self
+            .fs_header(index)
+            .context(MissingSectionSnafu { index })?;
+        let start_offset: u64 = self.headers.nca_header.section_table[index].start.into();
+
+        // sections are sector-aligned, so every chunk is a whole number of AES blocks
+        let size = storage.get_size();
+        out.set_size(size).context(StorageSnafu)?;
+        let mut buf = vec![0; BUFFER_SIZE];
+        for offset in (0..size).step_by(BUFFER_SIZE) {
+            let buf = &mut buf[..std::cmp::min(BUFFER_SIZE as u64, size - offset) as usize];
+            storage.read(offset, buf).context(StorageSnafu)?;
+            let nonce = ctr_nonce(fs_header.upper_counter, start_offset + offset);
+            key.encrypt_ctr(buf, &nonce);
+            out.write(offset, buf).context(StorageSnafu)?;
+        }
+        Ok(())
     }
 
     pub fn get_section_fs(
         &self,
         index: usize,
         integrity_level: IntegrityCheckLevel,
-    ) -> Option<SectionFileSystem<S>> {
+    ) -> Option<Result<SectionFileSystem<S>, NcaError>> {
+        let fs_header = self.fs_header(index)?;
         self.get_section_storage(index, integrity_level)
             .map(|storage| {
-                let fs_header = self.headers.fs_headers[index].as_ref().unwrap();
+                let storage = storage?;
 
-                match fs_header.format_type {
+                let result = match fs_header.format_type {
                     NcaFormatType::Romfs => {
-                        NcaFileSystem::new_romfs(storage).expect("invalid ROMFS header")
+                        NcaFileSystem::new_romfs(storage).context(RomfsParsingSnafu { index })
                     }
                     NcaFormatType::Pfs0 => {
-                        NcaFileSystem::new_pfs(storage).expect("invalid PFS0 header")
+                        NcaFileSystem::new_pfs(storage).context(PfsParsingSnafu { index })
                     }
-                }
+                };
+
+                result.map_err(|e| {
+                    if self.is_likely_wrong_key(index) {
+                        NcaError::LikelyWrongKey { index }
+                    } else {
+                        e
+                    }
+                })
             })
     }
 
+    /// Checks whether the decrypted section does not start with the header of the filesystem it's supposed to contain.
+    ///
+    /// Used to tell the wrong keys (the data decrypts to noise) from a genuinely malformed filesystem.
+    fn is_likely_wrong_key(&self, index: usize) -> bool {
+        let Some(fs_header) = self.fs_header(index) else {
+            return false;
+        };
+        let uses_key = matches!(self.body, Body::Nca(_))
+            && !self.is_plaintext()
+            && fs_header.encryption_type != NcaEncryptionType::None;
+        if !uses_key {
+            return false;
+        }
+
+        // don't verify the hashes, they will obviously fail with the wrong key
+        let Some(Ok(storage)) = self.get_section_storage(index, IntegrityCheckLevel::None) else {
+            return false;
+        };
+
+        let mut magic = [0; 8];
+        if storage.read(0, &mut magic).is_err() {
+            // can't tell, assume the FS is just broken
+            return false;
+        }
+
+        match fs_header.format_type {
+            // RomFS starts with the header size
+            NcaFormatType::Romfs => u64::from_le_bytes(magic) != ROMFS_HEADER_SIZE,
+            NcaFormatType::Pfs0 => &magic[..4] != b"PFS0",
+        }
+    }
+
     pub fn get_section_type(&self, index: usize) -> Option<NcaSectionType> {
         use crate::formats::nca::NcaContentType::Program;
         use crate::formats::nca::NcaSectionType::{Code, Data, Logo};
@@ -238,7 +533,7 @@ impl<S: ReadableStorage> Nca<S> {
         &self,
         ty: NcaSectionType,
         integrity_level: IntegrityCheckLevel,
-    ) -> Option<SectionFileSystem<S>> {
+    ) -> Option<Result<SectionFileSystem<S>, NcaError>> {
         let index = (0..4).find(|&i| self.get_section_type(i) == Some(ty))?;
 
         self.get_section_fs(index, integrity_level)
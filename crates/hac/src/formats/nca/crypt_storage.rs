@@ -5,24 +5,43 @@ use crate::storage::{
     AesCtrStorage, BlockAdapterStorage, LinearAdapterStorage, ReadableStorage, Storage,
     StorageError,
 };
+use std::fmt::Debug;
 
-#[derive(Debug)]
 pub enum NcaCryptStorage<S: ReadableStorage> {
     Plaintext(S),
     AesCtr(LinearAdapterStorage<AesCtrStorage<BlockAdapterStorage<S>>>),
 }
 
+/// Describes how a [`NcaCryptStorage`] decrypts the data, for troubleshooting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptDescription {
+    Plaintext,
+    AesCtr {
+        /// Fingerprint of the key used, see [`AesKey::fingerprint`]
+        key_fingerprint: HexData<0x4>,
+        /// Counter used for the first AES block of the storage
+        base_counter: HexData<0x10>,
+    },
+}
+
+/// Builds the AES-CTR counter for the data at `offset` in the NCA (must be a multiple of the AES block size)
+///
+/// First 8 bytes are specified in the fs header, the rest is big-endian offset in the NCA in AES blocks
+pub(crate) fn ctr_nonce(upper_counter: u64, offset: u64) -> [u8; 0x10] {
+    let mut nonce = [0; 0x10];
+    nonce[..8].copy_from_slice(&upper_counter.to_be_bytes());
+    nonce[8..].copy_from_slice(&(offset / 16).to_be_bytes());
+    nonce
+}
+
 impl<S: ReadableStorage> NcaCryptStorage<S> {
     pub fn new_plaintext(storage: S) -> Self {
         Self::Plaintext(storage)
     }
 
     pub fn new_ctr(storage: S, key: AesKey, upper_counter: u64, start_offset: u64) -> Self {
-        // base nonce: first 8 bytes are specified in the fs header, the rest is big-endian offset in the section counter in AES blocks
-        // the section decryptor itself will add the inner offset
-        let mut nonce = [0; 0x10];
-        nonce[..8].copy_from_slice(&upper_counter.to_be_bytes());
-        nonce[8..].copy_from_slice(&(start_offset / 16).to_be_bytes());
+        // base nonce of the section, the section decryptor itself will add the inner offset
+        let nonce = ctr_nonce(upper_counter, start_offset);
 
         let block_adapter = BlockAdapterStorage::new(storage, 0x10);
         let transform = AesCtrBlockTransform::new(key, HexData(nonce));
@@ -31,6 +50,29 @@ impl<S: ReadableStorage> NcaCryptStorage<S> {
 
         Self::AesCtr(linear_adapter)
     }
+
+    pub fn describe(&self) -> CryptDescription {
+        match self {
+            NcaCryptStorage::Plaintext(_) => CryptDescription::Plaintext,
+            NcaCryptStorage::AesCtr(storage) => {
+                let transform = storage.inner().transform();
+                CryptDescription::AesCtr {
+                    key_fingerprint: transform.key().fingerprint(),
+                    base_counter: transform.nonce(),
+                }
+            }
+        }
+    }
+}
+
+impl<S: ReadableStorage> Debug for NcaCryptStorage<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // don't print the inner storages, they would leak the key
+        f.debug_struct("NcaCryptStorage")
+            .field("crypt", &self.describe())
+            .field("size", &self.get_size())
+            .finish()
+    }
 }
 
 impl<S: ReadableStorage> ReadableStorage for NcaCryptStorage<S> {
@@ -1,31 +1,44 @@
+mod bktr;
 mod contents;
 mod crypt_storage;
 pub mod filesystem;
 mod ncz;
 mod structs;
+#[cfg(test)]
+pub(crate) mod test_fixture;
 mod verification_storage;
 
 use binrw::BinRead;
 use itertools::Either;
 use snafu::{ResultExt, Snafu};
+use std::fmt::Debug;
 use std::io::Cursor;
+use tracing::warn;
 
-use crate::crypto::keyset::KeySet;
+use crate::crypto::keyset::{KeySet, MissingKeyError, MissingTitleKeyError, TitleKeyProvider};
 use crate::crypto::{AesKey, AesXtsKey};
-use crate::formats::nca::structs::{NcaFsHeader, NcaHeader, NcaMagic};
-use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
+use crate::ids::{AnyId, RightsId};
+use crate::storage::{
+    CacheStats, ReadableStorage, ReadableStorageExt, SharedStorage, StorageError,
+};
 
+pub use bktr::{AesCtrExEntry, AesCtrExStorage, BktrError, IndirectEntry, IndirectStorage};
 pub use contents::{
-    RawDecryptedSectionStorage, RawEncryptedSectionStorage, SectionFileSystem,
-    VerifiedSectionStorage,
+    PatchedSectionStorage, RawDecryptedSectionStorage, RawEncryptedSectionStorage,
+    SectionFileSystem, SectionInfo, VerifiedPatchedSectionStorage, VerifiedSectionStorage,
+};
+pub use crypt_storage::{CryptDescription, NcaCryptStorage};
+pub use structs::{
+    CompressionInfo, DistributionType, IntegrityInfo, IvfcIntegrityInfo, IvfcIntegrityInfoLevel,
+    NcaContentType, NcaEncryptionType, NcaEnumParseError, NcaFormatType, NcaFsHeader, NcaHashType,
+    NcaHeader, NcaKeyArea, NcaMagic, NcaSectionType, NcaSignature, PatchInfo, SectionTableEntry,
+    SectionTableOffset, Sha256Hash, Sha256IntegrityInfo, Sha256IntegrityInfoLevel, SparseInfo,
 };
-pub use crypt_storage::NcaCryptStorage;
-pub use structs::{NcaContentType, NcaSectionType};
-pub use verification_storage::{IntegrityCheckLevel, NcaVerificationStorage};
+pub use verification_storage::{BlockStatusCache, IntegrityCheckLevel, NcaVerificationStorage};
 
 use crate::formats::nca::contents::Body;
 use crate::formats::nca::ncz::NczBodyStorage;
-pub use ncz::NczError;
+pub use ncz::{DecompressedNca, Ncz, NczCacheLayer, NczCacheOptions, NczError};
 
 #[derive(Snafu, Debug)]
 pub enum NcaError {
@@ -35,8 +48,9 @@ pub enum NcaError {
     MissingKey {
         source: crate::crypto::keyset::MissingKeyError,
     },
-    /// NCA: Missing a title key
+    /// NCA: Missing the title key for title {title_id}
     MissingTitleKey {
+        title_id: AnyId,
         source: crate::crypto::keyset::MissingTitleKeyError,
     },
     /// NCA: Failed to parse the NCA header
@@ -47,14 +61,59 @@ pub enum NcaError {
     Ncz { source: NczError },
     /// NCA: FS header hash mismatch for section {index}
     FsHeaderHashMismatch { index: usize },
-    /// NCA: Invalid size: expected {expected}, got {actual}
-    StorageSizeMismatch { expected: u64, actual: u64 },
+    /// NCA: Invalid size: the header declares {declared}, the body has {actual} (NCZ: {is_ncz})
+    // for NCZ, `actual` is the decompressed size, so a mismatch points to a bug in the NCZ handling rather than a corrupted file
+    StorageSizeMismatch {
+        declared: u64,
+        actual: u64,
+        is_ncz: bool,
+    },
+    /// NCA: Failed to parse the RomFS in section {index}
+    RomfsParsing {
+        index: usize,
+        source: crate::formats::romfs::RomFsParseError,
+    },
+    /// NCA: Failed to parse the PFS0 in section {index}
+    PfsParsing {
+        index: usize,
+        source: crate::formats::pfs::PfsParseError,
+    },
+    /// NCA: Section {index} is not present
+    MissingSection { index: usize },
+    /// NCA: Section {index} decrypted to garbage, the keys used for this content are probably wrong
+    LikelyWrongKey { index: usize },
+    /// NCA: Section {index} is not a patch section
+    NotPatchSection { index: usize },
+    /// NCA: Section {index} is a patch section and can only be read over its base NCA
+    PatchSection { index: usize },
+    /// NCA: Patch section {index} of an NCZ is not supported
+    PatchInNcz { index: usize },
+    /// NCA: Invalid BKTR tables in section {index}
+    Bktr { index: usize, source: BktrError },
+    /// NCA: Section {index} has an unsupported FS header version {version}
+    UnsupportedFsHeaderVersion { index: usize, version: u16 },
+    /// NCA: Unexpected number of sections for a {content_type}: {count}
+    UnexpectedSectionCount {
+        content_type: NcaContentType,
+        count: usize,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct AllNcaHeaders {
     pub nca_header: NcaHeader,
     pub fs_headers: [Option<NcaFsHeader>; 4],
+    pub raw: RawHeaders,
+}
+
+/// Decrypted bytes of all the headers
+#[derive(Clone)]
+struct RawHeaders(Box<[u8; ALL_HEADERS_SIZE]>);
+
+impl Debug for RawHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RawHeaders").finish_non_exhaustive()
+    }
 }
 
 impl AllNcaHeaders {
@@ -71,7 +130,7 @@ impl AllNcaHeaders {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum NcaContentKeys {
     /// NCA is decrypted, no keys are needed.
     Plaintext,
@@ -80,6 +139,28 @@ enum NcaContentKeys {
     KeyArea { ctr: AesKey, xts: AesXtsKey },
     /// Decrypted key for the RightsId crypto obtained externally
     RightsId(AesKey),
+    /// The keys could not be derived, the error is reported when an encrypted section is opened
+    Missing(MissingContentKey),
+}
+
+#[derive(Debug, Clone)]
+enum MissingContentKey {
+    Key(MissingKeyError),
+    TitleKey {
+        title_id: AnyId,
+        source: MissingTitleKeyError,
+    },
+}
+
+impl MissingContentKey {
+    fn to_error(&self) -> NcaError {
+        match self.clone() {
+            MissingContentKey::Key(source) => NcaError::MissingKey { source },
+            MissingContentKey::TitleKey { title_id, source } => {
+                NcaError::MissingTitleKey { title_id, source }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -87,58 +168,148 @@ pub struct Nca<S: ReadableStorage> {
     body: Body<S>,
     headers: AllNcaHeaders,
     content_key: NcaContentKeys,
+    block_status_cache: BlockStatusCache,
+}
+
+/// Cheap, the clones share the same underlying storage (deriving it would require `S: Clone`)
+impl<S: ReadableStorage> Clone for Nca<S> {
+    fn clone(&self) -> Self {
+        Self {
+            body: self.body.clone(),
+            headers: self.headers.clone(),
+            content_key: self.content_key.clone(),
+            block_status_cache: self.block_status_cache,
+        }
+    }
 }
 
 const ALL_HEADERS_SIZE: usize = 0xc00;
 const NCA_HEADER_SIZE: usize = 0x400;
 const HEADER_SECTOR_SIZE: usize = 0x200;
+/// The only FS header layout known, other versions may lay out the fields differently
+const SUPPORTED_FS_HEADER_VERSION: u16 = 2;
+/// Larger than any real NCA (the biggest game cards are 64 GiB), used to tell plaintext headers from random data
+const MAX_PLAUSIBLE_NCA_SIZE: u64 = 1 << 40;
 
 impl<S: ReadableStorage> Nca<S> {
     pub fn new(key_set: &KeySet, storage: S) -> Result<Self, NcaError> {
-        let (headers, is_decrypted) = Self::parse_headers(key_set, &storage)?;
+        Self::new_impl(key_set, storage, false, None, &NczCacheOptions::default())
+    }
 
-        let content_key = if is_decrypted {
-            NcaContentKeys::Plaintext
-        } else if headers.has_rights_id() {
-            let title_key = key_set
-                .title_key(&headers.nca_header.rights_id)
-                .context(MissingTitleKeySnafu)?;
+    /// Same as [`Nca::new`], but with the caching of the decompressed NCZ body configured by `ncz_options`
+    ///
+    /// Has no effect on the plain NCAs.
+    pub fn new_with_ncz_options(
+        key_set: &KeySet,
+        storage: S,
+        ncz_options: &NczCacheOptions,
+    ) -> Result<Self, NcaError> {
+        Self::new_impl(key_set, storage, false, None, ncz_options)
+    }
 
-            let title_kek = key_set
-                .title_kek(headers.master_key_revision())
-                .context(MissingKeySnafu)?;
+    /// Same as [`Nca::new`], but asks `title_key_provider` for the title key if it is missing from the `key_set`
+    pub fn new_with_title_key_provider(
+        key_set: &KeySet,
+        storage: S,
+        title_key_provider: &mut dyn TitleKeyProvider,
+    ) -> Result<Self, NcaError> {
+        Self::new_impl(
+            key_set,
+            storage,
+            false,
+            Some(title_key_provider),
+            &NczCacheOptions::default(),
+        )
+    }
 
-            NcaContentKeys::RightsId(title_key.decrypt(title_kek))
-        } else {
-            let kak = key_set
-                .key_area_key(
-                    headers.master_key_revision(),
-                    headers.nca_header.key_area_key_index,
-                )
-                .context(MissingKeySnafu)?;
+    /// Same as [`Nca::new`], but only warns about FS header hash mismatches instead of failing.
+    ///
+    /// Useful to recover data from slightly corrupted NCAs.
+    pub fn new_lenient(key_set: &KeySet, storage: S) -> Result<Self, NcaError> {
+        Self::new_impl(key_set, storage, true, None, &NczCacheOptions::default())
+    }
 
-            let ctr = kak.decrypt_key(headers.nca_header.key_area.encrypted_ctr_key);
-            let xts = kak.decrypt_xts_key(headers.nca_header.key_area.encrypted_xts_key);
+    /// Creates an NCA from headers previously obtained with [`Nca::raw_headers`], skipping their decryption.
+    ///
+    /// `is_plaintext` must be the value of [`Nca::is_plaintext`] of the NCA the headers came from.
+    ///
+    /// The FS header hashes are checked strictly like in [`Nca::new`], so the headers of an NCA
+    /// opened with [`Nca::new_lenient`] despite a mismatch are rejected.
+    pub fn from_raw_headers(
+        key_set: &KeySet,
+        storage: S,
+        raw_headers: &[u8; ALL_HEADERS_SIZE],
+        is_plaintext: bool,
+    ) -> Result<Self, NcaError> {
+        let headers = Self::parse_decrypted_headers(*raw_headers, false)?;
+        Self::from_headers(
+            key_set,
+            storage,
+            headers,
+            is_plaintext,
+            None,
+            &NczCacheOptions::default(),
+        )
+    }
 
-            NcaContentKeys::KeyArea { ctr, xts }
+    fn new_impl(
+        key_set: &KeySet,
+        storage: S,
+        lenient: bool,
+        title_key_provider: Option<&mut dyn TitleKeyProvider>,
+        ncz_options: &NczCacheOptions,
+    ) -> Result<Self, NcaError> {
+        let (headers, is_decrypted) = Self::parse_headers(key_set, &storage, lenient)?;
+        Self::from_headers(
+            key_set,
+            storage,
+            headers,
+            is_decrypted,
+            title_key_provider,
+            ncz_options,
+        )
+    }
+
+    fn from_headers(
+        key_set: &KeySet,
+        storage: S,
+        headers: AllNcaHeaders,
+        is_decrypted: bool,
+        title_key_provider: Option<&mut dyn TitleKeyProvider>,
+        ncz_options: &NczCacheOptions,
+    ) -> Result<Self, NcaError> {
+        let content_key = if is_decrypted {
+            NcaContentKeys::Plaintext
+        } else {
+            Self::derive_content_key(key_set, &headers, title_key_provider)
+                .unwrap_or_else(NcaContentKeys::Missing)
         };
 
         let section_count = headers.fs_headers.iter().flatten().count();
-        if headers.nca_header.content_type == NcaContentType::Program {
-            assert!(matches!(section_count, 2 | 3)); // base NCA contain 3 sections, update NCA contain 2 sections (w/o the logo)
+        let content_type = headers.nca_header.content_type;
+        let section_count_valid = if content_type == NcaContentType::Program {
+            matches!(section_count, 2 | 3) // base NCA contain 3 sections, update NCA contain 2 sections (w/o the logo)
         } else {
-            assert_eq!(section_count, 1);
+            section_count == 1
         };
+        if !section_count_valid {
+            return Err(NcaError::UnexpectedSectionCount {
+                content_type,
+                count: section_count,
+            });
+        }
 
-        let body = match NczBodyStorage::try_new(storage).context(NczSnafu)? {
+        let body = NczBodyStorage::try_new_with_options(storage, ncz_options).context(NczSnafu)?;
+        let body = match body {
             Either::Left(ncz_storage) => Body::Ncz(ncz_storage.shared()),
             Either::Right(storage) => Body::Nca(storage.shared()),
         };
 
         if headers.nca_header.nca_size != body.get_size() {
             return Err(NcaError::StorageSizeMismatch {
-                expected: headers.nca_header.nca_size,
+                declared: headers.nca_header.nca_size,
                 actual: body.get_size(),
+                is_ncz: matches!(body, Body::Ncz(_)),
             });
         }
 
@@ -146,13 +317,84 @@ impl<S: ReadableStorage> Nca<S> {
             body,
             headers,
             content_key,
+            block_status_cache: BlockStatusCache::default(),
         })
     }
 
+    /// Derives the keys needed to decrypt the sections.
+    ///
+    /// Missing keys are not an error yet: the headers are still usable with only the header key,
+    /// so the error is deferred until a section needing the keys is opened.
+    fn derive_content_key(
+        key_set: &KeySet,
+        headers: &AllNcaHeaders,
+        title_key_provider: Option<&mut dyn TitleKeyProvider>,
+    ) -> Result<NcaContentKeys, MissingContentKey> {
+        let master_key_revision = headers.master_key_revision();
+        if headers.has_rights_id() {
+            let title_id = headers.nca_header.title_id;
+            let rights_id = &headers.nca_header.rights_id;
+            let title_key = key_set
+                .title_key(rights_id)
+                .or_else(|e| {
+                    title_key_provider
+                        .and_then(|provider| provider.title_key(rights_id))
+                        .ok_or(e)
+                })
+                .map_err(|source| MissingContentKey::TitleKey { title_id, source })?;
+
+            let title_kek = key_set
+                .title_kek(master_key_revision)
+                .map_err(MissingContentKey::Key)?;
+
+            Ok(NcaContentKeys::RightsId(title_key.decrypt(title_kek)))
+        } else {
+            let kak = key_set
+                .key_area_key(master_key_revision, headers.nca_header.key_area_key_index)
+                .map_err(MissingContentKey::Key)?;
+
+            let ctr = kak.decrypt_key(headers.nca_header.key_area.encrypted_ctr_key);
+            let xts = kak.decrypt_xts_key(headers.nca_header.key_area.encrypted_xts_key);
+
+            Ok(NcaContentKeys::KeyArea { ctr, xts })
+        }
+    }
+
+    /// Fails with [`NcaError::MissingKey`] or [`NcaError::MissingTitleKey`] if the keys needed to decrypt the sections are not available.
+    ///
+    /// [`Nca::new`] succeeds with only the header key, so use this to check that the sections can actually be opened.
+    pub fn check_content_key(&self) -> Result<(), NcaError> {
+        match &self.content_key {
+            NcaContentKeys::Missing(missing) => Err(missing.to_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets how the section storages opened afterwards remember the verified blocks, see [`BlockStatusCache`]
+    pub fn set_block_status_cache(&mut self, block_status_cache: BlockStatusCache) {
+        self.block_status_cache = block_status_cache;
+    }
+
     pub fn is_plaintext(&self) -> bool {
         matches!(self.content_key, NcaContentKeys::Plaintext)
     }
 
+    /// The storage of the whole NCA file as it is stored, `None` for NCZs (they store the decompressed data)
+    pub(crate) fn nca_storage(&self) -> Option<&SharedStorage<S>> {
+        match &self.body {
+            Body::Nca(storage) => Some(storage),
+            Body::Ncz(_) => None,
+        }
+    }
+
+    /// Hit/miss counters of the decompressed block cache, `None` if this is not an NCZ
+    pub fn ncz_cache_stats(&self) -> Option<CacheStats> {
+        match &self.body {
+            Body::Nca(_) => None,
+            Body::Ncz(body) => Some(body.cache_stats()),
+        }
+    }
+
     fn try_parse_nca_header(header: &[u8]) -> Result<NcaHeader, NcaError> {
         assert_eq!(header.len(), NCA_HEADER_SIZE);
         let mut cur = Cursor::new(header);
@@ -162,8 +404,20 @@ impl<S: ReadableStorage> Nca<S> {
         Ok(res)
     }
 
+    /// Whether the `nca_size` from a header looks right for a body of `storage_size`
+    fn is_plausible_size(nca_size: u64, storage_size: u64) -> bool {
+        // the body is either the NCA itself or an NCZ, which is smaller
+        // (save for incompressible contents, where the NCZ tables add a bit)
+        (ALL_HEADERS_SIZE as u64..=MAX_PLAUSIBLE_NCA_SIZE).contains(&nca_size)
+            && storage_size <= nca_size.saturating_add(nca_size / 2)
+    }
+
     /// Just do the decryption, don't parse the full header yet.
-    fn parse_headers(key_set: &KeySet, storage: &S) -> Result<(AllNcaHeaders, bool), NcaError> {
+    fn parse_headers(
+        key_set: &KeySet,
+        storage: &S,
+        lenient: bool,
+    ) -> Result<(AllNcaHeaders, bool), NcaError> {
         let mut headers_data = [0; ALL_HEADERS_SIZE];
         storage.read(0, &mut headers_data).context(StorageSnafu)?;
 
@@ -171,10 +425,13 @@ impl<S: ReadableStorage> Nca<S> {
 
         let mut is_decrypted = false;
 
-        let nca_header = if let Ok(nca_header) = Self::try_parse_nca_header(nca_header_data) {
-            // if we were able to parse the NCA header, chances are it's not encrypted
+        // if we were able to parse the NCA header (this includes a known magic), chances are it's not encrypted
+        // check the size too though, an encrypted header can still parse by coincidence
+        let plaintext_header = Self::try_parse_nca_header(nca_header_data).ok();
+        if plaintext_header
+            .is_some_and(|header| Self::is_plausible_size(header.nca_size, storage.get_size()))
+        {
             is_decrypted = true;
-            nca_header
         } else {
             // else - perform the decryption
             let key = key_set.header_key().context(MissingKeySnafu)?;
@@ -201,9 +458,20 @@ impl<S: ReadableStorage> Nca<S> {
                     key.decrypt(fs_header_data, 2, HEADER_SECTOR_SIZE);
                 }
             }
+        }
 
-            nca_header
-        };
+        Ok((
+            Self::parse_decrypted_headers(headers_data, lenient)?,
+            is_decrypted,
+        ))
+    }
+
+    fn parse_decrypted_headers(
+        headers_data: [u8; ALL_HEADERS_SIZE],
+        lenient: bool,
+    ) -> Result<AllNcaHeaders, NcaError> {
+        let (nca_header_data, fs_header_data) = headers_data.split_at(NCA_HEADER_SIZE);
+        let nca_header = Self::try_parse_nca_header(nca_header_data)?;
 
         // TODO: here we ignore the header signature, probably we should check it
 
@@ -214,8 +482,13 @@ impl<S: ReadableStorage> Nca<S> {
 
             if section_entry.is_enabled {
                 let hash = nca_header.fs_header_hashes[index];
-                hash.verify(data)
-                    .map_err(|_| NcaError::FsHeaderHashMismatch { index })?;
+                if hash.verify(data).is_err() {
+                    if lenient {
+                        warn!("FS header hash mismatch for section {}, ignoring", index);
+                    } else {
+                        return Err(NcaError::FsHeaderHashMismatch { index });
+                    }
+                }
 
                 let mut cur = Cursor::new(data);
 
@@ -225,17 +498,268 @@ impl<S: ReadableStorage> Nca<S> {
             }
         }
 
-        Ok((
-            AllNcaHeaders {
-                nca_header,
-                fs_headers,
-            },
-            is_decrypted,
-        ))
+        Ok(AllNcaHeaders {
+            nca_header,
+            fs_headers,
+            raw: RawHeaders(Box::new(headers_data)),
+        })
     }
 }
 impl<S: ReadableStorage> Nca<S> {
     pub fn content_type(&self) -> NcaContentType {
         self.headers.nca_header.content_type
     }
+
+    /// Index of this content among the contents of the same program
+    pub fn content_index(&self) -> u32 {
+        self.headers.nca_header.content_index
+    }
+
+    pub fn header(&self) -> &NcaHeader {
+        &self.headers.nca_header
+    }
+
+    /// `None` if the section is not present
+    pub fn fs_header(&self, index: usize) -> Option<&NcaFsHeader> {
+        self.headers.fs_headers.get(index)?.as_ref()
+    }
+
+    /// The RightsId whose title key decrypts this NCA, `None` if it uses the key area crypto (or is plaintext)
+    pub fn required_rights_id(&self) -> Option<RightsId> {
+        self.headers
+            .has_rights_id()
+            .then_some(self.headers.nca_header.rights_id)
+    }
+
+    /// Decrypted bytes of the NCA header followed by the 4 FS headers
+    pub fn raw_headers(&self) -> &[u8; ALL_HEADERS_SIZE] {
+        &self.headers.raw.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_fixture::{
+        empty_key_set, make_pfs0, make_plaintext_nca, make_plaintext_patch_nca, PFS0_OFFSET,
+        SECTION_OFFSET,
+    };
+    use super::{
+        BlockStatusCache, IntegrityCheckLevel, Nca, NcaContentType, NcaCryptStorage, NcaError,
+        NcaSectionType,
+    };
+    use crate::crypto::AesKey;
+    use crate::filesystem::{ReadableFile, ReadableFileSystem};
+    use crate::storage::{ReadableStorage, ReadableStorageExt, VecStorage};
+
+    const FILES: &[(&str, &[u8])] = &[("a.cnmt", b"hello"), ("b.bin", &[0x42; 0x1000])];
+
+    #[test]
+    fn patched_section() {
+        let data = [[0x42; 0x800], [0x43; 0x800]].concat();
+        let files: &[(&str, &[u8])] = &[("a.cnmt", b"hello"), ("b.bin", &data)];
+        let base = Nca::new(
+            &empty_key_set(),
+            make_plaintext_nca(NcaContentType::Data, FILES, 0x200),
+        )
+        .unwrap();
+        let patch = Nca::new(
+            &empty_key_set(),
+            make_plaintext_patch_nca(NcaContentType::Data, FILES, files, 0x200),
+        )
+        .unwrap();
+
+        let storage = patch
+            .get_patched_storage(NcaSectionType::Data, &base, IntegrityCheckLevel::Full)
+            .unwrap()
+            .unwrap();
+        assert_eq!(storage.read_all().unwrap(), make_pfs0(files));
+        assert!(matches!(
+            patch.get_section_storage(0, IntegrityCheckLevel::Full),
+            Some(Err(NcaError::PatchSection { index: 0 }))
+        ));
+
+        // the unchanged parts come from the base, so they are verified by the hashes of the patch too
+        let wrong_files: &[(&str, &[u8])] = &[("a.cnmt", b"HELLO"), ("b.bin", &[0x42; 0x1000])];
+        let wrong_base = Nca::new(
+            &empty_key_set(),
+            make_plaintext_nca(NcaContentType::Data, wrong_files, 0x200),
+        )
+        .unwrap();
+        let storage = patch
+            .get_patched_section_storage(0, &wrong_base, IntegrityCheckLevel::Full)
+            .unwrap()
+            .unwrap();
+        assert!(storage.read_all().is_err());
+
+        assert!(matches!(
+            base.get_patched_section_storage(0, &base, IntegrityCheckLevel::Full),
+            Some(Err(NcaError::NotPatchSection { index: 0 }))
+        ));
+    }
+
+    #[test]
+    fn plaintext_pfs0() {
+        let storage = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200);
+        let nca = Nca::new(&empty_key_set(), storage).unwrap();
+        assert!(nca.is_plaintext());
+        assert_eq!(nca.content_type(), NcaContentType::Meta);
+
+        let info = nca.section_info(0).unwrap();
+        assert_eq!(info.offset, SECTION_OFFSET);
+        assert_eq!(
+            nca.section_ranges(),
+            [Some((SECTION_OFFSET, info.size)), None, None, None]
+        );
+        assert_eq!(info.block_sizes, vec![0x200, 0x200]);
+
+        let fs = nca
+            .get_fs(NcaSectionType::Data, IntegrityCheckLevel::Full)
+            .unwrap()
+            .unwrap();
+        for (name, data) in FILES {
+            let file = fs.open_file(&format!("/{}", name)).unwrap();
+            assert_eq!(&file.storage().unwrap().read_all().unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn implausible_plaintext() {
+        let mut data = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200).into_inner();
+        // a header with a nonsensical size could be encrypted data parsing by coincidence
+        data[0x208..0x210].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        // so it's not taken as plaintext, and decrypting it needs the header key
+        assert!(matches!(
+            Nca::new(&empty_key_set(), VecStorage::new(data)),
+            Err(NcaError::MissingKey { .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_fs_header_version() {
+        let mut data = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200).into_inner();
+        // the FS header of the first section follows the NCA header
+        data[0x400..0x402].copy_from_slice(&3u16.to_le_bytes());
+        // the FS header hash doesn't match anymore
+        let nca = Nca::new_lenient(&empty_key_set(), VecStorage::new(data)).unwrap();
+
+        assert!(matches!(
+            nca.get_section_storage(0, IntegrityCheckLevel::Full),
+            Some(Err(NcaError::UnsupportedFsHeaderVersion {
+                index: 0,
+                version: 3
+            }))
+        ));
+    }
+
+    #[test]
+    fn corrupted_data() {
+        let mut data = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200).into_inner();
+        // corrupt the last byte of b.bin
+        let last = (SECTION_OFFSET + PFS0_OFFSET) as usize + make_pfs0(FILES).len() - 1;
+        data[last] ^= 1;
+        let nca = Nca::new(&empty_key_set(), VecStorage::new(data)).unwrap();
+
+        assert!(nca
+            .get_fs(NcaSectionType::Data, IntegrityCheckLevel::Full)
+            .unwrap()
+            .is_err());
+
+        let fs = nca
+            .get_fs(NcaSectionType::Data, IntegrityCheckLevel::None)
+            .unwrap()
+            .unwrap();
+        let file = fs.open_file("/b.bin").unwrap();
+        assert_eq!(file.storage().unwrap().read_all().unwrap()[0xfff], 0x42 ^ 1);
+    }
+
+    #[test]
+    fn block_status_cache() {
+        let mut data = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200).into_inner();
+        let last = (SECTION_OFFSET + PFS0_OFFSET) as usize + make_pfs0(FILES).len() - 1;
+        data[last] ^= 1;
+        let mut nca = Nca::new(&empty_key_set(), VecStorage::new(data)).unwrap();
+
+        for cache in [
+            BlockStatusCache::Full,
+            BlockStatusCache::Compact,
+            BlockStatusCache::None,
+        ] {
+            nca.set_block_status_cache(cache);
+            let storage = nca
+                .get_section_storage(0, IntegrityCheckLevel::Full)
+                .unwrap()
+                .unwrap();
+            // the second read hits the cached status (if any)
+            assert!(storage.read_all().is_err());
+            assert!(storage.read_all().is_err());
+
+            let storage = nca
+                .get_section_storage(0, IntegrityCheckLevel::IgnoreOnInvalid)
+                .unwrap()
+                .unwrap();
+            assert_eq!(storage.read_all().unwrap()[..4], *b"PFS0");
+            assert_eq!(storage.read_all().unwrap()[..4], *b"PFS0");
+        }
+    }
+
+    #[test]
+    fn dump_section() {
+        let storage = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200);
+        let nca = Nca::new(&empty_key_set(), storage).unwrap();
+
+        let out = VecStorage::new(Vec::new());
+        nca.dump_section(0, IntegrityCheckLevel::Full, &out)
+            .unwrap();
+        assert_eq!(out.into_inner(), make_pfs0(FILES));
+
+        assert!(matches!(
+            nca.dump_section(1, IntegrityCheckLevel::Full, &VecStorage::new(Vec::new())),
+            Err(NcaError::MissingSection { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn missing_content_key() {
+        let make_storage = || make_plaintext_nca(NcaContentType::Meta, FILES, 0x200);
+        let raw_headers = *Nca::new(&empty_key_set(), make_storage())
+            .unwrap()
+            .raw_headers();
+
+        // pretend the NCA is encrypted: the key area key can't be derived from the empty key set
+        let nca =
+            Nca::from_raw_headers(&empty_key_set(), make_storage(), &raw_headers, false).unwrap();
+        assert!(!nca.is_plaintext());
+        assert_eq!(nca.content_type(), NcaContentType::Meta);
+        assert!(matches!(
+            nca.check_content_key(),
+            Err(NcaError::MissingKey { .. })
+        ));
+
+        // the section itself is not encrypted, so it can still be opened
+        let fs = nca
+            .get_fs(NcaSectionType::Data, IntegrityCheckLevel::Full)
+            .unwrap()
+            .unwrap();
+        let file = fs.open_file("/a.cnmt").unwrap();
+        assert_eq!(&file.storage().unwrap().read_all().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn encrypt_section() {
+        let storage = make_plaintext_nca(NcaContentType::Meta, FILES, 0x200);
+        let nca = Nca::new(&empty_key_set(), storage).unwrap();
+        assert!(nca.content_ctr_key().is_none());
+
+        let key = AesKey::from([0x13; 0x10]);
+        let encrypted = VecStorage::new(Vec::new());
+        nca.encrypt_section(0, key, &encrypted).unwrap();
+
+        let plaintext = nca.get_raw_decrypted_section_storage(0).unwrap().unwrap();
+        assert_eq!(encrypted.get_size(), plaintext.get_size());
+        assert_ne!(encrypted.read_all().unwrap(), plaintext.read_all().unwrap());
+
+        let decrypted = NcaCryptStorage::new_ctr(encrypted, key, 0, SECTION_OFFSET);
+        assert_eq!(decrypted.read_all().unwrap(), plaintext.read_all().unwrap());
+    }
 }
@@ -1,13 +1,16 @@
 mod streaming_zstd_storage;
 
+use super::crypt_storage::NcaCryptStorage;
+use crate::crypto::AesKey;
 use crate::hexstring::HexData;
 use crate::storage::{
-    BlockAdapterStorage, BlockCacheStorage, ConcatStorageN, LinearAdapterStorage, ReadableStorage,
-    ReadableStorageExt, SharedStorage, SliceStorage, StorageError, StorageIo,
+    BlockAdapterStorage, BlockCacheStorage, BlockCacheStorageBuilder, CacheStats, ConcatStorage2,
+    ConcatStorageN, LinearAdapterStorage, ReadableStorage, ReadableStorageExt, SharedStorage,
+    SliceStorage, StorageError, StorageIo, VecStorage,
 };
 use streaming_zstd_storage::StreamingZstdStorage;
 
-use binrw::{BinRead, BinReaderExt, BinWrite};
+use binrw::{BinRead, BinWrite};
 use itertools::Either;
 use snafu::{ResultExt, Snafu};
 use std::io::{BufReader, Read, Seek, SeekFrom};
@@ -26,9 +29,16 @@ pub enum NczError {
     InvalidBlockSizeExponent { exponent: u8 },
     /// NCZ's size is not the same as the storage's size: expected {expected}, got {actual}
     SizeMismatch { expected: u64, actual: u64 },
+    /// The storage is not an NCZ
+    NotNcz {},
+    /// Unsupported NCZ section crypto type: {crypto_type}
+    UnsupportedCryptoType { crypto_type: u64 },
+    /// NCZ section at {offset:#x} with size {size:#x} is out of bounds of the NCA
+    InvalidSection { offset: u64, size: u64 },
 }
 
 #[derive(Debug, Clone, BinRead, BinWrite)]
+#[brw(little)]
 struct NczSectionHeader {
     offset: u64,
     size: u64,
@@ -39,10 +49,32 @@ struct NczSectionHeader {
     crypto_counter: HexData<0x10>,
 }
 
+impl NczSectionHeader {
+    /// The section is stored unencrypted in the NCA
+    const CRYPTO_TYPE_NONE: u64 = 1;
+    /// The section is AES-CTR encrypted in the NCA
+    const CRYPTO_TYPE_AES_CTR: u64 = 3;
+    /// The section is AES-CTR-EX encrypted in the NCA, which is AES-CTR for the purposes of NCZ
+    const CRYPTO_TYPE_AES_CTR_EX: u64 = 4;
+
+    fn is_encrypted(&self) -> Result<bool, NczError> {
+        match self.crypto_type {
+            0 | Self::CRYPTO_TYPE_NONE => Ok(false),
+            Self::CRYPTO_TYPE_AES_CTR | Self::CRYPTO_TYPE_AES_CTR_EX => Ok(true),
+            crypto_type => Err(NczError::UnsupportedCryptoType { crypto_type }),
+        }
+    }
+
+    fn upper_counter(&self) -> u64 {
+        u64::from_be_bytes(self.crypto_counter.0[..8].try_into().unwrap())
+    }
+}
+
 const NCZ_MAGIC: &[u8; 8] = b"NCZSECTN";
 const NCZ_BLOCK_MAGIC: &[u8; 8] = b"NCZBLOCK";
 
 #[derive(Debug, Clone, BinRead, BinWrite)]
+#[brw(little)]
 #[br(magic = b"NCZSECTN")]
 struct NczHeader {
     section_count: u64,
@@ -51,6 +83,7 @@ struct NczHeader {
 }
 
 #[derive(Debug, Clone, BinRead, BinWrite)]
+#[brw(little)]
 #[br(magic = b"NCZBLOCK")]
 struct NczBlockHeader {
     #[br(assert(version == 0x2))]
@@ -68,33 +101,71 @@ const NCA_HEADERS_SIZE: u64 = 0x4000;
 
 #[derive(Debug)]
 pub enum NczBodyStorage<S: ReadableStorage> {
-    Streaming(
-        LinearAdapterStorage<
-            BlockCacheStorage<BlockAdapterStorage<StreamingZstdStorage<SliceStorage<S>>>>,
-        >,
-    ),
-    Block(
-        LinearAdapterStorage<
-            BlockCacheStorage<
-                BlockAdapterStorage<
-                    ConcatStorageN<StreamingZstdStorage<SliceStorage<SharedStorage<S>>>>,
-                >,
-            >,
-        >,
-    ),
+    Streaming(CachedStorage<StreamingZstdStorage<SliceStorage<S>>>),
+    Block(CachedStorage<ConcatStorageN<StreamingZstdStorage<SliceStorage<SharedStorage<S>>>>>),
+    /// Small body, decompressed once when opening
+    InMemory(VecStorage),
+}
+
+type CachedStorage<S> = LinearAdapterStorage<BlockCacheStorage<BlockAdapterStorage<S>>>;
+
+/// Cache of the decompressed data of one kind of NCZ body
+#[derive(Debug, Clone)]
+pub struct NczCacheLayer {
+    /// Size of the cached blocks of the decompressed data
+    pub block_size: u64,
+    pub cache: BlockCacheStorageBuilder,
+}
+
+/// How the decompressed NCZ bodies are kept around, allows to tune the memory use and the throughput
+#[derive(Debug, Clone)]
+pub struct NczCacheOptions {
+    /// Bodies decompressing to at most this many bytes are decompressed eagerly into memory (1 MiB by default)
+    pub in_memory_limit: u64,
+    /// Used for the block-compressed NCZs
+    pub block: NczCacheLayer,
+    /// Used for the NCZs compressed as a single stream, which are slow to seek backwards
+    pub streaming: NczCacheLayer,
+}
+
+impl Default for NczCacheOptions {
+    fn default() -> Self {
+        Self {
+            in_memory_limit: 1024 * 1024,
+            block: NczCacheLayer {
+                block_size: 1024 * 1024,
+                cache: BlockCacheStorageBuilder::default()
+                    .capacity(64)
+                    .time_to_idle(Duration::from_secs(2)),
+            },
+            streaming: NczCacheLayer {
+                block_size: 512 * 1024,
+                cache: BlockCacheStorageBuilder::default()
+                    .capacity(128)
+                    .time_to_idle(Duration::from_secs(2)),
+            },
+        }
+    }
 }
 
-fn make_cache<S: ReadableStorage>(
-    storage: S,
-    block_size: u64,
-    cache_blocks: u64,
-    time_to_idle: Duration,
-) -> LinearAdapterStorage<BlockCacheStorage<BlockAdapterStorage<S>>> {
-    let storage = BlockAdapterStorage::new(storage, block_size);
-    let storage = BlockCacheStorage::new(storage, cache_blocks, time_to_idle);
-    let storage = LinearAdapterStorage::new(storage);
-
-    storage
+fn decompress_in_memory<S: ReadableStorage>(storage: S) -> Result<VecStorage, NczError> {
+    Ok(VecStorage::new(storage.read_all().context(StorageSnafu)?))
+}
+
+fn make_cache<S: ReadableStorage>(storage: S, layer: &NczCacheLayer) -> CachedStorage<S> {
+    let storage = BlockAdapterStorage::new(storage, layer.block_size);
+    let storage = layer.cache.build(storage);
+    LinearAdapterStorage::new(storage)
+}
+
+impl<S: ReadableStorage> NczBodyStorage<S> {
+    pub fn cache_stats(&self) -> CacheStats {
+        match self {
+            Self::Streaming(storage) => storage.inner().stats(),
+            Self::Block(storage) => storage.inner().stats(),
+            Self::InMemory(_) => CacheStats::default(),
+        }
+    }
 }
 
 impl<S: ReadableStorage> ReadableStorage for NczBodyStorage<S> {
@@ -109,6 +180,7 @@ impl<S: ReadableStorage> ReadableStorage for NczBodyStorage<S> {
         match self {
             Self::Streaming(storage) => storage.read(offset, buf),
             Self::Block(storage) => storage.read(offset, buf),
+            Self::InMemory(storage) => storage.read(offset, buf),
         }
     }
 
@@ -116,6 +188,7 @@ impl<S: ReadableStorage> ReadableStorage for NczBodyStorage<S> {
         (match self {
             Self::Streaming(storage) => storage.get_size(),
             Self::Block(storage) => storage.get_size(),
+            Self::InMemory(storage) => storage.get_size(),
         }) + NCA_HEADERS_SIZE
     }
 }
@@ -125,8 +198,9 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
         mut reader: BufReader<StorageIo<S>>,
         _header: NczHeader,
         total_size: u64,
+        options: &NczCacheOptions,
     ) -> Result<NczBodyStorage<S>, NczError> {
-        let block_header: NczBlockHeader = reader.read_le().context(NczHeaderParsingSnafu)?;
+        let block_header = NczBlockHeader::read(&mut reader).context(NczHeaderParsingSnafu)?;
 
         let mut position = reader.stream_position().unwrap();
 
@@ -185,11 +259,13 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
 
         let uncompressed_storage = ConcatStorageN::new(block_storages);
 
+        if block_header.total_decompressed_size <= options.in_memory_limit {
+            return decompress_in_memory(uncompressed_storage).map(NczBodyStorage::InMemory);
+        }
+
         Ok(NczBodyStorage::Block(make_cache(
             uncompressed_storage,
-            1024 * 1024,
-            64,
-            Duration::from_secs(2),
+            &options.block,
         )))
     }
 
@@ -197,6 +273,7 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
         mut reader: BufReader<StorageIo<S>>,
         header: NczHeader,
         total_size: u64,
+        options: &NczCacheOptions,
     ) -> Result<NczBodyStorage<S>, NczError> {
         let compress_start = reader.stream_position().unwrap();
         let compress_size = total_size - compress_start;
@@ -215,11 +292,13 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
         let uncompressed_storage = StreamingZstdStorage::new(compressed_storage, uncompressed_size)
             .context(StorageSnafu)?;
 
+        if uncompressed_size <= options.in_memory_limit {
+            return decompress_in_memory(uncompressed_storage).map(NczBodyStorage::InMemory);
+        }
+
         Ok(NczBodyStorage::Streaming(make_cache(
             uncompressed_storage,
-            512 * 1024,
-            128,
-            Duration::from_secs(2),
+            &options.streaming,
         )))
     }
 
@@ -227,6 +306,14 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
     ///
     /// The header is unavailable in this storage
     pub fn try_new(storage: S) -> Result<Either<NczBodyStorage<S>, S>, NczError> {
+        Self::try_new_with_options(storage, &NczCacheOptions::default())
+    }
+
+    /// Same as [`NczBodyStorage::try_new`], but with the caching configured by `options`
+    pub fn try_new_with_options(
+        storage: S,
+        options: &NczCacheOptions,
+    ) -> Result<Either<NczBodyStorage<S>, S>, NczError> {
         let total_size = storage.get_size();
 
         if total_size < NCA_HEADERS_SIZE + 8 {
@@ -247,7 +334,7 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
             .seek(SeekFrom::Start(NCA_HEADERS_SIZE))
             .expect("BUG: Failed to seek to NCZ header");
 
-        let header: NczHeader = reader.read_le().context(NczHeaderParsingSnafu)?;
+        let header = NczHeader::read(&mut reader).context(NczHeaderParsingSnafu)?;
 
         let mut block_magic = [0; 8];
         reader
@@ -259,10 +346,253 @@ impl<S: ReadableStorage> NczBodyStorage<S> {
             .expect("BUG: Failed to seek back to NCZ block header");
 
         if &block_magic == NCZ_BLOCK_MAGIC {
-            Self::make_block(reader, header, total_size)
+            Self::make_block(reader, header, total_size, options)
         } else {
-            Self::make_stream(reader, header, total_size)
+            Self::make_stream(reader, header, total_size, options)
         }
         .map(Either::Left)
     }
 }
+
+/// Entry point for opening NCZ files without going through [`Nca`](crate::formats::nca::Nca)
+pub struct Ncz;
+
+type SectionStorage<S> =
+    NcaCryptStorage<SliceStorage<SharedStorage<NczBodyStorage<SharedStorage<S>>>>>;
+
+impl Ncz {
+    /// Opens an NCZ, returning a storage over the reconstructed NCA
+    ///
+    /// The first 0x4000 bytes are the (still encrypted) NCA headers, followed by the sections.
+    /// NCZ stores the sections decrypted, so they are re-encrypted according to the NCZ section headers,
+    /// making the result identical to the original NCA.
+    pub fn open<S: ReadableStorage>(storage: S) -> Result<DecompressedNca<S>, NczError> {
+        let storage = storage.shared();
+
+        let body = match NczBodyStorage::try_new(storage.clone())? {
+            Either::Left(body) => body.shared(),
+            Either::Right(_) => return Err(NczError::NotNcz {}),
+        };
+        let total_size = body.get_size();
+
+        let mut reader = storage.clone().buf_read();
+        reader
+            .seek(SeekFrom::Start(NCA_HEADERS_SIZE))
+            .expect("BUG: Failed to seek to NCZ header");
+        let header = NczHeader::read(&mut reader).context(NczHeaderParsingSnafu)?;
+
+        let mut section_headers = header.section_headers;
+        section_headers.sort_by_key(|section| section.offset);
+
+        // split the body into sections, filling the gaps between them with plaintext
+        let plaintext = |offset: u64, size: u64| {
+            NcaCryptStorage::new_plaintext(
+                body.clone()
+                    .slice(offset, size)
+                    .expect("BUG: Failed to slice NCZ body"),
+            )
+        };
+        let mut position = NCA_HEADERS_SIZE;
+        let mut body_storages = Vec::new();
+        for section in &section_headers {
+            // the first section usually claims to start at the beginning of the NCA, but the headers are not part of it
+            let offset = std::cmp::max(section.offset, NCA_HEADERS_SIZE);
+            let end = section.offset.saturating_add(section.size);
+            if offset < position || end > total_size {
+                return Err(NczError::InvalidSection {
+                    offset: section.offset,
+                    size: section.size,
+                });
+            }
+            if end <= offset {
+                continue;
+            }
+            if position < offset {
+                body_storages.push(plaintext(position, offset - position));
+            }
+
+            let section_storage = if section.is_encrypted()? {
+                let storage = body
+                    .clone()
+                    .slice(offset, end - offset)
+                    .expect("BUG: Failed to slice NCZ section");
+                // AES-CTR is symmetric, so "decrypting" the plaintext re-encrypts it
+                NcaCryptStorage::new_ctr(
+                    storage,
+                    AesKey::from(section.crypto_key.0),
+                    section.upper_counter(),
+                    offset,
+                )
+            } else {
+                plaintext(offset, end - offset)
+            };
+            body_storages.push(section_storage);
+
+            position = end;
+        }
+        if position < total_size {
+            body_storages.push(plaintext(position, total_size - position));
+        }
+
+        let headers = storage
+            .slice(0, NCA_HEADERS_SIZE)
+            .expect("BUG: Failed to slice NCZ headers");
+
+        Ok(DecompressedNca {
+            storage: ConcatStorage2::new(headers, ConcatStorageN::new(body_storages)),
+        })
+    }
+}
+
+/// Storage over an NCA reconstructed from an NCZ, see [`Ncz::open`]
+#[derive(Debug)]
+pub struct DecompressedNca<S: ReadableStorage> {
+    storage: ConcatStorage2<SliceStorage<SharedStorage<S>>, ConcatStorageN<SectionStorage<S>>>,
+}
+
+impl<S: ReadableStorage> ReadableStorage for DecompressedNca<S> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.storage.read(offset, buf)
+    }
+
+    fn get_size(&self) -> u64 {
+        self.storage.get_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ncz, NczCacheOptions, NczSectionHeader, NCA_HEADERS_SIZE};
+    use crate::crypto::AesKey;
+    use crate::formats::nca::crypt_storage::ctr_nonce;
+    use crate::formats::nca::test_fixture::{empty_key_set, make_pfs0, make_plaintext_nca};
+    use crate::formats::nca::{IntegrityCheckLevel, Nca, NcaContentType};
+    use crate::storage::{ReadableStorage, ReadableStorageExt, VecStorage};
+
+    const KEY: [u8; 0x10] = [0x13; 0x10];
+    const UPPER_COUNTER: u64 = 0x0102_0304_0506_0708;
+    const BLOCK_EXPONENT: u8 = 14;
+    const FILES: &[(&str, &[u8])] = &[("a.cnmt", &[0x42; 0x9000]), ("b.bin", b"hello")];
+
+    struct Fixture {
+        plaintext: Vec<u8>,
+        /// The plaintext NCA with its section AES-CTR encrypted, like the original of an NCZ
+        encrypted: Vec<u8>,
+        section: (u64, u64),
+    }
+
+    fn make_fixture() -> Fixture {
+        let plaintext = make_plaintext_nca(NcaContentType::Meta, FILES, 0x1000).into_inner();
+        let nca = Nca::new(&empty_key_set(), VecStorage::new(plaintext.clone())).unwrap();
+        let (offset, size) = nca.section_ranges()[0].unwrap();
+        // the NCZ sections have to cover the whole body
+        assert_eq!(
+            (offset, offset + size),
+            (NCA_HEADERS_SIZE, plaintext.len() as u64)
+        );
+
+        let mut encrypted = plaintext.clone();
+        AesKey::from(KEY).encrypt_ctr(
+            &mut encrypted[offset as usize..],
+            &ctr_nonce(UPPER_COUNTER, offset),
+        );
+
+        Fixture {
+            plaintext,
+            encrypted,
+            section: (offset, size),
+        }
+    }
+
+    /// Compresses the fixture like nsz does, either as a single stream or by blocks
+    fn make_ncz(fixture: &Fixture, blocks: bool) -> VecStorage {
+        let (offset, size) = fixture.section;
+        let (headers, body) = fixture.plaintext.split_at(NCA_HEADERS_SIZE as usize);
+        // the headers are already encrypted in a real NCZ, but they are copied as is anyway
+        let mut ncz = headers.to_vec();
+
+        ncz.extend_from_slice(b"NCZSECTN");
+        ncz.extend_from_slice(&1u64.to_le_bytes());
+        ncz.extend_from_slice(&offset.to_le_bytes());
+        ncz.extend_from_slice(&size.to_le_bytes());
+        ncz.extend_from_slice(&NczSectionHeader::CRYPTO_TYPE_AES_CTR.to_le_bytes());
+        ncz.extend_from_slice(&[0; 8]);
+        ncz.extend_from_slice(&KEY);
+        ncz.extend_from_slice(&UPPER_COUNTER.to_be_bytes());
+        ncz.extend_from_slice(&[0; 8]);
+
+        if blocks {
+            let blocks = body
+                .chunks(1 << BLOCK_EXPONENT)
+                .map(|block| zstd::encode_all(block, 0).unwrap())
+                .collect::<Vec<_>>();
+
+            ncz.extend_from_slice(b"NCZBLOCK");
+            ncz.extend_from_slice(&[2, 1, 0, BLOCK_EXPONENT]);
+            ncz.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+            ncz.extend_from_slice(&(body.len() as u64).to_le_bytes());
+            for block in &blocks {
+                ncz.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            }
+            for block in &blocks {
+                ncz.extend_from_slice(block);
+            }
+        } else {
+            ncz.extend_from_slice(&zstd::encode_all(body, 0).unwrap());
+        }
+
+        VecStorage::new(ncz)
+    }
+
+    #[test]
+    fn open_reencrypts() {
+        let fixture = make_fixture();
+        let (offset, size) = fixture.section;
+
+        for blocks in [false, true] {
+            let nca = Ncz::open(make_ncz(&fixture, blocks)).unwrap();
+            assert_eq!(nca.get_size(), fixture.encrypted.len() as u64);
+            assert_eq!(nca.read_all().unwrap(), fixture.encrypted);
+
+            // unaligned reads in the middle of the section and across the block boundaries
+            for (start, len) in [
+                (offset + 0x123, 0x456),
+                (0x7ff0, 0x20),
+                (offset + size - 0x11, 0x11),
+            ] {
+                let mut buf = vec![0; len];
+                nca.read(start, &mut buf).unwrap();
+                assert_eq!(
+                    buf,
+                    &fixture.encrypted[start as usize..start as usize + len],
+                    "read at {start:#x} (blocks: {blocks})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nca_body() {
+        let fixture = make_fixture();
+
+        for blocks in [false, true] {
+            for in_memory_limit in [0, u64::MAX] {
+                let options = NczCacheOptions {
+                    in_memory_limit,
+                    ..Default::default()
+                };
+                let nca = Nca::new_with_ncz_options(
+                    &empty_key_set(),
+                    make_ncz(&fixture, blocks),
+                    &options,
+                )
+                .unwrap();
+                let storage = nca
+                    .get_section_storage(0, IntegrityCheckLevel::Full)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(storage.read_all().unwrap(), make_pfs0(FILES));
+            }
+        }
+    }
+}
@@ -3,9 +3,22 @@ use crate::crypto::{EncryptedAesKey, EncryptedAesXtsKey};
 use crate::hexstring::HexData;
 use crate::ids::{AnyId, RightsId};
 use binrw::{BinRead, BinWrite};
-use std::fmt::Debug;
+use enum_map::Enum;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use snafu::Snafu;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+/// Unknown {ty} name: {name}
+#[derive(Snafu, Debug)]
+pub struct NcaEnumParseError {
+    ty: &'static str,
+    name: String,
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite, Enum)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum NcaSectionType {
     Code,
@@ -14,6 +27,7 @@ pub enum NcaSectionType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum NcaContentType {
     Program,
@@ -24,7 +38,103 @@ pub enum NcaContentType {
     PublicData,
 }
 
+impl NcaSectionType {
+    const ALL: [NcaSectionType; 3] = [
+        NcaSectionType::Code,
+        NcaSectionType::Data,
+        NcaSectionType::Logo,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            NcaSectionType::Code => "code",
+            NcaSectionType::Data => "data",
+            NcaSectionType::Logo => "logo",
+        }
+    }
+}
+
+impl Display for NcaSectionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NcaSectionType::Code => "Code",
+            NcaSectionType::Data => "Data",
+            NcaSectionType::Logo => "Logo",
+        };
+        write!(f, "{} section", name)
+    }
+}
+
+impl FromStr for NcaSectionType {
+    type Err = NcaEnumParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|ty| ty.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| NcaEnumParseError {
+                ty: "section type",
+                name: s.to_string(),
+            })
+    }
+}
+
+impl NcaContentType {
+    const ALL: [NcaContentType; 6] = [
+        NcaContentType::Program,
+        NcaContentType::Meta,
+        NcaContentType::Control,
+        NcaContentType::Manual,
+        NcaContentType::Data,
+        NcaContentType::PublicData,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            NcaContentType::Program => "program",
+            NcaContentType::Meta => "meta",
+            NcaContentType::Control => "control",
+            NcaContentType::Manual => "manual",
+            NcaContentType::Data => "data",
+            NcaContentType::PublicData => "public-data",
+        }
+    }
+}
+
+impl Display for NcaContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            NcaContentType::Program => "Program",
+            NcaContentType::Meta => "Meta",
+            NcaContentType::Control => "Control",
+            NcaContentType::Manual => "Manual",
+            NcaContentType::Data => "Data",
+            NcaContentType::PublicData => "Public data",
+        };
+        write!(f, "{} NCA", name)
+    }
+}
+
+impl FromStr for NcaContentType {
+    type Err = NcaEnumParseError;
+
+    // accepts `public-data` as well as `publicdata`, case-insensitively
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|ty| {
+                ty.name().eq_ignore_ascii_case(s)
+                    || ty.name().replace('-', "").eq_ignore_ascii_case(s)
+            })
+            .ok_or_else(|| NcaEnumParseError {
+                ty: "content type",
+                name: s.to_string(),
+            })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum DistributionType {
     Download,
@@ -32,6 +142,7 @@ pub enum DistributionType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum NcaEncryptionType {
     Auto,
@@ -42,6 +153,7 @@ pub enum NcaEncryptionType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum NcaHashType {
     Auto,
@@ -51,6 +163,7 @@ pub enum NcaHashType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(repr = u8)]
 pub enum NcaFormatType {
     Romfs,
@@ -58,9 +171,11 @@ pub enum NcaFormatType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct NcaSignature(pub HexData<0x100>);
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum NcaMagic {
     #[brw(magic = b"NCA0")]
     Nca0,
@@ -73,6 +188,8 @@ pub enum NcaMagic {
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(into = "u64"))]
 pub struct SectionTableOffset(u32);
 
 impl From<SectionTableOffset> for u64 {
@@ -94,6 +211,7 @@ impl Debug for SectionTableOffset {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SectionTableEntry {
     pub start: SectionTableOffset,
     pub end: SectionTableOffset,
@@ -111,9 +229,11 @@ impl SectionTableEntry {
 
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Sha256Hash(pub HexData<0x20>);
 
 impl Sha256Hash {
+    #[allow(clippy::result_unit_err)]
     pub fn verify(&self, data: &[u8]) -> Result<(), ()> {
         use digest::Digest;
         let mut hasher = sha2::Sha256::default();
@@ -124,6 +244,7 @@ impl Sha256Hash {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct NcaKeyArea {
     pub encrypted_xts_key: EncryptedAesXtsKey,
     pub encrypted_ctr_key: EncryptedAesKey,
@@ -134,6 +255,7 @@ pub struct NcaKeyArea {
 
 /// NCA header, corresponding to the first 0x400 bytes of the decrypted NCA
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(little)]
 pub struct NcaHeader {
     pub fixed_key_signature: NcaSignature,
@@ -156,12 +278,14 @@ pub struct NcaHeader {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Sha256IntegrityInfoLevel {
     pub offset: u64,
     pub size: u64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Sha256IntegrityInfo {
     pub master_hash: Sha256Hash,
     pub block_size: u32,
@@ -170,6 +294,7 @@ pub struct Sha256IntegrityInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct IvfcIntegrityInfoLevel {
     pub offset: u64,
     pub size: u64,
@@ -178,6 +303,7 @@ pub struct IvfcIntegrityInfoLevel {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(magic = b"IVFC")]
 pub struct IvfcIntegrityInfo {
     pub version: u32,
@@ -189,6 +315,7 @@ pub struct IvfcIntegrityInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[br(import(hash_type: NcaHashType))]
 pub enum IntegrityInfo {
     #[br(pre_assert(hash_type == NcaHashType::None))]
@@ -200,6 +327,7 @@ pub enum IntegrityInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PatchInfo {
     pub relocation_tree_offset: u64,
     pub relocation_tree_size: u64,
@@ -210,6 +338,7 @@ pub struct PatchInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SparseInfo {
     pub meta_offset: u64,
     pub meta_size: u64,
@@ -219,6 +348,7 @@ pub struct SparseInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct CompressionInfo {
     pub table_offset: u64,
     pub table_size: u64,
@@ -226,6 +356,7 @@ pub struct CompressionInfo {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 #[brw(little)]
 pub struct NcaFsHeader {
     pub version: u16,
@@ -264,3 +395,32 @@ impl NcaFsHeader {
         self.compression_info.table_offset != 0 && self.compression_info.table_size != 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{NcaContentType, NcaSectionType};
+
+    #[test]
+    fn names() {
+        assert_eq!(NcaContentType::Control.to_string(), "Control NCA");
+        assert_eq!(NcaSectionType::Data.to_string(), "Data section");
+
+        assert_eq!(
+            "logo".parse::<NcaSectionType>().unwrap(),
+            NcaSectionType::Logo
+        );
+        assert_eq!(
+            "Meta".parse::<NcaContentType>().unwrap(),
+            NcaContentType::Meta
+        );
+        assert_eq!(
+            "public-data".parse::<NcaContentType>().unwrap(),
+            NcaContentType::PublicData
+        );
+        assert_eq!(
+            "publicdata".parse::<NcaContentType>().unwrap(),
+            NcaContentType::PublicData
+        );
+        assert!("romfs".parse::<NcaSectionType>().is_err());
+    }
+}
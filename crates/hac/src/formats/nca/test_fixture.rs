@@ -0,0 +1,283 @@
+//! Builders of synthetic plaintext NCAs, allowing to test the NCA stack without real (encrypted) content
+
+use crate::crypto::keyset::KeySet;
+use crate::formats::nca::bktr::{AesCtrExEntry, IndirectEntry};
+use crate::formats::nca::structs::{
+    CompressionInfo, IntegrityInfo, NcaContentType, NcaEncryptionType, NcaFormatType, NcaFsHeader,
+    NcaHashType, NcaHeader, PatchInfo, SectionTableEntry, Sha256Hash, Sha256IntegrityInfo,
+    Sha256IntegrityInfoLevel, SparseInfo,
+};
+use crate::formats::nca::{ALL_HEADERS_SIZE, HEADER_SECTOR_SIZE, NCA_HEADER_SIZE};
+use crate::hexstring::HexData;
+use crate::storage::VecStorage;
+use binrw::{BinRead, BinWrite};
+use digest::Digest;
+use num_integer::Integer;
+use std::io::Cursor;
+
+/// Offset of the first section, same as in the real NCAs
+pub const SECTION_OFFSET: u64 = 0x4000;
+/// Offset of the PFS0 inside the section (right after the hash table)
+pub const PFS0_OFFSET: u64 = 0x200;
+
+fn sha256(data: &[u8]) -> [u8; 0x20] {
+    sha2::Sha256::digest(data).into()
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    Integer::div_ceil(&value, &alignment) * alignment
+}
+
+/// A key set without any keys, enough for the plaintext NCAs
+pub fn empty_key_set() -> KeySet {
+    KeySet::from_file_contents("", "").unwrap()
+}
+
+pub fn make_pfs0(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut string_table = Vec::new();
+    let mut entries = Vec::new();
+    let mut offset = 0u64;
+    for (name, data) in files {
+        entries.extend_from_slice(&offset.to_le_bytes());
+        entries.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        entries.extend_from_slice(&(string_table.len() as u32).to_le_bytes());
+        entries.extend_from_slice(&[0; 4]);
+        string_table.extend_from_slice(name.as_bytes());
+        string_table.push(0);
+        offset += data.len() as u64;
+    }
+
+    let mut result = b"PFS0".to_vec();
+    result.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    result.extend_from_slice(&(string_table.len() as u32).to_le_bytes());
+    result.extend_from_slice(&[0; 4]);
+    result.extend_from_slice(&entries);
+    result.extend_from_slice(&string_table);
+    for (_, data) in files {
+        result.extend_from_slice(data);
+    }
+    result
+}
+
+/// A PFS0 containing `files`, preceded by its HierarchicalSha256 hash table with the given `block_size`
+fn make_pfs0_section(files: &[(&str, &[u8])], block_size: u32) -> (Vec<u8>, IntegrityInfo) {
+    let pfs0 = make_pfs0(files);
+
+    let hash_table = pfs0
+        .chunks(block_size as usize)
+        .flat_map(sha256)
+        .collect::<Vec<_>>();
+    assert!(
+        hash_table.len() as u64 <= PFS0_OFFSET,
+        "too many blocks for the fixture"
+    );
+
+    let mut section = hash_table.clone();
+    section.resize(PFS0_OFFSET as usize, 0);
+    section.extend_from_slice(&pfs0);
+    section.resize(align_up(section.len() as u64, 0x200) as usize, 0);
+
+    let mut level_info = [Sha256IntegrityInfoLevel { offset: 0, size: 0 }; 6];
+    level_info[0].size = hash_table.len() as u64;
+    level_info[1] = Sha256IntegrityInfoLevel {
+        offset: PFS0_OFFSET,
+        size: pfs0.len() as u64,
+    };
+
+    let integrity_info = IntegrityInfo::Sha256(Sha256IntegrityInfo {
+        master_hash: Sha256Hash(HexData(sha256(&hash_table))),
+        block_size,
+        level_count: 2,
+        level_info,
+    });
+    (section, integrity_info)
+}
+
+fn make_fs_header(
+    encryption_type: NcaEncryptionType,
+    integrity_info: IntegrityInfo,
+    patch_info: PatchInfo,
+) -> NcaFsHeader {
+    NcaFsHeader {
+        version: 2,
+        format_type: NcaFormatType::Pfs0,
+        hash_type: NcaHashType::Sha256,
+        encryption_type,
+        integrity_info,
+        patch_info,
+        upper_counter: 0,
+        sparse_info: SparseInfo {
+            meta_offset: 0,
+            meta_size: 0,
+            meta_header: HexData([0; 0x10]),
+            physical_offset: 0,
+            generation: 0,
+        },
+        compression_info: CompressionInfo {
+            table_offset: 0,
+            table_size: 0,
+            table_header: HexData([0; 0x10]),
+        },
+    }
+}
+
+/// Builds a plaintext NCA with a single PFS0 section containing `files`, verified by a HierarchicalSha256 tree with the given `block_size`
+pub fn make_plaintext_nca(
+    content_type: NcaContentType,
+    files: &[(&str, &[u8])],
+    block_size: u32,
+) -> VecStorage {
+    let (section, integrity_info) = make_pfs0_section(files, block_size);
+    let patch_info = PatchInfo {
+        relocation_tree_offset: 0,
+        relocation_tree_size: 0,
+        relocation_tree_header: HexData([0; 0x10]),
+        encryption_tree_offset: 0,
+        encryption_tree_size: 0,
+        encryption_tree_header: HexData([0; 0x10]),
+    };
+    let fs_header = make_fs_header(NcaEncryptionType::None, integrity_info, patch_info);
+
+    make_nca(content_type, &fs_header, section)
+}
+
+/// Builds a plaintext NCA with a patch section, turning the section of the [`make_plaintext_nca`] with `base_files` into the one with `files`
+///
+/// The patch is made in blocks of 0x200 bytes, only the changed ones are stored in the patch data.
+pub fn make_plaintext_patch_nca(
+    content_type: NcaContentType,
+    base_files: &[(&str, &[u8])],
+    files: &[(&str, &[u8])],
+    block_size: u32,
+) -> VecStorage {
+    const PATCH_BLOCK_SIZE: usize = 0x200;
+
+    let (base_section, _) = make_pfs0_section(base_files, block_size);
+    let (section, integrity_info) = make_pfs0_section(files, block_size);
+
+    let mut patch_data = Vec::new();
+    let mut relocation_entries = Vec::<IndirectEntry>::new();
+    for (index, block) in section.chunks(PATCH_BLOCK_SIZE).enumerate() {
+        let offset = index * PATCH_BLOCK_SIZE;
+        let unchanged = base_section.get(offset..offset + block.len()) == Some(block);
+        let storage_index = if unchanged { 0 } else { 1 };
+        if relocation_entries.last().map(|e| e.storage_index) == Some(storage_index) {
+            // extend the previous range
+            if !unchanged {
+                patch_data.extend_from_slice(block);
+            }
+            continue;
+        }
+
+        let physical_offset = if unchanged {
+            offset as u64
+        } else {
+            patch_data.len() as u64
+        };
+        relocation_entries.push(IndirectEntry {
+            virtual_offset: offset as u64,
+            physical_offset,
+            storage_index,
+        });
+        if !unchanged {
+            patch_data.extend_from_slice(block);
+        }
+    }
+
+    let relocation_tree_offset = patch_data.len() as u64;
+    let (relocation_tree_header, relocation_tree) =
+        make_bucket_tree(&relocation_entries, section.len() as u64);
+    let encryption_tree_offset = relocation_tree_offset + relocation_tree.len() as u64;
+    let encryption_entries = [AesCtrExEntry {
+        offset: 0,
+        encryption_value: 0,
+        generation: 0,
+    }];
+    let (encryption_tree_header, encryption_tree) =
+        make_bucket_tree(&encryption_entries, relocation_tree_offset);
+
+    let patch_info = PatchInfo {
+        relocation_tree_offset,
+        relocation_tree_size: relocation_tree.len() as u64,
+        relocation_tree_header,
+        encryption_tree_offset,
+        encryption_tree_size: encryption_tree.len() as u64,
+        encryption_tree_header,
+    };
+    let fs_header = make_fs_header(NcaEncryptionType::AesCtrEx, integrity_info, patch_info);
+
+    let mut patch_section = patch_data;
+    patch_section.extend_from_slice(&relocation_tree);
+    patch_section.extend_from_slice(&encryption_tree);
+
+    make_nca(content_type, &fs_header, patch_section)
+}
+
+/// Builds a bucket tree (as found in the patch sections) with a single entry set, returning its header and the table
+pub fn make_bucket_tree<E: for<'a> BinWrite<Args<'a> = ()>>(
+    entries: &[E],
+    end_offset: u64,
+) -> (HexData<0x10>, Vec<u8>) {
+    const NODE_SIZE: usize = 0x4000;
+
+    let mut header = [0; 0x10];
+    header[..4].copy_from_slice(b"BKTR");
+    header[4..8].copy_from_slice(&1u32.to_le_bytes());
+    header[8..12].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+
+    let mut table = vec![0; NODE_SIZE * 2];
+    // the offset node: index, entry set count, end offset, then the start offset of each entry set (0)
+    table[4..8].copy_from_slice(&1u32.to_le_bytes());
+    table[8..16].copy_from_slice(&end_offset.to_le_bytes());
+    // the entry set: index, entry count, end offset, then the entries
+    table[NODE_SIZE + 4..NODE_SIZE + 8].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+    table[NODE_SIZE + 8..NODE_SIZE + 16].copy_from_slice(&end_offset.to_le_bytes());
+    let mut table = Cursor::new(table);
+    table.set_position(NODE_SIZE as u64 + 0x10);
+    for entry in entries {
+        entry.write_le(&mut table).unwrap();
+    }
+
+    (HexData(header), table.into_inner())
+}
+
+/// Puts the single section (at [`SECTION_OFFSET`]) and the headers together
+fn make_nca(
+    content_type: NcaContentType,
+    fs_header: &NcaFsHeader,
+    mut section: Vec<u8>,
+) -> VecStorage {
+    section.resize(align_up(section.len() as u64, 0x200) as usize, 0);
+
+    let mut fs_header_data = Cursor::new(Vec::new());
+    fs_header.write(&mut fs_header_data).unwrap();
+    let fs_header_data = fs_header_data.into_inner();
+    assert_eq!(fs_header_data.len(), HEADER_SECTOR_SIZE);
+
+    // some of the header fields can't be constructed outside their modules, so start from a parsed blank header
+    let mut blank_header = [0; NCA_HEADER_SIZE];
+    blank_header[0x200..0x204].copy_from_slice(b"NCA3");
+    let mut header = NcaHeader::read(&mut Cursor::new(&blank_header[..])).unwrap();
+
+    let nca_size = SECTION_OFFSET + section.len() as u64;
+    header.content_type = content_type;
+    header.nca_size = nca_size;
+    header.section_table[0] = SectionTableEntry {
+        start: SECTION_OFFSET.into(),
+        end: nca_size.into(),
+        is_enabled: true,
+    };
+    header.fs_header_hashes[0] = Sha256Hash(HexData(sha256(&fs_header_data)));
+
+    let mut headers = Cursor::new(Vec::new());
+    header.write(&mut headers).unwrap();
+    let mut headers = headers.into_inner();
+    headers.extend_from_slice(&fs_header_data);
+    headers.resize(ALL_HEADERS_SIZE, 0);
+
+    let mut nca = headers;
+    nca.resize(SECTION_OFFSET as usize, 0);
+    nca.extend_from_slice(&section);
+
+    VecStorage::new(nca)
+}
@@ -1,5 +1,5 @@
 use crate::formats::nca::verification_storage::{
-    IntegrityCheckLevel, IntegrityStorageType, DIGEST_SIZE,
+    BlockStatusCache, IntegrityCheckLevel, IntegrityStorageType, DIGEST_SIZE,
 };
 use crate::storage::{
     ReadableBlockStorage, ReadableBlockStorageExt, ReadableStorage, StorageError,
@@ -13,9 +13,54 @@ use std::sync::Mutex;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum BlockStatus {
-    Unchecked,
-    Invalid,
-    Valid,
+    Unchecked = 0,
+    Invalid = 1,
+    Valid = 2,
+}
+
+/// Block statuses stored according to the [`BlockStatusCache`]
+#[derive(Debug)]
+enum BlockStatuses {
+    Full(Vec<BlockStatus>),
+    /// Four 2-bit statuses per byte
+    Compact(Vec<u8>),
+    None,
+}
+
+impl BlockStatuses {
+    fn new(cache: BlockStatusCache, block_count: usize) -> Self {
+        match cache {
+            BlockStatusCache::Full => Self::Full(vec![BlockStatus::Unchecked; block_count]),
+            BlockStatusCache::Compact => {
+                Self::Compact(vec![0; Integer::div_ceil(&block_count, &4)])
+            }
+            BlockStatusCache::None => Self::None,
+        }
+    }
+
+    fn get(&self, index: usize) -> BlockStatus {
+        match self {
+            Self::Full(statuses) => statuses[index],
+            Self::Compact(statuses) => match (statuses[index / 4] >> (index % 4 * 2)) & 0b11 {
+                0 => BlockStatus::Unchecked,
+                1 => BlockStatus::Invalid,
+                _ => BlockStatus::Valid,
+            },
+            Self::None => BlockStatus::Unchecked,
+        }
+    }
+
+    fn set(&mut self, index: usize, status: BlockStatus) {
+        match self {
+            Self::Full(statuses) => statuses[index] = status,
+            Self::Compact(statuses) => {
+                let shift = index % 4 * 2;
+                let byte = &mut statuses[index / 4];
+                *byte = (*byte & !(0b11 << shift)) | ((status as u8) << shift);
+            }
+            Self::None => {}
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -24,7 +69,7 @@ pub struct IntegrityVerificationLevelStorage<S: ReadableBlockStorage, H: Readabl
     hash_storage: H,
     level: IntegrityCheckLevel,
     ty: IntegrityStorageType,
-    block_statuses: Mutex<Vec<BlockStatus>>,
+    block_statuses: Mutex<BlockStatuses>,
 }
 
 impl<S: ReadableBlockStorage, H: ReadableStorage> IntegrityVerificationLevelStorage<S, H> {
@@ -33,9 +78,11 @@ impl<S: ReadableBlockStorage, H: ReadableStorage> IntegrityVerificationLevelStor
         hash_storage: H,
         level: IntegrityCheckLevel,
         ty: IntegrityStorageType,
+        block_status_cache: BlockStatusCache,
     ) -> Self {
         let block_count = Integer::div_ceil(&storage.get_size(), &storage.block_size());
-        let block_statuses = vec![BlockStatus::Unchecked; block_count.try_into().unwrap()];
+        let block_statuses =
+            BlockStatuses::new(block_status_cache, block_count.try_into().unwrap());
 
         Self {
             storage,
@@ -123,9 +170,9 @@ impl<S: ReadableBlockStorage, H: ReadableStorage> ReadableBlockStorage
         }
 
         let mut block_statuses = self.block_statuses.lock().unwrap();
-        let block_status = &mut block_statuses[block_index as usize];
+        let mut block_status = block_statuses.get(block_index as usize);
 
-        if *block_status == BlockStatus::Unchecked {
+        if block_status == BlockStatus::Unchecked {
             let bytes_to_hash = match self.ty {
                 IntegrityStorageType::HierarchicalSha256 => {
                     // PartitionFs does not pad the last block
@@ -143,14 +190,15 @@ impl<S: ReadableBlockStorage, H: ReadableStorage> ReadableBlockStorage
             self.hash_storage
                 .read(block_index * DIGEST_SIZE as u64, &mut expected_hash)?;
 
-            *block_status = if hash.as_slice() == expected_hash {
+            block_status = if hash.as_slice() == expected_hash {
                 BlockStatus::Valid
             } else {
                 BlockStatus::Invalid
             };
+            block_statuses.set(block_index as usize, block_status);
         }
 
-        if *block_status == BlockStatus::Invalid && self.level == IntegrityCheckLevel::Full {
+        if block_status == BlockStatus::Invalid && self.level == IntegrityCheckLevel::Full {
             return Err(StorageError::IntegrityCheckFailed {});
         }
 
@@ -23,6 +23,21 @@ pub enum IntegrityCheckLevel {
     Full,
 }
 
+/// How the verification storages remember which blocks were already verified
+///
+/// With the default, the memory used is one byte per block of each hash level,
+/// which adds up for huge sections when memory is constrained.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum BlockStatusCache {
+    /// One byte per block
+    #[default]
+    Full,
+    /// Two bits per block
+    Compact,
+    /// Don't remember the statuses, verify the blocks on every read
+    None,
+}
+
 const DIGEST_SIZE: usize = 0x20;
 
 type AddLevel<S, B> = LinearAdapterStorage<
@@ -38,6 +53,8 @@ type VerificationStorage6<S> = AddLevel<S, VerificationStorage5<S>>;
 
 #[derive(Debug)]
 pub enum NcaVerificationStorage<S: ReadableStorage> {
+    /// The section has no hash tree, the data is passed through as is
+    Unverified(S),
     Level1(VerificationStorage1<S>),
     Level2(VerificationStorage2<S>),
     Level3(VerificationStorage3<S>),
@@ -67,6 +84,7 @@ impl From<IvfcIntegrityInfoLevel> for LevelInfo {
 struct VerificationParams {
     integrity_level: IntegrityCheckLevel,
     ty: IntegrityStorageType,
+    block_status_cache: BlockStatusCache,
 }
 
 fn add_level<S: ReadableStorage, B: ReadableStorage>(
@@ -86,6 +104,7 @@ fn add_level<S: ReadableStorage, B: ReadableStorage>(
             hash_storage,
             params.integrity_level,
             params.ty,
+            params.block_status_cache,
         ),
     ))
 }
@@ -148,16 +167,22 @@ make_level_storage!(
 );
 
 impl<S: ReadableStorage> NcaVerificationStorage<S> {
+    pub fn new_unverified(storage: S) -> Self {
+        Self::Unverified(storage)
+    }
+
     pub fn new_pfs_verification_storage(
         storage: S,
         master_hash: [u8; DIGEST_SIZE],
         levels: [Sha256IntegrityInfoLevel; 2],
         block_size: u32,
         integrity_level: IntegrityCheckLevel,
+        block_status_cache: BlockStatusCache,
     ) -> Result<Self, SliceStorageError> {
         let params = VerificationParams {
             integrity_level,
             ty: IntegrityStorageType::HierarchicalSha256,
+            block_status_cache,
         };
 
         Ok(Self::Level2(make_level2_storage(
@@ -185,10 +210,12 @@ impl<S: ReadableStorage> NcaVerificationStorage<S> {
         level_count: u32,
         levels: [IvfcIntegrityInfoLevel; 6],
         integrity_level: IntegrityCheckLevel,
+        block_status_cache: BlockStatusCache,
     ) -> Result<Self, SliceStorageError> {
         let params = VerificationParams {
             integrity_level,
             ty: IntegrityStorageType::Ivfc,
+            block_status_cache,
         };
 
         let levels: [LevelInfo; 6] = levels
@@ -245,6 +272,7 @@ impl<S: ReadableStorage> NcaVerificationStorage<S> {
 impl<S: ReadableStorage> ReadableStorage for NcaVerificationStorage<S> {
     fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
         match self {
+            Self::Unverified(storage) => storage.read(offset, buf),
             Self::Level1(storage) => storage.read(offset, buf),
             Self::Level2(storage) => storage.read(offset, buf),
             Self::Level3(storage) => storage.read(offset, buf),
@@ -256,6 +284,7 @@ impl<S: ReadableStorage> ReadableStorage for NcaVerificationStorage<S> {
 
     fn get_size(&self) -> u64 {
         match self {
+            Self::Unverified(storage) => storage.get_size(),
             Self::Level1(storage) => storage.get_size(),
             Self::Level2(storage) => storage.get_size(),
             Self::Level3(storage) => storage.get_size(),
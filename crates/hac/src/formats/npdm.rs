@@ -0,0 +1,535 @@
+use crate::hexstring::HexData;
+use crate::ids::{AnyId, ProgramId};
+use binrw::{BinRead, BinReaderExt, BinWrite, NullString};
+use bitflags::bitflags;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::collections::BTreeSet;
+use std::io::{Cursor, Seek, SeekFrom};
+
+/// The META header at the start of an NPDM
+#[derive(Debug, Clone, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[brw(little, magic = b"META")]
+pub struct NpdmMeta {
+    pub acid_signature_key_generation: u32,
+    #[brw(pad_before = 0x4)]
+    pub flags: u8,
+    #[brw(pad_before = 0x1)]
+    pub main_thread_priority: u8,
+    pub main_thread_core_number: u8,
+    #[brw(pad_before = 0x4)]
+    pub system_resource_size: u32,
+    pub version: u32,
+    pub main_thread_stack_size: u32,
+    #[brw(pad_size_to = 0x10)]
+    #[br(try_map = |s: NullString| String::from_utf8(s.0))]
+    #[bw(map = |s| NullString(s.clone().into_bytes()))]
+    pub name: String,
+    #[brw(pad_size_to = 0x10)]
+    #[br(try_map = |s: NullString| String::from_utf8(s.0))]
+    #[bw(map = |s| NullString(s.clone().into_bytes()))]
+    pub product_code: String,
+    #[brw(pad_before = 0x30)]
+    pub aci_offset: u32,
+    pub aci_size: u32,
+    pub acid_offset: u32,
+    pub acid_size: u32,
+}
+
+/// Access Control Info, describes the permissions actually granted to the program
+#[derive(Debug, Clone, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[brw(little, magic = b"ACI0")]
+pub struct NpdmAci {
+    #[brw(pad_before = 0xc)]
+    pub program_id: ProgramId,
+    #[brw(pad_before = 0x8)]
+    pub fac_offset: u32,
+    pub fac_size: u32,
+    pub sac_offset: u32,
+    pub sac_size: u32,
+    pub kc_offset: u32,
+    #[brw(pad_after = 0x8)]
+    pub kc_size: u32,
+}
+
+/// Access Control Info Descriptor, describes the permissions the program is allowed to have (signed by Nintendo)
+#[derive(Debug, Clone, PartialEq, Eq, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[brw(little)]
+pub struct NpdmAcid {
+    pub signature: HexData<0x100>,
+    pub public_key: HexData<0x100>,
+    #[brw(magic = b"ACID")]
+    pub size: u32,
+    #[brw(pad_after = 0x3)]
+    pub version: u8,
+    pub flags: u32,
+    pub program_id_min: ProgramId,
+    pub program_id_max: ProgramId,
+    pub fac_offset: u32,
+    pub fac_size: u32,
+    pub sac_offset: u32,
+    pub sac_size: u32,
+    pub kc_offset: u32,
+    #[brw(pad_after = 0x8)]
+    pub kc_size: u32,
+}
+
+/// Program metadata (`main.npdm` in the ExeFS)
+#[derive(Debug, Clone, PartialEq, Eq, BinRead)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[br(little)]
+pub struct Npdm {
+    pub meta: NpdmMeta,
+    #[br(seek_before = SeekFrom::Start(meta.aci_offset as u64))]
+    pub aci: NpdmAci,
+    #[br(seek_before = SeekFrom::Start(meta.acid_offset as u64))]
+    pub acid: NpdmAcid,
+    /// Raw FS access control of the ACI, see [`Npdm::capabilities`] for the decoded version
+    #[br(seek_before = SeekFrom::Start(meta.aci_offset as u64 + aci.fac_offset as u64))]
+    #[br(count = aci.fac_size)]
+    pub aci_fs_access_control: Vec<u8>,
+    /// Raw service access control of the ACI, see [`Npdm::capabilities`] for the decoded version
+    #[br(seek_before = SeekFrom::Start(meta.aci_offset as u64 + aci.sac_offset as u64))]
+    #[br(count = aci.sac_size)]
+    pub aci_service_access_control: Vec<u8>,
+    /// Raw kernel capabilities of the ACI, see [`Npdm::capabilities`] for the decoded version
+    #[br(seek_before = SeekFrom::Start(meta.aci_offset as u64 + aci.kc_offset as u64))]
+    #[br(count = aci.kc_size)]
+    pub aci_kernel_capabilities: Vec<u8>,
+}
+
+#[derive(Snafu, Debug)]
+pub enum NpdmCapabilitiesError {
+    /// Failed to parse the FS access control
+    FsAccessControlParse { source: binrw::Error },
+    /// Service access control entry at {offset} is truncated
+    ServiceAccessControlTruncated { offset: usize },
+    /// Service name at {offset} is not valid UTF-8
+    ServiceNameUtf8 {
+        offset: usize,
+        source: std::string::FromUtf8Error,
+    },
+    /// Kernel capability {index} is a memory map without the size descriptor
+    MemoryMapTruncated { index: usize },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FsAccessFlags(u64);
+
+bitflags! {
+    impl FsAccessFlags : u64
+    {
+        const APPLICATION_INFO = 1 << 0;
+        const BOOT_MODE_CONTROL = 1 << 1;
+        const CALIBRATION = 1 << 2;
+        const SYSTEM_SAVE_DATA = 1 << 3;
+        const GAME_CARD = 1 << 4;
+        const SAVE_DATA_BACKUP = 1 << 5;
+        const SAVE_DATA_MANAGEMENT = 1 << 6;
+        const BIS_ALL_RAW = 1 << 7;
+        const GAME_CARD_RAW = 1 << 8;
+        const GAME_CARD_PRIVATE = 1 << 9;
+        const SET_TIME = 1 << 10;
+        const CONTENT_MANAGER = 1 << 11;
+        const IMAGE_MANAGER = 1 << 12;
+        const CREATE_SAVE_DATA = 1 << 13;
+        const SYSTEM_SAVE_DATA_MANAGEMENT = 1 << 14;
+        const BIS_FILE_SYSTEM = 1 << 15;
+        const SYSTEM_UPDATE = 1 << 16;
+        const SAVE_DATA_META = 1 << 17;
+        const DEVICE_SAVE_DATA = 1 << 18;
+        const SETTINGS_CONTROL = 1 << 19;
+        const SYSTEM_DATA = 1 << 20;
+        const SD_CARD = 1 << 21;
+        const HOST = 1 << 22;
+        const FILL_BIS = 1 << 23;
+        const CORRUPT_SAVE_DATA = 1 << 24;
+        const SAVE_DATA_FOR_DEBUG = 1 << 25;
+        const FORMAT_SD_CARD = 1 << 26;
+        const GET_RIGHTS_ID = 1 << 27;
+        const REGISTER_EXTERNAL_KEY = 1 << 28;
+        const REGISTER_UPDATE_PARTITION = 1 << 29;
+        const SAVE_DATA_TRANSFER = 1 << 30;
+        const DEVICE_DETECTION = 1 << 31;
+        const ACCESS_FAILURE_RESOLUTION = 1 << 32;
+        const SAVE_DATA_TRANSFER_VERSION_2 = 1 << 33;
+        const REGISTER_PROGRAM_INDEX_MAP_INFO = 1 << 34;
+        const CREATE_OWN_SAVE_DATA = 1 << 35;
+        const MOVE_CACHE_STORAGE = 1 << 36;
+        const DEBUG = 1 << 62;
+        const FULL_PERMISSION = 1 << 63;
+    }
+}
+
+/// Header of the FS access control in the ACI (the ACID one has a different layout)
+#[derive(Debug, Clone, Copy, BinRead)]
+#[br(little)]
+struct FsAccessControlHeader {
+    #[br(pad_after = 0x3)]
+    _version: u8,
+    permissions: u64,
+    content_owner_info_offset: u32,
+    content_owner_info_size: u32,
+    save_data_owner_info_offset: u32,
+    save_data_owner_info_size: u32,
+}
+
+/// Save data of another program the program can access
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct SaveDataOwner {
+    pub id: AnyId,
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ThreadInfo {
+    pub lowest_priority: u8,
+    pub highest_priority: u8,
+    pub min_core_number: u8,
+    pub max_core_number: u8,
+}
+
+/// A physical memory range mapped into the program
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MemoryMap {
+    pub address: u64,
+    pub size: u64,
+    pub read_only: bool,
+    /// Whether this is an IO (device) mapping as opposed to normal memory
+    pub is_io: bool,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct KernelVersion {
+    pub major: u16,
+    pub minor: u8,
+}
+
+/// Decoded kernel capability descriptors
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct KernelCapabilities {
+    pub thread_info: Option<ThreadInfo>,
+    /// Numbers of the syscalls the program may use
+    pub syscalls: BTreeSet<u8>,
+    /// Device memory the program may map, i.e. the hardware it can access directly
+    pub memory_maps: Vec<MemoryMap>,
+    pub interrupts: Vec<u16>,
+    pub program_type: Option<u8>,
+    pub kernel_version: Option<KernelVersion>,
+    pub handle_table_size: Option<u16>,
+    pub enable_debug: bool,
+    pub force_debug: bool,
+    /// Descriptors of the types not decoded here
+    pub unknown: Vec<u32>,
+}
+
+/// What the program is permitted to do, decoded from the ACI of the NPDM
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ProgramCapabilities {
+    pub fs_permissions: FsAccessFlags,
+    /// Programs whose content the program can access
+    pub content_owner_ids: Vec<AnyId>,
+    /// Programs whose save data the program can access
+    pub save_data_owners: Vec<SaveDataOwner>,
+    /// Services the program can register
+    pub hosted_services: Vec<String>,
+    /// Services the program can connect to
+    pub accessed_services: Vec<String>,
+    pub kernel: KernelCapabilities,
+    pub main_thread_stack_size: u32,
+    /// Memory reserved for the kernel resources of the program
+    ///
+    /// The NPDM does not specify the heap size, it's up to the program to request it at runtime.
+    pub system_resource_size: u32,
+}
+
+impl Npdm {
+    /// Decodes the permissions granted to the program (the ACI)
+    pub fn capabilities(&self) -> Result<ProgramCapabilities, NpdmCapabilitiesError> {
+        let (fs_permissions, content_owner_ids, save_data_owners) =
+            parse_fs_access_control(&self.aci_fs_access_control)
+                .context(FsAccessControlParseSnafu)?;
+        let (hosted_services, accessed_services) =
+            parse_service_access_control(&self.aci_service_access_control)?;
+        let kernel = parse_kernel_capabilities(&self.aci_kernel_capabilities)?;
+
+        Ok(ProgramCapabilities {
+            fs_permissions,
+            content_owner_ids,
+            save_data_owners,
+            hosted_services,
+            accessed_services,
+            kernel,
+            main_thread_stack_size: self.meta.main_thread_stack_size,
+            system_resource_size: self.meta.system_resource_size,
+        })
+    }
+}
+
+type FsAccessControl = (FsAccessFlags, Vec<AnyId>, Vec<SaveDataOwner>);
+
+fn parse_fs_access_control(data: &[u8]) -> binrw::BinResult<FsAccessControl> {
+    let mut cur = Cursor::new(data);
+    let header = FsAccessControlHeader::read(&mut cur)?;
+    let permissions = FsAccessFlags::from_bits_retain(header.permissions);
+
+    // the owner info offsets are relative to the start of the FS access control
+    let mut content_owner_ids = Vec::new();
+    if header.content_owner_info_size != 0 {
+        cur.seek(SeekFrom::Start(header.content_owner_info_offset as u64))?;
+        let count: u32 = cur.read_le()?;
+        for _ in 0..count {
+            content_owner_ids.push(AnyId::from(cur.read_le::<u64>()?));
+        }
+    }
+
+    let mut save_data_owners = Vec::new();
+    if header.save_data_owner_info_size != 0 {
+        cur.seek(SeekFrom::Start(header.save_data_owner_info_offset as u64))?;
+        let count: u32 = cur.read_le()?;
+        let mut accessibilities = vec![0u8; count as usize];
+        for accessibility in accessibilities.iter_mut() {
+            *accessibility = cur.read_le()?;
+        }
+        // the ids are aligned to 4 bytes
+        let position = cur.position();
+        cur.seek(SeekFrom::Start((position + 3) & !3))?;
+        for accessibility in accessibilities {
+            save_data_owners.push(SaveDataOwner {
+                id: AnyId::from(cur.read_le::<u64>()?),
+                read: accessibility & 1 != 0,
+                write: accessibility & 2 != 0,
+            });
+        }
+    }
+
+    Ok((permissions, content_owner_ids, save_data_owners))
+}
+
+fn parse_service_access_control(
+    data: &[u8],
+) -> Result<(Vec<String>, Vec<String>), NpdmCapabilitiesError> {
+    let mut hosted = Vec::new();
+    let mut accessed = Vec::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        // the control byte: bits 0-2 are the name length minus one, bit 7 is set for the hosted services
+        let control = data[offset];
+        let len = (control & 0x7) as usize + 1;
+        let name = data
+            .get(offset + 1..offset + 1 + len)
+            .context(ServiceAccessControlTruncatedSnafu { offset })?;
+        let name = String::from_utf8(name.to_vec()).context(ServiceNameUtf8Snafu { offset })?;
+
+        if control & 0x80 != 0 {
+            hosted.push(name);
+        } else {
+            accessed.push(name);
+        }
+        offset += 1 + len;
+    }
+
+    Ok((hosted, accessed))
+}
+
+fn bits(value: u32, start: u32, end: u32) -> u32 {
+    (value >> start) & ((1 << (end - start)) - 1)
+}
+
+fn parse_kernel_capabilities(data: &[u8]) -> Result<KernelCapabilities, NpdmCapabilitiesError> {
+    let descriptors = data
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+        .collect::<Vec<_>>();
+
+    let mut result = KernelCapabilities::default();
+    let mut index = 0;
+    while index < descriptors.len() {
+        let descriptor = descriptors[index];
+        // the type is encoded by the number of the trailing ones
+        match descriptor.trailing_ones() {
+            3 => {
+                result.thread_info = Some(ThreadInfo {
+                    lowest_priority: bits(descriptor, 4, 10) as u8,
+                    highest_priority: bits(descriptor, 10, 16) as u8,
+                    min_core_number: bits(descriptor, 16, 24) as u8,
+                    max_core_number: bits(descriptor, 24, 32) as u8,
+                })
+            }
+            4 => {
+                let mask = bits(descriptor, 5, 29);
+                let base = bits(descriptor, 29, 32) * 24;
+                result.syscalls.extend(
+                    (0..24)
+                        .filter(|bit| mask & (1 << bit) != 0)
+                        .map(|bit| (base + bit) as u8),
+                );
+            }
+            6 => {
+                // the range takes two descriptors: the address and the size
+                index += 1;
+                let size = *descriptors
+                    .get(index)
+                    .context(MemoryMapTruncatedSnafu { index: index - 1 })?;
+                result.memory_maps.push(MemoryMap {
+                    address: (bits(descriptor, 7, 31) as u64) << 12,
+                    size: (bits(size, 7, 27) as u64) << 12,
+                    read_only: bits(descriptor, 31, 32) != 0,
+                    is_io: bits(size, 31, 32) == 0,
+                });
+            }
+            7 => result.memory_maps.push(MemoryMap {
+                address: (bits(descriptor, 8, 32) as u64) << 12,
+                size: 0x1000,
+                read_only: false,
+                is_io: true,
+            }),
+            11 => {
+                // 0x3ff marks an unused slot
+                result.interrupts.extend(
+                    [bits(descriptor, 12, 22), bits(descriptor, 22, 32)]
+                        .into_iter()
+                        .filter(|&irq| irq != 0x3ff)
+                        .map(|irq| irq as u16),
+                );
+            }
+            13 => result.program_type = Some(bits(descriptor, 14, 17) as u8),
+            14 => {
+                result.kernel_version = Some(KernelVersion {
+                    major: bits(descriptor, 19, 32) as u16,
+                    minor: bits(descriptor, 15, 19) as u8,
+                })
+            }
+            15 => result.handle_table_size = Some(bits(descriptor, 16, 26) as u16),
+            16 => {
+                result.enable_debug = bits(descriptor, 17, 18) != 0;
+                result.force_debug = bits(descriptor, 18, 19) != 0;
+            }
+            // padding
+            32 => {}
+            _ => result.unknown.push(descriptor),
+        }
+        index += 1;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FsAccessFlags, MemoryMap, Npdm};
+    use crate::ids::AnyId;
+    use binrw::BinRead;
+    use std::io::Cursor;
+
+    const ACI_OFFSET: usize = 0x80;
+    const ACID_OFFSET: usize = 0x200;
+
+    fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn capabilities() {
+        let mut fac = vec![0; 0x1c];
+        fac[4..12].copy_from_slice(
+            &(FsAccessFlags::SD_CARD | FsAccessFlags::HOST)
+                .bits()
+                .to_le_bytes(),
+        );
+        // one save data owner, readable only
+        put_u32(&mut fac, 0x14, 0x1c);
+        put_u32(&mut fac, 0x18, 0x10);
+        fac.extend_from_slice(&1u32.to_le_bytes());
+        fac.extend_from_slice(&[1, 0, 0, 0]);
+        fac.extend_from_slice(&0x0100000000001000u64.to_le_bytes());
+
+        // the length in the control byte is minus one, bit 7 marks the hosted services
+        let sac = [b"\x82fsp".as_slice(), b"\x02sm:", b"\x02hid"].concat();
+
+        let kc = [
+            // thread info: priorities 59..28, cores 0..2
+            (2 << 24) | (28 << 10) | (59 << 4) | 0b0111,
+            // syscalls 1 and 6
+            (1 << (5 + 6)) | (1 << (5 + 1)) | 0b1111,
+            // normal read-only memory map of 2 pages at 0x70000000
+            (1 << 31) | ((0x70000000 >> 12) << 7) | 0b111111,
+            (1 << 31) | (2 << 7) | 0b111111,
+            // handle table size 0x200
+            (0x200 << 16) | 0x7fff,
+            0xffffffff,
+        ]
+        .iter()
+        .flat_map(|d: &u32| d.to_le_bytes())
+        .collect::<Vec<_>>();
+
+        let mut npdm = vec![0; ACID_OFFSET + 0x240];
+        npdm[..4].copy_from_slice(b"META");
+        put_u32(&mut npdm, 0x1c, 0x100000);
+        put_u32(&mut npdm, 0x70, ACI_OFFSET as u32);
+        put_u32(&mut npdm, 0x78, ACID_OFFSET as u32);
+        put_u32(&mut npdm, 0x7c, 0x240);
+
+        npdm[ACI_OFFSET..ACI_OFFSET + 4].copy_from_slice(b"ACI0");
+        let mut offset = 0x40;
+        for (i, section) in [&fac, &sac, &kc].into_iter().enumerate() {
+            put_u32(&mut npdm, ACI_OFFSET + 0x20 + i * 8, offset as u32);
+            put_u32(&mut npdm, ACI_OFFSET + 0x24 + i * 8, section.len() as u32);
+            let start = ACI_OFFSET + offset;
+            npdm[start..start + section.len()].copy_from_slice(section);
+            offset += (section.len() + 3) & !3;
+        }
+        assert!(ACI_OFFSET + offset <= ACID_OFFSET);
+        npdm[ACID_OFFSET + 0x200..ACID_OFFSET + 0x204].copy_from_slice(b"ACID");
+
+        let npdm = Npdm::read(&mut Cursor::new(npdm)).unwrap();
+        let caps = npdm.capabilities().unwrap();
+
+        assert_eq!(caps.main_thread_stack_size, 0x100000);
+        assert_eq!(
+            caps.fs_permissions,
+            FsAccessFlags::SD_CARD | FsAccessFlags::HOST
+        );
+        assert!(caps.content_owner_ids.is_empty());
+        assert_eq!(caps.save_data_owners.len(), 1);
+        assert_eq!(caps.save_data_owners[0].id, AnyId::from(0x0100000000001000));
+        assert!(caps.save_data_owners[0].read && !caps.save_data_owners[0].write);
+        assert_eq!(caps.hosted_services, vec!["fsp"]);
+        assert_eq!(caps.accessed_services, vec!["sm:", "hid"]);
+
+        let thread_info = caps.kernel.thread_info.unwrap();
+        assert_eq!(
+            (thread_info.lowest_priority, thread_info.highest_priority),
+            (59, 28)
+        );
+        assert_eq!(thread_info.max_core_number, 2);
+        assert_eq!(
+            caps.kernel.syscalls.iter().copied().collect::<Vec<_>>(),
+            vec![1, 6]
+        );
+        assert_eq!(
+            caps.kernel.memory_maps,
+            vec![MemoryMap {
+                address: 0x70000000,
+                size: 0x2000,
+                read_only: true,
+                is_io: false,
+            }]
+        );
+        assert_eq!(caps.kernel.handle_table_size, Some(0x200));
+        assert!(caps.kernel.unknown.is_empty());
+    }
+}
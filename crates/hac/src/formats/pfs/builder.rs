@@ -0,0 +1,95 @@
+use crate::formats::pfs::structs::{PartitionFsEntry, PartitionFsHeader};
+use crate::storage::{ReadableStorage, Storage, StorageError};
+use binrw::BinWrite;
+use snafu::{ResultExt, Snafu};
+use std::io::Cursor;
+
+/// The header is padded to this alignment, like in the NSPs made by the official tools
+const HEADER_ALIGNMENT: usize = 0x20;
+
+#[derive(Snafu, Debug)]
+pub enum PfsBuildError {
+    /// Failed to serialize the PFS header
+    HeaderSerialize { source: binrw::Error },
+    /// Failed to read {name}
+    BuildReadFile { name: String, source: StorageError },
+    /// Failed to write the PFS
+    BuildWrite { source: StorageError },
+}
+
+/// Builds a PFS0 (e.g. an NSP) out of the given files, the data is streamed into the output without temporary copies
+#[derive(Default)]
+pub struct PartitionFsBuilder<'a> {
+    files: Vec<(String, Box<dyn ReadableStorage + 'a>)>,
+}
+
+impl<'a> PartitionFsBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, the files are stored in the order they are added
+    pub fn file(mut self, name: impl Into<String>, storage: impl ReadableStorage + 'a) -> Self {
+        self.files.push((name.into(), Box::new(storage)));
+        self
+    }
+
+    fn header(&self) -> Result<Vec<u8>, binrw::Error> {
+        let mut string_table = Vec::new();
+        let mut file_entries = Vec::new();
+        let mut offset = 0;
+        for (name, storage) in &self.files {
+            file_entries.push(PartitionFsEntry {
+                offset,
+                size: storage.get_size(),
+                string_table_offset: string_table.len() as u32,
+            });
+            string_table.extend_from_slice(name.as_bytes());
+            string_table.push(0);
+            offset += storage.get_size();
+        }
+
+        // pad the string table so that the data starts aligned
+        let header_size = 0x10 + 0x18 * file_entries.len() + string_table.len();
+        let padding = (HEADER_ALIGNMENT - header_size % HEADER_ALIGNMENT) % HEADER_ALIGNMENT;
+        string_table.resize(string_table.len() + padding, 0);
+
+        let header = PartitionFsHeader {
+            num_files: file_entries.len() as u32,
+            string_table_size: string_table.len() as u32,
+            file_entries,
+            string_table,
+        };
+        let mut result = Cursor::new(Vec::new());
+        header.write(&mut result)?;
+        Ok(result.into_inner())
+    }
+
+    /// Writes the PFS0 to `out`
+    pub fn build_into(&self, out: &impl Storage) -> Result<(), PfsBuildError> {
+        const BUFFER_SIZE: usize = 0x10000;
+
+        let header = self.header().context(HeaderSerializeSnafu)?;
+        let data_size: u64 = self.files.iter().map(|(_, s)| s.get_size()).sum();
+        out.set_size(header.len() as u64 + data_size)
+            .context(BuildWriteSnafu)?;
+        out.write(0, &header).context(BuildWriteSnafu)?;
+
+        let mut out_offset = header.len() as u64;
+        let mut buf = vec![0; BUFFER_SIZE];
+        for (name, storage) in &self.files {
+            let size = storage.get_size();
+            for offset in (0..size).step_by(BUFFER_SIZE) {
+                let buf = &mut buf[..std::cmp::min(BUFFER_SIZE as u64, size - offset) as usize];
+                storage
+                    .read(offset, buf)
+                    .context(BuildReadFileSnafu { name })?;
+                out.write(out_offset + offset, buf)
+                    .context(BuildWriteSnafu)?;
+            }
+            out_offset += size;
+        }
+
+        out.flush().context(BuildWriteSnafu)
+    }
+}
@@ -1,10 +1,17 @@
+mod builder;
 mod open_file;
 mod structs;
+#[cfg(test)]
+pub(crate) mod test_fixture;
 
 use crate::filesystem::{Entry, ReadableDirectory, ReadableFile, ReadableFileSystem};
-use crate::formats::pfs::structs::{get_string, PartitionFsHeader};
+use crate::formats::pfs::structs::{
+    get_string, AnyPartitionFsHeader, HashedPartitionFsHeader, PartitionFsHeader,
+};
+use crate::hexstring::HexData;
 use crate::storage::{
     ReadableStorage, ReadableStorageExt, SharedStorage, SliceStorage, SliceStorageError,
+    StorageError,
 };
 use binrw::BinRead;
 use indexmap::IndexMap;
@@ -12,9 +19,19 @@ use snafu::{ResultExt, Snafu};
 use std::fmt::Debug;
 use std::io::Seek;
 
+pub use builder::{PartitionFsBuilder, PfsBuildError};
+
 #[derive(Snafu, Debug)]
-pub struct PfsParseError {
-    source: binrw::Error,
+pub enum PfsParseError {
+    /// Failed to parse the PFS header
+    HeaderParsing { source: binrw::Error },
+    /// File name offset {offset} is out of bounds of the string table
+    InvalidStringOffset { offset: u32 },
+    /// File name at offset {offset} is not valid UTF-8
+    InvalidFileName {
+        offset: u32,
+        source: std::str::Utf8Error,
+    },
 }
 
 #[derive(Snafu, Debug)]
@@ -22,10 +39,42 @@ pub struct PfsOpenError {
     source: SliceStorageError,
 }
 
+#[derive(Snafu, Debug)]
+pub enum PfsVerifyError {
+    /// Failed to open {filename}
+    VerifyOpen {
+        filename: String,
+        source: PfsOpenError,
+    },
+    /// Failed to read {filename}
+    VerifyRead {
+        filename: String,
+        source: StorageError,
+    },
+    /// Hash mismatch for {filename}
+    HashMismatch { filename: String },
+}
+
+/// {filename} ends at {end}, past the end of the partition at {size}, the file is probably truncated
+#[derive(Snafu, Debug)]
+pub struct PfsTruncatedError {
+    pub filename: String,
+    pub end: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FileHash {
+    hashed_size: u32,
+    hash: HexData<0x20>,
+}
+
 #[derive(Debug, Copy, Clone)]
 struct FileInfo {
     offset: u64,
     size: u64,
+    /// Only present in HFS0
+    hash: Option<FileHash>,
 }
 
 #[derive(Debug)]
@@ -97,23 +146,43 @@ impl<S: ReadableStorage> PartitionFileSystem<S> {
     pub fn new(storage: S) -> Result<Self, PfsParseError> {
         let mut io = storage.buf_read();
 
-        let PartitionFsHeader {
-            file_entries,
-            string_table,
-            ..
-        } = PartitionFsHeader::read(&mut io).context(PfsParseSnafu)?;
-
-        let files = file_entries
-            .into_iter()
-            .map(|e| {
-                let name = get_string(&string_table, e.string_table_offset);
-                let file = FileInfo {
-                    offset: e.offset,
-                    size: e.size,
-                };
-                (name, file)
-            })
-            .collect();
+        let files = match AnyPartitionFsHeader::read(&mut io).context(HeaderParsingSnafu)? {
+            AnyPartitionFsHeader::Pfs0(PartitionFsHeader {
+                file_entries,
+                string_table,
+                ..
+            }) => file_entries
+                .into_iter()
+                .map(|e| {
+                    let name = get_string(&string_table, e.string_table_offset)?;
+                    let file = FileInfo {
+                        offset: e.offset,
+                        size: e.size,
+                        hash: None,
+                    };
+                    Ok((name, file))
+                })
+                .collect::<Result<_, _>>()?,
+            AnyPartitionFsHeader::Hfs0(HashedPartitionFsHeader {
+                file_entries,
+                string_table,
+                ..
+            }) => file_entries
+                .into_iter()
+                .map(|e| {
+                    let name = get_string(&string_table, e.string_table_offset)?;
+                    let file = FileInfo {
+                        offset: e.offset,
+                        size: e.size,
+                        hash: Some(FileHash {
+                            hashed_size: e.hashed_size,
+                            hash: e.hash,
+                        }),
+                    };
+                    Ok((name, file))
+                })
+                .collect::<Result<_, _>>()?,
+        };
 
         let header_size = io.stream_position().unwrap();
 
@@ -126,6 +195,70 @@ impl<S: ReadableStorage> PartitionFileSystem<S> {
     }
 }
 
+impl<S: ReadableStorage> PartitionFileSystem<S> {
+    /// Whether the partition is an HFS0 (and so has the file hashes)
+    pub fn is_hashed(&self) -> bool {
+        self.files.values().any(|f| f.hash.is_some())
+    }
+
+    /// Checks the hashes stored in the HFS0 entries against the file data.
+    ///
+    /// Files without a hash (all of them for PFS0) are skipped.
+    pub fn verify_hashes(&self) -> Result<(), PfsVerifyError> {
+        for (filename, info) in &self.files {
+            let Some(hash) = info.hash else {
+                continue;
+            };
+
+            let storage = self
+                .open_file_info(info)
+                .context(VerifyOpenSnafu { filename })?;
+            let actual_hash = storage
+                .slice(0, std::cmp::min(hash.hashed_size as u64, info.size))
+                .map_err(|source| PfsOpenError { source })
+                .context(VerifyOpenSnafu { filename })?
+                .compute_sha256()
+                .context(VerifyReadSnafu { filename })?;
+
+            if actual_hash != hash.hash.0 {
+                return HashMismatchSnafu { filename }.fail();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that all the files fit within the storage, to detect truncated files before reading them
+    pub fn validate(&self) -> Result<(), PfsTruncatedError> {
+        let size = self.storage.get_size();
+        for (filename, info) in &self.files {
+            // the offsets come from the file, a crafted entry can make them overflow
+            let end = self
+                .header_size
+                .checked_add(info.offset)
+                .and_then(|end| end.checked_add(info.size))
+                .unwrap_or(u64::MAX);
+            if end > size {
+                return PfsTruncatedSnafu {
+                    filename,
+                    end,
+                    size,
+                }
+                .fail();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_file_info(&self, info: &FileInfo) -> Result<FileStorage<S>, PfsOpenError> {
+        let storage = self.storage.clone();
+        // an overflowing offset is out of bounds anyway, let the slice report it
+        let offset = info.offset.saturating_add(self.header_size);
+        storage.slice(offset, info.size).context(PfsOpenSnafu)
+    }
+}
+
 impl<S: ReadableStorage> ReadableFileSystem for PartitionFileSystem<S> {
     type File<'a> = File<'a, S> where Self: 'a;
     type Directory<'a> = Directory<'a, S> where Self: 'a;
@@ -186,9 +319,171 @@ impl<'a, S: ReadableStorage> ReadableFile for File<'a, S> {
     }
 
     fn storage(&self) -> Result<Self::Storage, Self::Error> {
-        let storage = self.fs.storage.clone();
-        let offset = self.info.offset + self.fs.header_size;
-        let size = self.info.size;
-        storage.slice(offset, size).context(PfsOpenSnafu)
+        self.fs.open_file_info(&self.info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::structs::{get_string, HashedPartitionFsHeader};
+    use super::test_fixture::make_hfs0;
+    use super::{
+        PartitionFileSystem, PartitionFsBuilder, PfsParseError, PfsTruncatedError, PfsVerifyError,
+    };
+    use crate::filesystem::{
+        EntryMetadata, ReadableDirectoryExt, ReadableFile, ReadableFileSystem,
+    };
+    use crate::storage::{ReadableStorageExt, VecStorage};
+    use binrw::BinRead;
+    use std::io::Cursor;
+
+    #[test]
+    fn hfs0() {
+        let files: &[(&str, &[u8])] = &[("a.nca", b"hello"), ("b.nca", b"world!")];
+
+        let fs = PartitionFileSystem::new(VecStorage::new(make_hfs0(files, false))).unwrap();
+        assert!(fs.is_hashed());
+        fs.verify_hashes().unwrap();
+
+        let file = fs.open_file("/b.nca").unwrap();
+        assert_eq!(file.storage().unwrap().read_all().unwrap(), b"world!");
+
+        let streamed = fs
+            .stream_files()
+            .map(|(path, storage)| (path, storage.unwrap().read_all().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            streamed,
+            [
+                ("/a.nca".to_string(), b"hello".to_vec()),
+                ("/b.nca".to_string(), b"world!".to_vec())
+            ]
+        );
+
+        let fs = PartitionFileSystem::new(VecStorage::new(make_hfs0(files, true))).unwrap();
+        assert!(matches!(
+            fs.verify_hashes(),
+            Err(PfsVerifyError::HashMismatch { filename }) if filename == "a.nca"
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let files: &[(&str, &[u8])] = &[("a.nca", b"hello"), ("b.nca", b"world!")];
+        let mut data = make_hfs0(files, false);
+
+        PartitionFileSystem::new(VecStorage::new(data.clone()))
+            .unwrap()
+            .validate()
+            .unwrap();
+
+        data.truncate(data.len() - 1);
+        let fs = PartitionFileSystem::new(VecStorage::new(data)).unwrap();
+        assert!(matches!(
+            fs.validate(),
+            Err(PfsTruncatedError { filename, .. }) if filename == "b.nca"
+        ));
+    }
+
+    #[test]
+    fn overflowing_entry() {
+        let files: &[(&str, &[u8])] = &[("a.nca", b"hello")];
+        let mut data = make_hfs0(files, false);
+        // the size of the first entry, following the 0x10 bytes header and the offset
+        data[0x18..0x20].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let fs = PartitionFileSystem::new(VecStorage::new(data)).unwrap();
+        assert!(matches!(
+            fs.validate(),
+            Err(PfsTruncatedError { end: u64::MAX, .. })
+        ));
+    }
+
+    #[test]
+    fn explicit_endianness() {
+        let files: &[(&str, &[u8])] = &[("a.nca", b"hello")];
+        let data = make_hfs0(files, false);
+
+        // the headers are little-endian no matter what endianness the reader asks for (or the host has)
+        let le = HashedPartitionFsHeader::read_le(&mut Cursor::new(&data)).unwrap();
+        let be = HashedPartitionFsHeader::read_be(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(le.num_files, 1);
+        assert_eq!(be.num_files, 1);
+        assert_eq!(be.file_entries[0].size, 5);
+    }
+
+    #[test]
+    fn string_table() {
+        let string_table = b"a.nca\0b\xffc\0";
+
+        assert_eq!(get_string(string_table, 0).unwrap(), "a.nca");
+        assert!(matches!(
+            get_string(string_table, 6),
+            Err(PfsParseError::InvalidFileName { offset: 6, .. })
+        ));
+        assert!(matches!(
+            get_string(string_table, 100),
+            Err(PfsParseError::InvalidStringOffset { offset: 100 })
+        ));
+    }
+
+    #[test]
+    fn builder() {
+        let out = VecStorage::new(Vec::new());
+        PartitionFsBuilder::new()
+            .file("a.nca", VecStorage::new(b"hello".to_vec()))
+            .file("b.tik", VecStorage::new(vec![0x42; 0x20000]))
+            .build_into(&out)
+            .unwrap();
+
+        let fs = PartitionFileSystem::new(out).unwrap();
+        fs.validate().unwrap();
+        let a = fs.open_file("/a.nca").unwrap().storage().unwrap();
+        assert_eq!(a.read_all().unwrap(), b"hello");
+        let b = fs.open_file("/b.tik").unwrap().storage().unwrap();
+        assert_eq!(b.read_all().unwrap(), vec![0x42; 0x20000]);
+    }
+
+    #[test]
+    fn metadata() {
+        let out = VecStorage::new(Vec::new());
+        PartitionFsBuilder::new()
+            .file("control.nacp", VecStorage::new(vec![0; 0x4000]))
+            .build_into(&out)
+            .unwrap();
+        let fs = PartitionFileSystem::new(out).unwrap();
+
+        assert_eq!(
+            fs.metadata("/control.nacp"),
+            Some(EntryMetadata {
+                is_dir: false,
+                size: 0x4000
+            })
+        );
+        assert_eq!(
+            fs.metadata("/"),
+            Some(EntryMetadata {
+                is_dir: true,
+                size: 0
+            })
+        );
+        assert!(!fs.exists("/main.npdm"));
+    }
+
+    #[test]
+    fn extract_parallel() {
+        let out = VecStorage::new(Vec::new());
+        PartitionFsBuilder::new()
+            .file("a.bin", VecStorage::new(vec![1; 0x100]))
+            .file("b.bin", VecStorage::new(vec![2; 0x300]))
+            .build_into(&out)
+            .unwrap();
+        let fs = PartitionFileSystem::new(out).unwrap();
+
+        let dest = std::env::temp_dir().join(format!("hac-extract-{}", std::process::id()));
+        fs.root().extract_to_parallel(&dest).unwrap();
+        assert_eq!(std::fs::read(dest.join("a.bin")).unwrap(), vec![1; 0x100]);
+        assert_eq!(std::fs::read(dest.join("b.bin")).unwrap(), vec![2; 0x300]);
+        std::fs::remove_dir_all(&dest).unwrap();
     }
 }
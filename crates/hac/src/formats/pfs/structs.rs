@@ -1,6 +1,9 @@
-// TODO: HFS0 is __just__ like PFS0, we could probably support it later
+// HFS0 is __just__ like PFS0, but with hashes of the file contents in the entries
 
+use crate::formats::pfs::{InvalidFileNameSnafu, InvalidStringOffsetSnafu, PfsParseError};
+use crate::hexstring::HexData;
 use binrw::{BinRead, BinWrite};
+use snafu::{OptionExt, ResultExt};
 
 #[derive(Debug, Clone, PartialEq, Eq, BinRead, BinWrite)]
 pub struct PartitionFsEntry {
@@ -24,14 +27,45 @@ pub struct PartitionFsHeader {
     pub string_table: Vec<u8>,
 }
 
-pub fn get_string(string_table: &[u8], offset: u32) -> String {
-    let start = offset as usize;
-    let end = string_table[start..]
-        .iter()
-        .position(|&c| c == 0)
-        .unwrap_or(string_table.len());
+#[derive(Debug, Clone, PartialEq, Eq, BinRead, BinWrite)]
+pub struct HashedPartitionFsEntry {
+    pub offset: u64,
+    pub size: u64,
+    pub string_table_offset: u32,
+    /// Size of the region at the start of the file covered by the hash
+    #[brw(pad_after = 8)] // some reserved field
+    pub hashed_size: u32,
+    pub hash: HexData<0x20>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BinRead, BinWrite)]
+#[brw(little, magic = b"HFS0")]
+pub struct HashedPartitionFsHeader {
+    pub num_files: u32,
+    pub string_table_size: u32,
+
+    #[brw(pad_before = 4)] // some reserved field
+    #[br(count = num_files)]
+    pub file_entries: Vec<HashedPartitionFsEntry>,
+
+    #[br(count = string_table_size)]
+    pub string_table: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BinRead)]
+#[br(little)]
+pub enum AnyPartitionFsHeader {
+    Pfs0(PartitionFsHeader),
+    Hfs0(HashedPartitionFsHeader),
+}
+
+pub fn get_string(string_table: &[u8], offset: u32) -> Result<String, PfsParseError> {
+    let string = string_table
+        .get(offset as usize..)
+        .context(InvalidStringOffsetSnafu { offset })?;
+    let end = string.iter().position(|&c| c == 0).unwrap_or(string.len());
 
-    std::str::from_utf8(&string_table[start..start + end])
-        .expect("invalid utf8 in string table")
-        .to_string()
+    std::str::from_utf8(&string[..end])
+        .map(|s| s.to_string())
+        .context(InvalidFileNameSnafu { offset })
 }
@@ -0,0 +1,44 @@
+//! Builders of synthetic partition filesystems
+
+use crate::formats::pfs::structs::{HashedPartitionFsEntry, HashedPartitionFsHeader};
+use crate::hexstring::HexData;
+use binrw::BinWrite;
+use digest::Digest;
+
+/// A HFS0 containing `files`, with the hashes of all of them spoiled if `corrupt` is set
+pub fn make_hfs0(files: &[(&str, &[u8])], corrupt: bool) -> Vec<u8> {
+    let mut string_table = Vec::new();
+    let mut file_entries = Vec::new();
+    let mut offset = 0;
+    for (name, data) in files {
+        let mut hash: [u8; 0x20] = sha2::Sha256::digest(data).into();
+        if corrupt {
+            hash[0] ^= 1;
+        }
+        file_entries.push(HashedPartitionFsEntry {
+            offset,
+            size: data.len() as u64,
+            string_table_offset: string_table.len() as u32,
+            hashed_size: data.len() as u32,
+            hash: HexData(hash),
+        });
+        string_table.extend_from_slice(name.as_bytes());
+        string_table.push(0);
+        offset += data.len() as u64;
+    }
+
+    let header = HashedPartitionFsHeader {
+        num_files: files.len() as u32,
+        string_table_size: string_table.len() as u32,
+        file_entries,
+        string_table,
+    };
+
+    let mut cur = std::io::Cursor::new(Vec::new());
+    header.write(&mut cur).unwrap();
+    let mut result = cur.into_inner();
+    for (_, data) in files {
+        result.extend_from_slice(data);
+    }
+    result
+}
@@ -1,6 +1,11 @@
 use crate::formats::romfs::structs::{RomFsEntry, RomId};
+use crate::formats::romfs::{
+    EmptyHashTableSnafu, EntryCycleSnafu, InvalidEntrySnafu, RomFsParseError,
+};
 use crate::storage::{ReadableStorage, ReadableStorageExt};
 use binrw::{BinRead, BinWrite};
+use snafu::{ensure, OptionExt};
+use std::collections::HashSet;
 use std::marker::PhantomData;
 
 #[derive(BinRead)]
@@ -68,16 +73,47 @@ impl<T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + 'stat
     pub fn get_entry_by_id(&self, id: RomId) -> (&str, RomFsEntry<T>) {
         assert!(id.is_some());
 
+        self.try_get_entry_by_id(id)
+            .expect("Invalid entry in RomFS dictionary")
+    }
+
+    /// Same as [`RomFsDictionary::get_entry_by_id`], but returns `None` instead of panicking on out of bounds or malformed entries
+    pub fn try_get_entry_by_id(&self, id: RomId) -> Option<(&str, RomFsEntry<T>)> {
+        let offset = u64::try_from(id.0).ok()?;
+
         let mut cur = std::io::Cursor::new(&self.entries);
-        cur.set_position(id.0 as u64);
+        cur.set_position(offset);
+
+        let entry = RomFsEntry::read(&mut cur).ok()?;
 
-        let entry = RomFsEntry::read(&mut cur).unwrap();
+        let key = self
+            .entries
+            .get(cur.position() as usize..)?
+            .get(..entry.key_length as usize)?;
 
-        let key = &self.entries[cur.position() as usize..][..entry.key_length as usize];
+        let key = std::str::from_utf8(key).ok()?;
 
-        let key = std::str::from_utf8(key).expect("Invalid UTF-8 in RomFS dictionary");
+        Some((key, entry))
+    }
+
+    /// Checks that all the hash bucket chains point to valid entries and are finite, so that lookups by key can't panic or hang
+    pub fn validate_buckets(&self, table: &'static str) -> Result<(), RomFsParseError> {
+        ensure!(!self.buckets.is_empty(), EmptyHashTableSnafu { table });
+
+        let mut visited = HashSet::new();
+        for &bucket in &self.buckets {
+            let mut id = bucket;
+            while id.is_some() {
+                // each entry belongs to exactly one chain, so seeing it twice means a loop
+                ensure!(visited.insert(id), EntryCycleSnafu { table, id: id.0 });
+                let (_, entry) = self
+                    .try_get_entry_by_id(id)
+                    .context(InvalidEntrySnafu { table, id: id.0 })?;
+                id = entry.next;
+            }
+        }
 
-        (key, entry)
+        Ok(())
     }
 }
 
@@ -92,7 +128,7 @@ impl<'a> RomEntryKey<'a> {
         let mut hash = 123456789 ^ self.parent.0 as u32;
 
         for c in self.name.bytes() {
-            hash = (c as u32) ^ ((hash << 27) | (hash >> 5));
+            hash = (c as u32) ^ hash.rotate_right(5);
         }
 
         hash
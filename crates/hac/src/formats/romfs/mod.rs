@@ -1,8 +1,8 @@
-use crate::filesystem::{Entry, ReadableDirectory, ReadableFile, ReadableFileSystem};
-use crate::formats::romfs::dictionary::RomFsDictionary;
-use crate::formats::romfs::structs::{
-    DirectoryRomEntry, FileRomEntry, FindPosition, RomFileInfo, RomFsHeader,
+use crate::filesystem::{
+    Entry, EntryMetadata, ReadableDirectory, ReadableFile, ReadableFileSystem,
 };
+use crate::formats::romfs::dictionary::RomFsDictionary;
+use crate::formats::romfs::structs::{DirectoryRomEntry, FileRomEntry, FindPosition, RomFsHeader};
 use crate::formats::romfs::tables::HierarchicalRomTables;
 use crate::storage::{
     ReadableStorage, ReadableStorageExt, SharedStorage, SliceStorage, SliceStorageError,
@@ -15,10 +15,32 @@ mod dictionary;
 mod structs;
 mod tables;
 
+pub use structs::RomFileInfo;
+
 #[derive(Snafu, Debug)]
 pub enum RomFsParseError {
-    Parse { source: binrw::Error },
-    Slice { source: SliceStorageError },
+    Parse {
+        source: binrw::Error,
+    },
+    Slice {
+        source: SliceStorageError,
+    },
+    /// The RomFS {table} hash table is empty
+    EmptyHashTable {
+        table: &'static str,
+    },
+    /// Invalid entry at offset {id} of the RomFS {table} table
+    InvalidEntry {
+        table: &'static str,
+        id: i32,
+    },
+    /// Loop detected at entry {id} of the RomFS {table} table
+    EntryCycle {
+        table: &'static str,
+        id: i32,
+    },
+    /// The RomFS does not have a root directory
+    MissingRootDirectory {},
 }
 
 #[derive(Snafu, Debug)]
@@ -72,7 +94,6 @@ impl<'a, S: ReadableStorage> Debug for File<'a, S> {
     }
 }
 
-// TODO specialized iterators for "only files" and "only directories" cases
 #[derive(Debug)]
 pub struct DirectoryIter<'a, S: ReadableStorage> {
     fs: &'a RomFileSystem<S>,
@@ -102,6 +123,47 @@ impl<'a, S: ReadableStorage> Iterator for DirectoryIter<'a, S> {
     }
 }
 
+/// Iterator over full paths of all the files in a RomFS, see [`RomFileSystem::iter_paths`]
+#[derive(Debug)]
+pub struct PathIter<'a> {
+    table: &'a HierarchicalRomTables,
+    /// Directories being walked, with their full paths
+    stack: Vec<(String, FindPosition)>,
+}
+
+impl<'a> Iterator for PathIter<'a> {
+    type Item = (String, RomFileInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, position) = self.stack.last_mut()?;
+            if let Some((name, info)) = self.table.next_file(position) {
+                return Some((format!("{}/{}", path, name), info));
+            }
+            if let Some((name, position)) = self.table.next_directory(position) {
+                let path = format!("{}/{}", path, name);
+                self.stack.push((path, position));
+                continue;
+            }
+            self.stack.pop();
+        }
+    }
+}
+
+impl<S: ReadableStorage> RomFileSystem<S> {
+    /// Lists full paths (like `/a/b/c`) of all the files, walking the RomFS tables directly.
+    ///
+    /// Faster than [`ReadableDirectoryExt::entries_recursive`](crate::filesystem::ReadableDirectoryExt::entries_recursive) when only the file list is needed.
+    pub fn iter_paths(&self) -> PathIter<'_> {
+        let (_, position) = self.table.get_directory("/").unwrap();
+
+        PathIter {
+            table: &self.table,
+            stack: vec![(String::new(), position)],
+        }
+    }
+}
+
 impl<S: ReadableStorage> RomFileSystem<S> {
     pub fn new(storage: S) -> Result<Self, RomFsParseError> {
         let storage = storage.shared();
@@ -133,6 +195,7 @@ impl<S: ReadableStorage> RomFileSystem<S> {
             .context(ParseSnafu)?;
 
         let table = HierarchicalRomTables::new(files, directories);
+        table.validate()?;
 
         Ok(Self {
             storage,
@@ -177,6 +240,19 @@ impl<S: ReadableStorage> ReadableFileSystem for RomFileSystem<S> {
             info,
         })
     }
+
+    fn metadata(&self, path: &str) -> Option<EntryMetadata> {
+        if let Some((_, info)) = self.table.get_file(path) {
+            return Some(EntryMetadata {
+                is_dir: false,
+                size: info.size,
+            });
+        }
+        self.table.get_directory(path).map(|_| EntryMetadata {
+            is_dir: true,
+            size: 0,
+        })
+    }
 }
 
 impl<'a, S: ReadableStorage> ReadableDirectory for Directory<'a, S> {
@@ -193,6 +269,25 @@ impl<'a, S: ReadableStorage> ReadableDirectory for Directory<'a, S> {
             position: self.position,
         }
     }
+
+    // walk only the relevant table
+    fn entries_files(&self) -> impl Iterator<Item = Self::File> {
+        let fs = self.fs;
+        let mut position = self.position;
+        std::iter::from_fn(move || {
+            let (name, info) = fs.table.next_file(&mut position)?;
+            Some(File { fs, name, info })
+        })
+    }
+
+    fn entries_directories(&self) -> impl Iterator<Item = Self> {
+        let fs = self.fs;
+        let mut position = self.position;
+        std::iter::from_fn(move || {
+            let (name, position) = fs.table.next_directory(&mut position)?;
+            Some(Directory { fs, name, position })
+        })
+    }
 }
 
 impl<'a, S: ReadableStorage> ReadableFile for File<'a, S> {
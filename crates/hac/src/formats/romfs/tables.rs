@@ -2,6 +2,11 @@ use crate::formats::romfs::dictionary::{RomEntryKey, RomFsDictionary};
 use crate::formats::romfs::structs::{
     DirectoryRomEntry, FileRomEntry, FindPosition, RomFileInfo, RomId,
 };
+use crate::formats::romfs::{
+    EntryCycleSnafu, InvalidEntrySnafu, MissingRootDirectorySnafu, RomFsParseError,
+};
+use snafu::{ensure, OptionExt};
+use std::collections::HashSet;
 
 #[derive(Debug)]
 pub struct HierarchicalRomTables {
@@ -20,6 +25,63 @@ impl HierarchicalRomTables {
         }
     }
 
+    /// Checks the links between the entries upfront, so that walking a corrupt (or malicious) RomFS
+    /// fails here instead of panicking or looping forever later.
+    pub fn validate(&self) -> Result<(), RomFsParseError> {
+        self.directory_table.validate_buckets("directory")?;
+        self.file_table.validate_buckets("file")?;
+
+        let (_, root) = self.get_directory("/").context(MissingRootDirectorySnafu)?;
+
+        let mut visited_directories = HashSet::new();
+        let mut visited_files = HashSet::new();
+        let mut stack = vec![root];
+
+        while let Some(position) = stack.pop() {
+            let mut id = position.next_directory;
+            while id.is_some() {
+                // a directory can only have one parent, seeing it twice means a loop in the tree
+                ensure!(
+                    visited_directories.insert(id),
+                    EntryCycleSnafu {
+                        table: "directory",
+                        id: id.0
+                    }
+                );
+                let (_, entry) =
+                    self.directory_table
+                        .try_get_entry_by_id(id)
+                        .context(InvalidEntrySnafu {
+                            table: "directory",
+                            id: id.0,
+                        })?;
+                stack.push(entry.value.position);
+                id = entry.value.next_sibling;
+            }
+
+            let mut id = position.next_file;
+            while id.is_some() {
+                ensure!(
+                    visited_files.insert(id),
+                    EntryCycleSnafu {
+                        table: "file",
+                        id: id.0
+                    }
+                );
+                let (_, entry) =
+                    self.file_table
+                        .try_get_entry_by_id(id)
+                        .context(InvalidEntrySnafu {
+                            table: "file",
+                            id: id.0,
+                        })?;
+                id = entry.value.next_sibling;
+            }
+        }
+
+        Ok(())
+    }
+
     fn find_path_recursive<'a>(&self, path: &'a str) -> Option<RomEntryKey<'a>> {
         let mut path = path.split('/');
         let mut key = RomEntryKey {
@@ -77,3 +139,217 @@ impl HierarchicalRomTables {
         Some((name, entry.value.position))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HierarchicalRomTables;
+    use crate::formats::romfs::dictionary::RomFsDictionary;
+    use crate::formats::romfs::structs::{
+        DirectoryRomEntry, FileRomEntry, FindPosition, RomFileInfo, RomFsEntry, RomId,
+    };
+    use crate::formats::romfs::RomFsParseError;
+    use binrw::{BinRead, BinWrite};
+    use std::io::Cursor;
+
+    /// Id of the subdirectory, right after the root entry with an empty name
+    const SUBDIRECTORY: i32 = 0x18;
+    /// Size of a file entry with a 5 byte name
+    const FILE_SIZE: i32 = 0x28;
+
+    /// Appends an entry named `name` to `entries`, returning its id
+    fn push_entry<T>(
+        entries: &mut Vec<u8>,
+        parent: RomId,
+        value: T,
+        next: RomId,
+        name: &str,
+    ) -> RomId
+    where
+        T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()> + 'static,
+    {
+        let id = RomId(entries.len() as i32);
+        let entry = RomFsEntry {
+            parent,
+            value,
+            next,
+            key_length: name.len() as u32,
+        };
+        let mut cur = Cursor::new(Vec::new());
+        entry.write_le(&mut cur).unwrap();
+        entries.extend_from_slice(&cur.into_inner());
+        entries.extend_from_slice(name.as_bytes());
+        entries.resize(entries.len().next_multiple_of(4), 0);
+        id
+    }
+
+    fn dir(next_sibling: RomId, next_directory: RomId, next_file: RomId) -> DirectoryRomEntry {
+        DirectoryRomEntry {
+            next_sibling,
+            position: FindPosition {
+                next_directory,
+                next_file,
+            },
+        }
+    }
+
+    fn file(next_sibling: RomId) -> FileRomEntry {
+        FileRomEntry {
+            next_sibling,
+            info: RomFileInfo { offset: 0, size: 0 },
+        }
+    }
+
+    /// Tables with the root directory containing `subdirectory` and `files`
+    ///
+    /// Only the directories are in the hash table, the files are reached through the root.
+    fn make_tables(
+        subdirectory: DirectoryRomEntry,
+        files: &[FileRomEntry],
+    ) -> HierarchicalRomTables {
+        let mut directories = Vec::new();
+        // the root is looked up by its (empty) name, both directories are in the only hash bucket
+        let root = push_entry(
+            &mut directories,
+            RomId(0),
+            dir(RomId::NONE, RomId(SUBDIRECTORY), RomId(0)),
+            RomId(SUBDIRECTORY),
+            "",
+        );
+        let subdirectory = push_entry(&mut directories, root, subdirectory, RomId::NONE, "dir");
+        assert_eq!(subdirectory, RomId(SUBDIRECTORY));
+
+        let mut entries = Vec::new();
+        for (i, &entry) in files.iter().enumerate() {
+            push_entry(&mut entries, root, entry, RomId::NONE, &format!("{i}.bin"));
+        }
+
+        HierarchicalRomTables::new(
+            RomFsDictionary::new(vec![RomId::NONE], entries),
+            RomFsDictionary::new(vec![root], directories),
+        )
+    }
+
+    #[test]
+    fn valid() {
+        let tables = make_tables(
+            dir(RomId::NONE, RomId::NONE, RomId::NONE),
+            &[file(RomId(FILE_SIZE)), file(RomId::NONE)],
+        );
+        tables.validate().unwrap();
+
+        let mut position = tables.get_directory("/").unwrap().1;
+        assert_eq!(tables.next_file(&mut position).unwrap().0, "0.bin");
+        assert_eq!(tables.next_file(&mut position).unwrap().0, "1.bin");
+        assert!(tables.next_file(&mut position).is_none());
+        assert_eq!(tables.next_directory(&mut position).unwrap().0, "dir");
+    }
+
+    #[test]
+    fn bucket_chain_cycle() {
+        let mut entries = Vec::new();
+        let first = push_entry(
+            &mut entries,
+            RomId(0),
+            file(RomId::NONE),
+            RomId(FILE_SIZE),
+            "a.bin",
+        );
+        push_entry(&mut entries, RomId(0), file(RomId::NONE), first, "b.bin");
+        let dictionary = RomFsDictionary::<FileRomEntry>::new(vec![first], entries);
+
+        assert!(matches!(
+            dictionary.validate_buckets("file"),
+            Err(RomFsParseError::EntryCycle { id: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn bucket_chain_out_of_range() {
+        let mut entries = Vec::new();
+        let first = push_entry(
+            &mut entries,
+            RomId(0),
+            file(RomId::NONE),
+            RomId(0x1000),
+            "a.bin",
+        );
+        let dictionary = RomFsDictionary::<FileRomEntry>::new(vec![first], entries);
+
+        assert!(matches!(
+            dictionary.validate_buckets("file"),
+            Err(RomFsParseError::InvalidEntry { id: 0x1000, .. })
+        ));
+        assert!(matches!(
+            RomFsDictionary::<FileRomEntry>::new(Vec::new(), Vec::new()).validate_buckets("file"),
+            Err(RomFsParseError::EmptyHashTable { .. })
+        ));
+    }
+
+    #[test]
+    fn sibling_cycle() {
+        // the second file links back to the first one
+        let tables = make_tables(
+            dir(RomId::NONE, RomId::NONE, RomId::NONE),
+            &[file(RomId(FILE_SIZE)), file(RomId(0))],
+        );
+
+        assert!(matches!(
+            tables.validate(),
+            Err(RomFsParseError::EntryCycle {
+                table: "file",
+                id: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn directory_cycle() {
+        // the subdirectory contains itself
+        let tables = make_tables(
+            dir(RomId::NONE, RomId(SUBDIRECTORY), RomId::NONE),
+            &[file(RomId::NONE)],
+        );
+
+        assert!(matches!(
+            tables.validate(),
+            Err(RomFsParseError::EntryCycle {
+                table: "directory",
+                id: SUBDIRECTORY
+            })
+        ));
+    }
+
+    #[test]
+    fn sibling_out_of_range() {
+        let tables = make_tables(
+            dir(RomId::NONE, RomId::NONE, RomId::NONE),
+            &[file(RomId(0x1000))],
+        );
+
+        assert!(matches!(
+            tables.validate(),
+            Err(RomFsParseError::InvalidEntry {
+                table: "file",
+                id: 0x1000
+            })
+        ));
+    }
+
+    #[test]
+    fn dangling_entry() {
+        // the subdirectory claims to have files, but the file table is empty
+        let tables = make_tables(dir(RomId::NONE, RomId::NONE, RomId(0)), &[]);
+        assert!(matches!(
+            tables.validate(),
+            Err(RomFsParseError::InvalidEntry {
+                table: "file",
+                id: 0
+            })
+        ));
+
+        let dictionary = RomFsDictionary::<FileRomEntry>::new(vec![RomId::NONE], vec![0; 8]);
+        assert!(dictionary.try_get_entry_by_id(RomId(0)).is_none());
+        assert!(dictionary.try_get_entry_by_id(RomId(-2)).is_none());
+        assert!(dictionary.try_get_entry_by_id(RomId(0x1000)).is_none());
+    }
+}
@@ -0,0 +1,282 @@
+mod structs;
+
+pub use structs::{CartridgeSize, XciHeader};
+
+use crate::filesystem::{ReadableFile, ReadableFileSystem};
+use crate::formats::pfs::{
+    FileStorage, PartitionFileSystem, PfsOpenError, PfsParseError, PfsVerifyError,
+};
+use crate::storage::{
+    ReadableStorage, ReadableStorageExt, SharedStorage, SliceStorage, SliceStorageError,
+    StorageError,
+};
+use binrw::BinRead;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Snafu, Debug)]
+pub enum XciError {
+    /// XCI: Failed to parse the gamecard header
+    HeaderParsing { source: binrw::Error },
+    /// XCI: Root partition is out of the storage bounds
+    RootPartitionBounds { source: SliceStorageError },
+    /// XCI: Failed to parse the root partition
+    RootPartitionParsing { source: PfsParseError },
+    /// XCI: Failed to read the root partition
+    RootPartitionRead { source: StorageError },
+    /// XCI: Root partition header hash mismatch
+    RootPartitionHashMismatch {},
+    /// XCI: Failed to verify the root partition
+    RootPartitionVerify { source: PfsVerifyError },
+    /// XCI: The gamecard does not have the {partition:?} partition
+    MissingPartition { partition: XciPartitionType },
+    /// XCI: Failed to open the {partition:?} partition
+    PartitionOpen {
+        partition: XciPartitionType,
+        source: PfsOpenError,
+    },
+    /// XCI: Failed to parse the {partition:?} partition
+    PartitionParsing {
+        partition: XciPartitionType,
+        source: PfsParseError,
+    },
+    /// XCI: Failed to verify the {partition:?} partition
+    PartitionVerify {
+        partition: XciPartitionType,
+        source: PfsVerifyError,
+    },
+}
+
+/// Whether the image contains the padding after the valid data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XciDumpKind {
+    /// The image ends right after the valid data
+    Trimmed,
+    /// The image contains the (unused) space after the valid data
+    Untrimmed,
+    /// The image is smaller than the valid data, something is missing
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum XciPartitionType {
+    Update,
+    Normal,
+    Secure,
+    Logo,
+}
+
+impl XciPartitionType {
+    /// Name of the partition in the root HFS0
+    pub fn name(&self) -> &'static str {
+        match self {
+            XciPartitionType::Update => "update",
+            XciPartitionType::Normal => "normal",
+            XciPartitionType::Secure => "secure",
+            XciPartitionType::Logo => "logo",
+        }
+    }
+}
+
+type RootPartitionFileSystem<S> = PartitionFileSystem<SliceStorage<SharedStorage<S>>>;
+pub type XciPartitionFileSystem<S> =
+    PartitionFileSystem<FileStorage<SliceStorage<SharedStorage<S>>>>;
+
+#[derive(Debug)]
+pub struct Xci<S: ReadableStorage> {
+    storage: SharedStorage<S>,
+    header: XciHeader,
+    root: RootPartitionFileSystem<S>,
+}
+
+impl<S: ReadableStorage> Xci<S> {
+    pub fn new(storage: S) -> Result<Self, XciError> {
+        let storage = storage.shared();
+
+        let header =
+            XciHeader::read(&mut storage.clone().buf_read()).context(HeaderParsingSnafu)?;
+
+        let root_offset = header.partition_fs_header_offset;
+        let root_storage = storage
+            .clone()
+            .slice(root_offset, storage.get_size().saturating_sub(root_offset))
+            .context(RootPartitionBoundsSnafu)?;
+        let root = PartitionFileSystem::new(root_storage).context(RootPartitionParsingSnafu)?;
+
+        Ok(Self {
+            storage,
+            header,
+            root,
+        })
+    }
+
+    pub fn header(&self) -> &XciHeader {
+        &self.header
+    }
+
+    pub fn dump_kind(&self) -> XciDumpKind {
+        let size = self.storage.get_size();
+        let valid_data_size = self.header.valid_data_size();
+
+        match size.cmp(&valid_data_size) {
+            std::cmp::Ordering::Less => XciDumpKind::Truncated,
+            std::cmp::Ordering::Equal => XciDumpKind::Trimmed,
+            std::cmp::Ordering::Greater => XciDumpKind::Untrimmed,
+        }
+    }
+
+    pub fn partition(
+        &self,
+        partition: XciPartitionType,
+    ) -> Result<XciPartitionFileSystem<S>, XciError> {
+        let file = self
+            .root
+            .open_file(&format!("/{}", partition.name()))
+            .context(MissingPartitionSnafu { partition })?;
+        let storage = file.storage().context(PartitionOpenSnafu { partition })?;
+
+        PartitionFileSystem::new(storage).context(PartitionParsingSnafu { partition })
+    }
+
+    /// Checks the integrity of a partition: the hash of its header (stored in the root partition) and the hashes of all its files.
+    pub fn verify_partition(&self, partition: XciPartitionType) -> Result<(), XciError> {
+        // the root partition stores the hashes of the partition headers
+        self.verify_root_partition()?;
+
+        self.partition(partition)?
+            .verify_hashes()
+            .context(PartitionVerifySnafu { partition })
+    }
+
+    fn verify_root_partition(&self) -> Result<(), XciError> {
+        let header_hash = self
+            .storage
+            .clone()
+            .slice(
+                self.header.partition_fs_header_offset,
+                self.header.partition_fs_header_size,
+            )
+            .context(RootPartitionBoundsSnafu)?
+            .compute_sha256()
+            .context(RootPartitionReadSnafu)?;
+        if header_hash != self.header.partition_fs_header_hash.0 {
+            return RootPartitionHashMismatchSnafu.fail();
+        }
+
+        self.root.verify_hashes().context(RootPartitionVerifySnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Xci, XciDumpKind, XciError, XciHeader, XciPartitionType};
+    use crate::filesystem::{ReadableFile, ReadableFileSystem};
+    use crate::formats::pfs::test_fixture::make_hfs0;
+    use crate::hexstring::HexData;
+    use crate::storage::{ReadableStorageExt, VecStorage};
+    use binrw::BinWrite;
+    use digest::Digest;
+    use std::io::Cursor;
+
+    const ROOT_OFFSET: u64 = 0xf000;
+
+    /// A trimmed XCI with the update, normal and secure partitions, the secure one containing `secure_files`
+    fn make_xci(secure_files: &[(&str, &[u8])]) -> Vec<u8> {
+        let update = make_hfs0(&[("update.nca", b"update")], false);
+        let normal = make_hfs0(&[], false);
+        let secure = make_hfs0(secure_files, false);
+
+        let partitions: &[(&str, &[u8])] = &[
+            ("update", &update),
+            ("normal", &normal),
+            ("secure", &secure),
+        ];
+        let root = make_hfs0(partitions, false);
+        let root_header_size = root.len() - update.len() - normal.len() - secure.len();
+
+        let valid_data_end = ROOT_OFFSET + root.len() as u64;
+        let header = XciHeader {
+            signature: HexData([0; 0x100]),
+            rom_area_start_page: 0,
+            backup_area_start_page: 0xffffffff,
+            key_index: 0,
+            rom_size: 0xfa,
+            version: 0,
+            flags: 0,
+            package_id: 0,
+            valid_data_end_page: (valid_data_end.div_ceil(0x200) - 1) as u32,
+            iv: HexData([0; 0x10]),
+            partition_fs_header_offset: ROOT_OFFSET,
+            partition_fs_header_size: root_header_size as u64,
+            partition_fs_header_hash: HexData(
+                sha2::Sha256::digest(&root[..root_header_size]).into(),
+            ),
+            initial_data_hash: HexData([0; 0x20]),
+            sel_sec: 1,
+            sel_t1_key: 2,
+            sel_key: 0,
+            lim_area_page: 0,
+            encrypted_card_info: HexData([0; 0x70]),
+        };
+
+        let mut cur = Cursor::new(Vec::new());
+        header.write(&mut cur).unwrap();
+        let mut result = cur.into_inner();
+        result.resize(ROOT_OFFSET as usize, 0);
+        result.extend_from_slice(&root);
+        result.resize(header.valid_data_size() as usize, 0);
+        result
+    }
+
+    #[test]
+    fn partitions() {
+        let secure_files: &[(&str, &[u8])] = &[("a.nca", b"hello"), ("b.nca", b"world!")];
+        let xci = Xci::new(VecStorage::new(make_xci(secure_files))).unwrap();
+        assert_eq!(xci.dump_kind(), XciDumpKind::Trimmed);
+
+        let secure = xci.partition(XciPartitionType::Secure).unwrap();
+        let file = secure.open_file("/b.nca").unwrap();
+        assert_eq!(file.storage().unwrap().read_all().unwrap(), b"world!");
+
+        let update = xci.partition(XciPartitionType::Update).unwrap();
+        assert!(update.open_file("/update.nca").is_some());
+        xci.partition(XciPartitionType::Normal).unwrap();
+        assert!(matches!(
+            xci.partition(XciPartitionType::Logo),
+            Err(XciError::MissingPartition {
+                partition: XciPartitionType::Logo
+            })
+        ));
+
+        for partition in [
+            XciPartitionType::Update,
+            XciPartitionType::Normal,
+            XciPartitionType::Secure,
+        ] {
+            xci.verify_partition(partition).unwrap();
+        }
+    }
+
+    #[test]
+    fn dump_kind() {
+        let mut data = make_xci(&[]);
+        data.extend_from_slice(&[0xff; 0x200]);
+        let xci = Xci::new(VecStorage::new(data.clone())).unwrap();
+        assert_eq!(xci.dump_kind(), XciDumpKind::Untrimmed);
+
+        data.truncate(data.len() - 0x201);
+        let xci = Xci::new(VecStorage::new(data)).unwrap();
+        assert_eq!(xci.dump_kind(), XciDumpKind::Truncated);
+    }
+
+    #[test]
+    fn root_hash_mismatch() {
+        let mut data = make_xci(&[]);
+        // spoil the hash of the root partition header in the gamecard header
+        data[0x140] ^= 1;
+        let xci = Xci::new(VecStorage::new(data)).unwrap();
+        assert!(matches!(
+            xci.verify_partition(XciPartitionType::Secure),
+            Err(XciError::RootPartitionHashMismatch {})
+        ));
+    }
+}
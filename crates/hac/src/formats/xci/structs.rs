@@ -0,0 +1,137 @@
+use crate::hexstring::HexData;
+use binrw::{BinRead, BinWrite};
+use std::fmt::Display;
+
+pub const PAGE_SIZE: u64 = 0x200;
+
+/// Capacity of the gamecard, as declared in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CartridgeSize {
+    Size1GB,
+    Size2GB,
+    Size4GB,
+    Size8GB,
+    Size16GB,
+    Size32GB,
+}
+
+impl CartridgeSize {
+    pub fn from_raw(rom_size: u8) -> Option<Self> {
+        Some(match rom_size {
+            0xfa => Self::Size1GB,
+            0xf8 => Self::Size2GB,
+            0xf0 => Self::Size4GB,
+            0xe0 => Self::Size8GB,
+            0xe1 => Self::Size16GB,
+            0xe2 => Self::Size32GB,
+            _ => return None,
+        })
+    }
+
+    pub fn gigabytes(&self) -> u32 {
+        match self {
+            CartridgeSize::Size1GB => 1,
+            CartridgeSize::Size2GB => 2,
+            CartridgeSize::Size4GB => 4,
+            CartridgeSize::Size8GB => 8,
+            CartridgeSize::Size16GB => 16,
+            CartridgeSize::Size32GB => 32,
+        }
+    }
+}
+
+impl Display for CartridgeSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}GB", self.gigabytes())
+    }
+}
+
+/// Gamecard header, corresponding to the first 0x200 bytes of the XCI (without the key area)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, BinWrite)]
+#[brw(little)]
+pub struct XciHeader {
+    pub signature: HexData<0x100>,
+    #[brw(magic = b"HEAD")]
+    pub rom_area_start_page: u32,
+    pub backup_area_start_page: u32,
+    pub key_index: u8,
+    pub rom_size: u8,
+    pub version: u8,
+    pub flags: u8,
+    pub package_id: u64,
+    #[brw(pad_after = 4)]
+    pub valid_data_end_page: u32,
+    pub iv: HexData<0x10>,
+    pub partition_fs_header_offset: u64,
+    pub partition_fs_header_size: u64,
+    pub partition_fs_header_hash: HexData<0x20>,
+    pub initial_data_hash: HexData<0x20>,
+    pub sel_sec: u32,
+    pub sel_t1_key: u32,
+    pub sel_key: u32,
+    pub lim_area_page: u32,
+    /// Encrypted with a key we don't have
+    pub encrypted_card_info: HexData<0x70>,
+}
+
+impl XciHeader {
+    /// `None` if the header specifies an unknown cartridge size
+    pub fn cartridge_size(&self) -> Option<CartridgeSize> {
+        CartridgeSize::from_raw(self.rom_size)
+    }
+
+    /// Size of the image up to the end of the valid data, i.e. the size of a trimmed dump
+    pub fn valid_data_size(&self) -> u64 {
+        (self.valid_data_end_page as u64 + 1) * PAGE_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CartridgeSize, XciHeader};
+    use crate::hexstring::HexData;
+    use binrw::{BinRead, BinWrite};
+    use std::io::Cursor;
+
+    #[test]
+    fn header_layout() {
+        let header = XciHeader {
+            signature: HexData([0x11; 0x100]),
+            rom_area_start_page: 0x12345678,
+            backup_area_start_page: 0xffffffff,
+            key_index: 0,
+            rom_size: 0xf8,
+            version: 0,
+            flags: 0,
+            package_id: 0x0102030405060708,
+            valid_data_end_page: 0x1000,
+            iv: HexData([0x22; 0x10]),
+            partition_fs_header_offset: 0xf000,
+            partition_fs_header_size: 0x200,
+            partition_fs_header_hash: HexData([0x33; 0x20]),
+            initial_data_hash: HexData([0x44; 0x20]),
+            sel_sec: 1,
+            sel_t1_key: 2,
+            sel_key: 0,
+            lim_area_page: 0x12345678,
+            encrypted_card_info: HexData([0x55; 0x70]),
+        };
+
+        let mut cur = Cursor::new(Vec::new());
+        header.write(&mut cur).unwrap();
+        let data = cur.into_inner();
+
+        assert_eq!(data.len(), 0x200);
+        assert_eq!(&data[0x100..0x104], b"HEAD");
+        assert_eq!(data[0x10d], 0xf8);
+        assert_eq!(&data[0x118..0x120], &[0x00, 0x10, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&data[0x130..0x138], &0xf000u64.to_le_bytes());
+        assert_eq!(&data[0x140..0x160], &[0x33; 0x20]);
+        assert_eq!(&data[0x190..0x200], &[0x55; 0x70]);
+
+        let read = XciHeader::read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(read, header);
+        assert_eq!(read.cartridge_size(), Some(CartridgeSize::Size2GB));
+        assert_eq!(read.valid_data_size(), 0x1001 * 0x200);
+    }
+}
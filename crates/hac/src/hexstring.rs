@@ -26,6 +26,7 @@ impl<const N: usize> core::fmt::Display for HexData<N> {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de, const N: usize> serde::Deserialize<'de> for HexData<N> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -65,6 +66,7 @@ impl<'de, const N: usize> serde::Deserialize<'de> for HexData<N> {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<const N: usize> serde::Serialize for HexData<N> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
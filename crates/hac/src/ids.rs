@@ -1,6 +1,8 @@
+use crate::formats::cnmt::ContentMetaType;
 use crate::hexstring::HexData;
 use binrw::{BinRead, BinWrite};
 use hex::FromHexError;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use std::fmt::{Debug, Display};
@@ -43,6 +45,13 @@ macro_rules! define_some_id {
             }
         }
 
+        #[cfg(feature = "serde")]
+        impl Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
         impl From<$ty> for AnyId {
             fn from(id: $ty) -> Self {
                 AnyId(id.0)
@@ -54,6 +63,12 @@ macro_rules! define_some_id {
                 Self(id.0)
             }
         }
+
+        impl From<u64> for $ty {
+            fn from(id: u64) -> Self {
+                Self(id)
+            }
+        }
     };
 }
 
@@ -69,6 +84,17 @@ impl Display for AnyId {
         Debug::fmt(self, f)
     }
 }
+#[cfg(feature = "serde")]
+impl Serialize for AnyId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl From<u64> for AnyId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
 
 define_some_id!(ProgramId);
 define_some_id!(ApplicationId);
@@ -84,6 +110,51 @@ impl ProgramId {
     }
 }
 
+/// An id along with its meaning, as determined by the [`ContentMetaType`] it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TypedId {
+    Program(ProgramId),
+    /// System data, system update or boot image package
+    System(AnyId),
+    Application(ApplicationId),
+    Patch(PatchId),
+    AddOnContent(DataId),
+    Delta(DeltaId),
+    DataPatch(DataPatchId),
+}
+
+impl AnyId {
+    /// Interprets the id according to the type of the content meta it identifies
+    pub fn typed(self, ty: ContentMetaType) -> TypedId {
+        match ty {
+            ContentMetaType::SystemProgram => TypedId::Program(self.into()),
+            ContentMetaType::SystemData
+            | ContentMetaType::SystemUpdate
+            | ContentMetaType::BootImagePackage
+            | ContentMetaType::BootImagePackageSafe => TypedId::System(self),
+            ContentMetaType::Application => TypedId::Application(self.into()),
+            ContentMetaType::Patch => TypedId::Patch(self.into()),
+            ContentMetaType::AddOnContent => TypedId::AddOnContent(self.into()),
+            ContentMetaType::Delta => TypedId::Delta(self.into()),
+            ContentMetaType::DataPatch => TypedId::DataPatch(self.into()),
+        }
+    }
+}
+
+impl From<TypedId> for AnyId {
+    fn from(id: TypedId) -> Self {
+        match id {
+            TypedId::Program(id) => id.into(),
+            TypedId::System(id) => id,
+            TypedId::Application(id) => id.into(),
+            TypedId::Patch(id) => id.into(),
+            TypedId::AddOnContent(id) => id.into(),
+            TypedId::Delta(id) => id.into(),
+            TypedId::DataPatch(id) => id.into(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, BinRead, BinWrite)]
 pub struct ContentId([u8; 0x10]);
 
@@ -102,6 +173,13 @@ impl Display for ContentId {
     }
 }
 
+#[cfg(feature = "serde")]
+impl Serialize for ContentId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl FromStr for ContentId {
     type Err = IdParseError;
 
@@ -112,20 +190,8 @@ impl FromStr for ContentId {
 }
 
 /// Identifies a title key in the keyset.
-#[derive(
-    Debug,
-    Copy,
-    Clone,
-    Eq,
-    PartialEq,
-    Hash,
-    Ord,
-    PartialOrd,
-    Serialize,
-    Deserialize,
-    BinRead,
-    BinWrite,
-)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RightsId(HexData<0x10>);
 
 impl RightsId {
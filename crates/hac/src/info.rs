@@ -0,0 +1,44 @@
+//! Machine-readable dumps of the metadata stored in the Switch formats.
+
+use crate::formats::nca::{Nca, NcaContentType};
+use crate::storage::ReadableStorage;
+use crate::switch_fs::content_set::{read_control, read_meta};
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// Describes an NCA as JSON: its header, the FS headers of the present sections and,
+/// for Meta and Control NCAs, the parsed CNMT and NACP.
+///
+/// The CNMT and NACP are reported as `null` if they could not be read.
+pub fn nca_info_json<S: ReadableStorage>(nca: &Nca<S>) -> Value {
+    let sections = (0..4)
+        .filter_map(|index| {
+            nca.fs_header(index)
+                .map(|fs_header| json!({ "index": index, "fs_header": fs_header }))
+        })
+        .collect::<Vec<_>>();
+
+    let mut info = json!({
+        "header": nca.header(),
+        "is_plaintext": nca.is_plaintext(),
+        "sections": sections,
+    });
+
+    match nca.content_type() {
+        NcaContentType::Meta => {
+            let cnmt = read_meta(nca)
+                .map_err(|e| warn!("Could not read the CNMT: {}", e))
+                .ok();
+            info["cnmt"] = json!(cnmt);
+        }
+        NcaContentType::Control => {
+            let nacp = read_control(nca)
+                .map_err(|e| warn!("Could not read the NACP: {}", e))
+                .ok();
+            info["nacp"] = json!(nacp);
+        }
+        _ => {}
+    }
+
+    info
+}
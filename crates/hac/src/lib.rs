@@ -3,10 +3,18 @@ pub use snafu;
 
 mod brw_utils;
 pub mod crypto;
+mod detect;
+mod error;
 pub mod filesystem;
 pub mod formats;
 mod hexstring;
 pub mod ids;
+#[cfg(feature = "serde")]
+pub mod info;
+mod parallel;
 pub mod storage;
 pub mod switch_fs;
 pub mod version;
+
+pub use detect::{detect_format, DetectedFormat};
+pub use error::format_error_chain;
@@ -0,0 +1,50 @@
+//! Spreading work over all the available cores with scoped threads
+
+use num_integer::Integer;
+
+/// Splits `items` into one chunk per core, keeping their order, and runs `f` on each chunk on its own thread
+///
+/// The results are in the order of the chunks, a panic in `f` is propagated to the caller.
+pub(crate) fn parallel_chunks<T: Send, R: Send>(
+    mut items: Vec<T>,
+    f: impl Fn(Vec<T>) -> R + Sync,
+) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = Integer::div_ceil(&items.len(), &threads).max(1);
+    let mut chunks = Vec::new();
+    while !items.is_empty() {
+        let rest = items.split_off(chunk_size.min(items.len()));
+        chunks.push(std::mem::replace(&mut items, rest));
+    }
+
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| scope.spawn(move || f(chunk)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parallel_chunks;
+
+    #[test]
+    fn keeps_order() {
+        let items = (0..1000).collect::<Vec<_>>();
+        let chunks = parallel_chunks(items.clone(), |chunk| chunk);
+        assert_eq!(chunks.concat(), items);
+
+        assert!(parallel_chunks(Vec::<u32>::new(), |chunk| chunk).is_empty());
+    }
+}
@@ -1,21 +1,99 @@
 use crate::storage::{ReadableBlockStorage, ReadableBlockStorageExt, StorageError};
 use mini_moka::sync::{Cache, CacheBuilder};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+/// Hit/miss counters of a [`BlockCacheStorage`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of block reads served from the cache, `0.0` if nothing was read yet
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// Configuration of a [`BlockCacheStorage`]
+#[derive(Debug, Clone)]
+pub struct BlockCacheStorageBuilder {
+    blocks_in_cache: u64,
+    time_to_idle: Option<Duration>,
+}
+
+impl Default for BlockCacheStorageBuilder {
+    fn default() -> Self {
+        Self {
+            blocks_in_cache: 64,
+            time_to_idle: None,
+        }
+    }
+}
+
+impl BlockCacheStorageBuilder {
+    /// Maximum number of blocks kept in the cache
+    pub fn capacity(mut self, blocks_in_cache: u64) -> Self {
+        self.blocks_in_cache = blocks_in_cache;
+        self
+    }
+
+    /// Evict the blocks that were not accessed for this long
+    pub fn time_to_idle(mut self, time_to_idle: Duration) -> Self {
+        self.time_to_idle = Some(time_to_idle);
+        self
+    }
+
+    pub fn build<S: ReadableBlockStorage>(&self, storage: S) -> BlockCacheStorage<S> {
+        let mut cache = CacheBuilder::new(self.blocks_in_cache);
+        if let Some(time_to_idle) = self.time_to_idle {
+            cache = cache.time_to_idle(time_to_idle);
+        }
+
+        BlockCacheStorage {
+            storage,
+            cache: cache.build(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+}
+
 pub struct BlockCacheStorage<S> {
     storage: S,
     cache: Cache<u64, Arc<Vec<u8>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl<S: ReadableBlockStorage> BlockCacheStorage<S> {
     pub fn new(storage: S, blocks_in_cache: u64, time_to_idle: Duration) -> Self {
-        let cache = CacheBuilder::new(blocks_in_cache)
+        Self::builder()
+            .capacity(blocks_in_cache)
             .time_to_idle(time_to_idle)
-            .build();
+            .build(storage)
+    }
+
+    pub fn builder() -> BlockCacheStorageBuilder {
+        BlockCacheStorageBuilder::default()
+    }
+}
 
-        Self { storage, cache }
+impl<S> BlockCacheStorage<S> {
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
     }
 }
 
@@ -31,10 +109,12 @@ impl<S: ReadableBlockStorage> ReadableBlockStorage for BlockCacheStorage<S> {
 
         match self.cache.get(&block_index) {
             Some(content) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
                 buf.copy_from_slice(content.as_slice());
                 Ok(())
             }
             None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
                 self.storage.read_block(block_index, buf)?;
                 // allocating on every cache miss is a bit sad..
                 let content = Arc::new(buf.to_vec());
@@ -53,6 +133,7 @@ impl<S: fmt::Debug> fmt::Debug for BlockCacheStorage<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("BlockCacheStorage")
             .field("storage", &self.storage)
+            .field("stats", &self.stats())
             .finish()
     }
 }
@@ -13,6 +13,14 @@ impl AesCtrBlockTransform {
         Self { key, nonce }
     }
 
+    pub fn key(&self) -> &AesKey {
+        &self.key
+    }
+
+    pub fn nonce(&self) -> HexData<0x10> {
+        self.nonce
+    }
+
     fn get_ctr(&self, block_index: u64) -> [u8; 0x10] {
         (u128::from_be_bytes(self.nonce.0) + block_index as u128).to_be_bytes()
         // let mut ctr = [0; 0x10];
@@ -24,7 +24,7 @@ pub type AesCtrStorage<S> = BlockTransformStorage<S, AesCtrBlockTransform>;
 impl<S: ReadableBlockStorage, T: BlockTransform> BlockTransformStorage<S, T> {
     pub fn new(storage: S, transform: T) -> Self {
         assert_eq!(
-            storage.get_size() % T::BLOCK_SIZE as u64,
+            storage.get_size() % T::BLOCK_SIZE,
             0,
             "Storage size must be a multiple of the block size"
         );
@@ -35,6 +35,10 @@ impl<S: ReadableBlockStorage, T: BlockTransform> BlockTransformStorage<S, T> {
         );
         Self { storage, transform }
     }
+
+    pub fn transform(&self) -> &T {
+        &self.transform
+    }
 }
 
 impl<S: ReadableBlockStorage, T: BlockTransform> ReadableBlockStorage
@@ -0,0 +1,123 @@
+use crate::storage::{ReadableStorage, Storage, StorageError};
+use digest::Digest;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+struct HashingState {
+    hasher: sha2::Sha256,
+    position: u64,
+}
+
+/// A storage that computes the SHA-256 of the data written to it
+///
+/// The writes must be sequential, starting from the beginning of the storage, so this is suitable for append-only writers.
+#[derive(Debug)]
+pub struct HashingStorage<S: Storage> {
+    storage: S,
+    state: Mutex<HashingState>,
+}
+
+impl<S: Storage> HashingStorage<S> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            state: Mutex::new(HashingState {
+                hasher: sha2::Sha256::new(),
+                position: 0,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.storage
+    }
+
+    /// Returns the number of bytes hashed so far
+    pub fn position(&self) -> u64 {
+        self.state.lock().unwrap().position
+    }
+
+    /// Returns the SHA-256 of all the data written
+    pub fn finalize(self) -> [u8; 0x20] {
+        self.state.into_inner().unwrap().hasher.finalize().into()
+    }
+}
+
+impl<S: Storage> ReadableStorage for HashingStorage<S> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.storage.read(offset, buf)
+    }
+
+    fn get_size(&self) -> u64 {
+        self.storage.get_size()
+    }
+}
+
+impl<S: Storage> Storage for HashingStorage<S> {
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<(), StorageError> {
+        let mut state = self.state.lock().unwrap();
+        if offset != state.position {
+            return Err(StorageError::NonSequentialWrite {
+                expected: state.position,
+                actual: offset,
+            });
+        }
+
+        self.storage.write(offset, buf)?;
+
+        state.hasher.update(buf);
+        state.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.storage.flush()
+    }
+
+    fn set_size(&self, new_size: u64) -> Result<(), StorageError> {
+        self.storage.set_size(new_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashingStorage;
+    use crate::storage::{ReadableStorageExt, Storage, StorageError, VecStorage};
+    use digest::Digest;
+
+    #[test]
+    fn sequential() {
+        let data = (0..0x1000).map(|i| i as u8).collect::<Vec<_>>();
+        let storage = HashingStorage::new(VecStorage::new(vec![0; data.len()]));
+
+        for chunk in data.chunks(0x300) {
+            storage.write(storage.position(), chunk).unwrap();
+        }
+        assert_eq!(storage.position(), data.len() as u64);
+        assert_eq!(storage.inner().read_all().unwrap(), data);
+
+        let expected: [u8; 0x20] = sha2::Sha256::digest(&data).into();
+        assert_eq!(storage.finalize(), expected);
+    }
+
+    #[test]
+    fn non_sequential() {
+        let storage = HashingStorage::new(VecStorage::new(vec![0; 0x20]));
+        storage.write(0, &[1; 0x10]).unwrap();
+
+        for offset in [0, 0x8, 0x18] {
+            assert!(matches!(
+                storage.write(offset, &[2; 0x8]),
+                Err(StorageError::NonSequentialWrite {
+                    expected: 0x10,
+                    actual,
+                }) if actual == offset
+            ));
+        }
+        // the rejected writes are neither hashed nor written
+        assert_eq!(storage.position(), 0x10);
+        assert_eq!(storage.inner().read_all().unwrap()[0x10..], [0; 0x10]);
+        let expected: [u8; 0x20] = sha2::Sha256::digest([1; 0x10]).into();
+        assert_eq!(storage.finalize(), expected);
+    }
+}
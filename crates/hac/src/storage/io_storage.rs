@@ -1,6 +1,6 @@
 use snafu::ResultExt;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::Mutex;
 
@@ -68,6 +68,27 @@ impl<Io: Read + Seek + Send> Storage for RoIoStorage<Io> {
     }
 }
 
+/// An IO object that can be resized (both grown and shrunk)
+pub trait SetLen {
+    fn set_len(&mut self, size: u64) -> std::io::Result<()>;
+}
+
+impl SetLen for File {
+    fn set_len(&mut self, size: u64) -> std::io::Result<()> {
+        File::set_len(self, size)
+    }
+}
+
+impl SetLen for Cursor<Vec<u8>> {
+    fn set_len(&mut self, size: u64) -> std::io::Result<()> {
+        let size = size
+            .try_into()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "size too large"))?;
+        self.get_mut().resize(size, 0);
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 struct RwIoStorageInner<Io: Read + Write + Seek + Send + Sync> {
     io: Io,
@@ -87,7 +108,7 @@ impl<Io: Read + Write + Seek + Send + Sync> RwIoStorageInner<Io> {
 
 /// A storage that wraps an IO object, allowing read and write access.
 ///
-/// Note that this storage does not implement resizing correctly, as there is no trait for that =(.
+/// The IO object has to implement [`SetLen`] for the storage to be resizable.
 pub struct RwIoStorage<Io: Read + Write + Seek + Send + Sync>(Mutex<RwIoStorageInner<Io>>);
 
 impl<Io: Read + Write + Seek + Send + Sync> RwIoStorage<Io> {
@@ -121,7 +142,7 @@ impl<Io: Read + Write + Seek + Send + Sync> ReadableStorage for RwIoStorage<Io>
     }
 }
 
-impl<Io: Read + Write + Seek + Send + Sync> Storage for RwIoStorage<Io> {
+impl<Io: Read + Write + Seek + SetLen + Send + Sync> Storage for RwIoStorage<Io> {
     fn write(&self, offset: u64, buf: &[u8]) -> Result<(), StorageError> {
         let mut inner = self.0.lock().unwrap();
         inner.check_size(offset, buf)?;
@@ -143,12 +164,9 @@ impl<Io: Read + Write + Seek + Send + Sync> Storage for RwIoStorage<Io> {
 
     fn set_size(&self, new_size: u64) -> Result<(), StorageError> {
         let mut inner = self.0.lock().unwrap();
-        // at least try to expand the file
-        // no way to shrink it, though =(
-        inner
-            .io
-            .seek(SeekFrom::Start(new_size))
-            .context(IoSnafu { operation: "seek" })?;
+        inner.io.set_len(new_size).context(IoSnafu {
+            operation: "set_len",
+        })?;
         inner.size = new_size;
         Ok(())
     }
@@ -183,3 +201,22 @@ impl FileRwStorage {
         Self::new(io)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{ReadableStorage, RwIoStorage, Storage};
+    use std::io::Cursor;
+
+    #[test]
+    fn set_size_shrinks() {
+        let storage = RwIoStorage::new(Cursor::new(b"1234567890".to_vec())).unwrap();
+
+        storage.set_size(4).unwrap();
+        assert_eq!(storage.get_size(), 4);
+        storage.set_size(6).unwrap();
+
+        let mut buf = [0xff; 6];
+        storage.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"1234\0\0");
+    }
+}
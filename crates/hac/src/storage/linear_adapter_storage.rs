@@ -9,6 +9,10 @@ impl<S: ReadableBlockStorage> LinearAdapterStorage<S> {
     pub fn new(storage: S) -> Self {
         Self { storage }
     }
+
+    pub fn inner(&self) -> &S {
+        &self.storage
+    }
 }
 
 impl<S: ReadableBlockStorage> ReadableStorage for LinearAdapterStorage<S> {
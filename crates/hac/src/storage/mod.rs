@@ -1,5 +1,5 @@
 use num_integer::Integer;
-use snafu::Snafu;
+use snafu::{ensure, Snafu};
 use std::io::BufReader;
 use std::path::Path;
 
@@ -8,29 +8,34 @@ mod block_cache_storage;
 mod block_slice_storage;
 mod block_transform_storage;
 mod concat_storage;
-mod deny_storage;
 mod either_storage;
+mod hashing_storage;
 mod io_storage;
 mod linear_adapter_storage;
+mod pad_storage;
 mod shared_storage;
 mod slice_storage;
+mod sparse_read_storage;
 mod storage_io;
 mod vec_storage;
 
 pub use block_adapter_storage::BlockAdapterStorage;
-pub use block_cache_storage::BlockCacheStorage;
+pub use block_cache_storage::{BlockCacheStorage, BlockCacheStorageBuilder, CacheStats};
 pub use block_slice_storage::{BlockSliceStorage, BlockSliceStorageError};
 pub use block_transform_storage::{
     block_transforms, AesCtrStorage, BlockTransform, BlockTransformStorage,
 };
 pub use concat_storage::{ConcatStorage2, ConcatStorageN};
 pub use either_storage::EitherStorage;
-pub use io_storage::{FileRoStorage, FileRwStorage, RoIoStorage, RwIoStorage};
+pub use hashing_storage::HashingStorage;
+pub use io_storage::{FileRoStorage, FileRwStorage, RoIoStorage, RwIoStorage, SetLen};
 pub use linear_adapter_storage::LinearAdapterStorage;
+pub use pad_storage::PadStorage;
 pub use shared_storage::SharedStorage;
 pub use slice_storage::{SliceStorage, SliceStorageError};
+pub use sparse_read_storage::{SparseReadStorage, SparseReadStorageError};
 pub use storage_io::StorageIo;
-pub use vec_storage::VecStorage;
+pub use vec_storage::{VecStorage, VecStorageSlice};
 
 pub trait ReadableStorage: Send + Sync {
     fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError>;
@@ -60,7 +65,7 @@ pub trait ReadableBlockStorage: Send + Sync {
         for i in 0..block_count {
             let buf = &mut buf[(i * block_size) as usize..];
             let read_size = std::cmp::min(block_size, buf.len() as u64);
-            self.read_block(block_index + i as u64, &mut buf[..read_size as usize])?;
+            self.read_block(block_index + i, &mut buf[..read_size as usize])?;
         }
         Ok(())
     }
@@ -77,7 +82,7 @@ pub trait BlockStorage: ReadableBlockStorage {
         for i in 0..block_count {
             let buf = &buf[(i * block_size) as usize..];
             let write_size = std::cmp::min(block_size, buf.len() as u64);
-            self.write_block(block_index + i as u64, &buf[..write_size as usize])?;
+            self.write_block(block_index + i, &buf[..write_size as usize])?;
         }
         Ok(())
     }
@@ -112,12 +117,33 @@ pub trait ReadableStorageExt: ReadableStorage {
         BufReader::new(self.io())
     }
 
+    /// Reads as much of `buf` as the storage has data for starting at `offset`, returning the number of bytes read
+    ///
+    /// Unlike [`ReadableStorage::read`], reading past the end is not an error, like with [`std::io::Read`] (0 means the end of the storage).
+    fn read_partial(&self, offset: u64, buf: &mut [u8]) -> Result<usize, StorageError> {
+        let read = std::cmp::min(buf.len() as u64, self.get_size().saturating_sub(offset)) as usize;
+        // some storages reject even empty reads past the end
+        if read > 0 {
+            self.read(offset, &mut buf[..read])?;
+        }
+        Ok(read)
+    }
+
     fn read_all(&self) -> Result<Vec<u8>, StorageError> {
         let mut buf = vec![0; self.get_size() as usize];
         self.read(0, &mut buf)?;
         Ok(buf)
     }
 
+    /// Same as [`ReadableStorageExt::read_all`], but fails with [`StorageError::TooLarge`] instead of reading more than `max` bytes
+    ///
+    /// Use it when the size comes from untrusted input, so that a malformed file can't make it allocate all the memory.
+    fn read_all_capped(&self, max: u64) -> Result<Vec<u8>, StorageError> {
+        let size = self.get_size();
+        ensure!(size <= max, TooLargeSnafu { size, max });
+        self.read_all()
+    }
+
     fn copy_to<S: Storage>(&self, other: &S) -> Result<(), StorageError> {
         const BUFFER_SIZE: usize = 0x10000;
         let size = self.get_size();
@@ -134,6 +160,22 @@ pub trait ReadableStorageExt: ReadableStorage {
     fn save_to_file(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
         self.copy_to(&FileRwStorage::create(path)?)
     }
+
+    /// Computes the SHA-256 of the whole storage, reading it in chunks
+    fn compute_sha256(&self) -> Result<[u8; 0x20], StorageError> {
+        use digest::Digest;
+
+        const BUFFER_SIZE: usize = 0x10000;
+        let size = self.get_size();
+        let mut hasher = sha2::Sha256::new();
+        let mut buf = vec![0; BUFFER_SIZE];
+        for offset in (0..size).step_by(BUFFER_SIZE) {
+            let read_size = std::cmp::min(BUFFER_SIZE as u64, size - offset);
+            self.read(offset, &mut buf[..read_size as usize])?;
+            hasher.update(&buf[..read_size as usize]);
+        }
+        Ok(hasher.finalize().into())
+    }
 }
 
 pub trait ReadableBlockStorageExt: ReadableBlockStorage {
@@ -186,4 +228,8 @@ pub enum StorageError {
     IntegrityCheckFailed {},
     /// A storage requiring aligned access was accessed with an unaligned offset
     UnalignedAccess {},
+    /// A storage requiring sequential writes was written at {actual}, expected {expected}
+    NonSequentialWrite { expected: u64, actual: u64 },
+    /// Storage of {size} bytes is larger than the limit of {max} bytes
+    TooLarge { size: u64, max: u64 },
 }
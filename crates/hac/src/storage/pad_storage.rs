@@ -0,0 +1,65 @@
+use crate::storage::{ReadableStorage, StorageError};
+use num_integer::Integer;
+
+/// Presents the storage with its size rounded up to a multiple of `alignment`, the padding reads as zeros
+///
+/// Allows feeding unaligned storages to the block-based consumers.
+#[derive(Debug)]
+pub struct PadStorage<S> {
+    storage: S,
+    size: u64,
+}
+
+impl<S: ReadableStorage> PadStorage<S> {
+    pub fn new(storage: S, alignment: u64) -> Self {
+        assert_ne!(alignment, 0, "alignment must be non-zero");
+        let size = Integer::div_ceil(&storage.get_size(), &alignment) * alignment;
+        Self { storage, size }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+}
+
+impl<S: ReadableStorage> ReadableStorage for PadStorage<S> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        if offset + buf.len() as u64 > self.size {
+            return Err(StorageError::OutOfBounds {});
+        }
+
+        let inner_size = self.storage.get_size();
+        let inner_len = inner_size.saturating_sub(offset).min(buf.len() as u64) as usize;
+        let (inner, padding) = buf.split_at_mut(inner_len);
+        if !inner.is_empty() {
+            self.storage.read(offset, inner)?;
+        }
+        padding.fill(0);
+
+        Ok(())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{PadStorage, ReadableStorage, ReadableStorageExt, VecStorage};
+
+    #[test]
+    fn pad() {
+        let storage = PadStorage::new(VecStorage::new(b"12345".to_vec()), 4);
+        assert_eq!(storage.get_size(), 8);
+        assert_eq!(storage.read_all().unwrap(), b"12345\0\0\0");
+
+        let mut buf = [0xff; 2];
+        storage.read(6, &mut buf).unwrap();
+        assert_eq!(buf, [0, 0]);
+        assert!(storage.read(7, &mut buf).is_err());
+
+        let aligned = PadStorage::new(VecStorage::new(b"1234".to_vec()), 4);
+        assert_eq!(aligned.get_size(), 4);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::storage::{ReadableStorage, StorageError};
+use snafu::Snafu;
+
+/// Presents the storages placed at the given offsets as one storage of a fixed size, the gaps between them read as zeros
+///
+/// Useful to assemble partial dumps and to reconstruct sparse sections.
+#[derive(Debug)]
+pub struct SparseReadStorage<S> {
+    /// Sorted by the offset, do not overlap
+    ranges: Vec<(u64, S)>,
+    size: u64,
+}
+
+#[derive(Snafu, Debug)]
+pub enum SparseReadStorageError {
+    /// Range at {offset} overlaps the previous one, which ends at {previous_end}
+    RangesOverlap { offset: u64, previous_end: u64 },
+    /// Range at {offset} ends at {end}, past the end of the storage at {size}
+    RangeOutOfBounds { offset: u64, end: u64, size: u64 },
+    /// Range at {offset} of size {range_size} overflows the 64-bit offsets
+    RangeOverflow { offset: u64, range_size: u64 },
+}
+
+impl<S: ReadableStorage> SparseReadStorage<S> {
+    pub fn new(mut ranges: Vec<(u64, S)>, size: u64) -> Result<Self, SparseReadStorageError> {
+        ranges.sort_by_key(|(offset, _)| *offset);
+
+        let mut previous_end = 0;
+        for (offset, storage) in &ranges {
+            let offset = *offset;
+            let range_size = storage.get_size();
+            let end = offset
+                .checked_add(range_size)
+                .ok_or(SparseReadStorageError::RangeOverflow { offset, range_size })?;
+            if offset < previous_end {
+                return Err(SparseReadStorageError::RangesOverlap {
+                    offset,
+                    previous_end,
+                });
+            }
+            if end > size {
+                return Err(SparseReadStorageError::RangeOutOfBounds { offset, end, size });
+            }
+            previous_end = end;
+        }
+
+        Ok(Self { ranges, size })
+    }
+}
+
+impl<S: ReadableStorage> ReadableStorage for SparseReadStorage<S> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or(StorageError::OutOfBounds {})?;
+        if end > self.size {
+            return Err(StorageError::OutOfBounds {});
+        }
+
+        buf.fill(0);
+
+        // the ranges don't overlap, so their ends are sorted too
+        let first = self
+            .ranges
+            .partition_point(|(range_offset, storage)| range_offset + storage.get_size() <= offset);
+        for (range_offset, storage) in &self.ranges[first..] {
+            let range_offset = *range_offset;
+            if range_offset >= end {
+                break;
+            }
+
+            let start = std::cmp::max(offset, range_offset);
+            let stop = std::cmp::min(end, range_offset + storage.get_size());
+            storage.read(
+                start - range_offset,
+                &mut buf[(start - offset) as usize..(stop - offset) as usize],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{
+        ReadableStorage, SparseReadStorage, SparseReadStorageError, StorageError, VecStorage,
+    };
+
+    #[test]
+    fn sparse() {
+        let storage = SparseReadStorage::new(
+            vec![
+                (6, VecStorage::new(b"456".to_vec())),
+                (1, VecStorage::new(b"12".to_vec())),
+            ],
+            10,
+        )
+        .unwrap();
+        assert_eq!(storage.get_size(), 10);
+
+        let mut buf = [0xff; 10];
+        storage.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"\x0012\x00\x00\x00456\x00");
+
+        let mut buf = [0xff; 4];
+        storage.read(2, &mut buf).unwrap();
+        assert_eq!(&buf, b"2\0\0\0");
+
+        let mut buf = [0xff; 2];
+        storage.read(7, &mut buf).unwrap();
+        assert_eq!(&buf, b"56");
+
+        assert!(storage.read(9, &mut [0; 2]).is_err());
+
+        assert!(SparseReadStorage::new(
+            vec![
+                (0, VecStorage::new(b"12".to_vec())),
+                (1, VecStorage::new(b"3".to_vec())),
+            ],
+            10,
+        )
+        .is_err());
+        assert!(SparseReadStorage::new(vec![(9, VecStorage::new(b"12".to_vec()))], 10).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(matches!(
+            SparseReadStorage::new(vec![(u64::MAX, VecStorage::new(b"12".to_vec()))], u64::MAX),
+            Err(SparseReadStorageError::RangeOverflow {
+                offset: u64::MAX,
+                range_size: 2
+            })
+        ));
+
+        let storage =
+            SparseReadStorage::new(vec![(1, VecStorage::new(b"12".to_vec()))], u64::MAX).unwrap();
+        assert!(matches!(
+            storage.read(u64::MAX - 1, &mut [0; 2]),
+            Err(StorageError::OutOfBounds {})
+        ));
+    }
+}
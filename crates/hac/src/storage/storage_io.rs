@@ -1,4 +1,4 @@
-use crate::storage::{ReadableStorage, Storage, StorageError};
+use crate::storage::{ReadableStorage, ReadableStorageExt, Storage, StorageError};
 use std::io::{ErrorKind, Read};
 
 pub struct StorageIo<S: ReadableStorage> {
@@ -17,6 +17,19 @@ impl<S: ReadableStorage> StorageIo<S> {
     pub fn into_inner(self) -> S {
         self.storage
     }
+
+    pub fn get_ref(&self) -> &S {
+        &self.storage
+    }
+
+    /// Reads from `offset` without using or changing the current position (like [`std::os::unix::fs::FileExt::read_at`]).
+    ///
+    /// Only takes `&self`, so a single `StorageIo` can be shared by multiple threads reading at different offsets.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.storage
+            .read_partial(offset, buf)
+            .map_err(map_storage_error_to_std)
+    }
 }
 
 fn map_storage_error_to_std(error: StorageError) -> std::io::Error {
@@ -28,18 +41,17 @@ fn map_storage_error_to_std(error: StorageError) -> std::io::Error {
         StorageError::OutOfBounds { .. } => ErrorKind::UnexpectedEof,
         StorageError::IntegrityCheckFailed { .. } => ErrorKind::InvalidData,
         StorageError::UnalignedAccess { .. } => ErrorKind::InvalidInput,
+        StorageError::NonSequentialWrite { .. } => ErrorKind::InvalidInput,
+        StorageError::TooLarge { .. } => ErrorKind::InvalidData,
     };
     std::io::Error::new(kind, error)
 }
 
 impl<S: ReadableStorage> Read for StorageIo<S> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let read = std::cmp::min(buf.len() as u64, self.storage.get_size() - self.position);
-        self.storage
-            .read(self.position, &mut buf[..read as usize])
-            .map_err(map_storage_error_to_std)?;
-        self.position += read;
-        Ok(read as usize)
+        let read = self.read_at(self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
     }
 }
 
@@ -1,6 +1,7 @@
 use crate::storage::{ReadableStorage, Storage, StorageError};
 use std::fmt::Debug;
-use std::sync::RwLock;
+use std::ops::Deref;
+use std::sync::{RwLock, RwLockReadGuard};
 
 pub struct VecStorage {
     data: RwLock<Vec<u8>>,
@@ -18,6 +19,26 @@ impl VecStorage {
             data: RwLock::new(data),
         }
     }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data.into_inner().unwrap()
+    }
+
+    /// Borrows the contents. Writes to the storage are blocked while the returned guard is alive.
+    pub fn as_slice(&self) -> VecStorageSlice<'_> {
+        VecStorageSlice(self.data.read().unwrap())
+    }
+}
+
+/// Contents of a [`VecStorage`], see [`VecStorage::as_slice`]
+pub struct VecStorageSlice<'a>(RwLockReadGuard<'a, Vec<u8>>);
+
+impl<'a> Deref for VecStorageSlice<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 impl ReadableStorage for VecStorage {
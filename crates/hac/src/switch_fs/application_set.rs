@@ -14,7 +14,7 @@ pub struct Program {
 
     pub html_document_content_id: Option<ContentId>,
     pub control_content_id: ContentId,
-    pub control: ApplicationControlProperty,
+    pub control: Option<ApplicationControlProperty>,
 }
 
 #[derive(Debug)]
@@ -80,27 +80,24 @@ pub fn build_application_set<S: ReadableStorage>(
     }
 
     for content in content_set.values() {
-        match content {
-            AnyContentInfo::Application(app) => {
-                // create ApplicationVersion for the base version
-                let app_version = ApplicationVersion {
-                    version: app.common.metadata.version,
-                    kind: VersionKind::Base,
-                    meta_content_id: app.common.meta_content_id,
-                    programs: make_programs(&app.programs, |pi| (None, pi.program_content_id)),
-                };
-
-                let application = Application {
-                    id: app.id,
-                    patch_id: app.patch_id,
-                    base_version: app_version.version,
-                    versions: BTreeMap::from([(app_version.version, app_version)]),
-                    addons: Default::default(),
-                };
-
-                assert!(applications.insert(app.id, application).is_none());
-            }
-            _ => {}
+        if let AnyContentInfo::Application(app) = content {
+            // create ApplicationVersion for the base version
+            let app_version = ApplicationVersion {
+                version: app.common.metadata.version,
+                kind: VersionKind::Base,
+                meta_content_id: app.common.meta_content_id,
+                programs: make_programs(&app.programs, |pi| (None, pi.program_content_id)),
+            };
+
+            let application = Application {
+                id: app.id,
+                patch_id: app.patch_id,
+                base_version: app_version.version,
+                versions: BTreeMap::from([(app_version.version, app_version)]),
+                addons: Default::default(),
+            };
+
+            assert!(applications.insert(app.id, application).is_none());
         }
     }
 
@@ -2,26 +2,39 @@ mod program;
 
 use crate::filesystem::{ReadableDirectoryExt, ReadableFile, ReadableFileSystem};
 use crate::formats::cnmt::{
-    ContentMetaKey, ContentMetaType, ExtendedMetaHeader, NcmContentType, PackagedContentMeta,
+    ContentAccessibilities, ContentMetaAttribute, ContentMetaKey, ContentMetaType,
+    ExtendedMetaHeader, NcmContentType, PackagedContentMeta,
 };
 use crate::formats::nacp::{ApplicationControlProperty, ProgramTitle};
 use crate::formats::nca::filesystem::NcaOpenError;
-use crate::formats::nca::{IntegrityCheckLevel, Nca, NcaContentType, NcaSectionType};
-use crate::ids::{ApplicationId, ContentId, DataId, DataPatchId, PatchId, ProgramId};
-use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
+use crate::formats::nca::{
+    IntegrityCheckLevel, Nca, NcaContentType, NcaError, NcaSectionType, SectionFileSystem,
+    VerifiedPatchedSectionStorage,
+};
+use crate::ids::{ApplicationId, ContentId, DataId, DataPatchId, PatchId, ProgramId, TypedId};
+use crate::storage::{ReadableStorage, ReadableStorageExt, Storage, StorageError};
 use crate::switch_fs::content_set::program::ProgramsParseError;
 use crate::switch_fs::nca_set::NcaSet;
-use binrw::BinRead;
+use binrw::{BinRead, BinWrite};
 use itertools::Itertools;
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use std::collections::BTreeMap;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Limit on the size of the CNMT file, the real ones are a few KiB at most
+const MAX_CNMT_SIZE: u64 = 0x100000;
+
+#[cfg(feature = "serde")]
+pub(crate) use program::read_control;
+pub use program::NpdmVerifyError;
 
 #[derive(Snafu, Debug)]
 pub enum ControlParseError {
     /// Control NCA does not have the data section
     NoDataSection {},
-    /// Control NCA does not the control.nacp file
+    /// Failed to open the data section of the Control NCA
+    DataSectionOpen { source: NcaError },
+    /// Control NCA does not contain the control.nacp file (or any other .nacp file)
     NoControlNacp {},
     /// Failed to open control.nacp
     ControlNacpOpen { source: NcaOpenError },
@@ -35,6 +48,8 @@ pub enum ControlParseError {
 pub enum ContentParseError {
     /// Meta NCA does not have the data section
     MetaNoDataSection {},
+    /// Failed to open the data section of the Meta NCA
+    MetaDataSectionOpen { source: NcaError },
     /// Meta NCA has multiple CNMT
     MetaMultipleCnmt {},
     /// Meta NCA has no CNMT
@@ -77,6 +92,26 @@ pub struct ContentSetParseError {
     pub source: ContentParseError,
 }
 
+#[derive(Snafu, Debug)]
+pub enum DataPatchApplyError {
+    /// The data patch applies to {expected}, not to {actual}
+    DataPatchWrongBase { expected: DataId, actual: DataId },
+    /// NCA {nca_id} of the data patch or its base not found
+    DataPatchMissingNca { nca_id: ContentId },
+    /// NCA {nca_id} does not have the data section
+    DataPatchNoDataSection { nca_id: ContentId },
+    /// Failed to open the data section of NCA {nca_id}
+    DataPatchNca { nca_id: ContentId, source: NcaError },
+}
+
+#[derive(Snafu, Debug)]
+pub enum CnmtWriteError {
+    /// Failed to serialize the CNMT
+    CnmtSerialize { source: binrw::Error },
+    /// Failed to write the CNMT to the storage
+    CnmtStorage { source: StorageError },
+}
+
 #[derive(Debug)]
 pub struct ContentInfoCommon {
     pub metadata: PackagedContentMeta,
@@ -88,6 +123,54 @@ impl ContentInfoCommon {
     pub fn content_meta_key(&self) -> ContentMetaKey {
         self.metadata.content_meta_key()
     }
+
+    pub fn attributes(&self) -> ContentMetaAttribute {
+        self.metadata.attributes
+    }
+
+    /// See [`PackagedContentMeta::contents_detailed`]
+    pub fn contents_detailed(&self) -> impl Iterator<Item = (ContentId, NcmContentType, u64)> + '_ {
+        self.metadata.contents_detailed()
+    }
+
+    /// Total size of the contents, as listed in the CNMT
+    ///
+    /// Like [`ContentInfoCommon::contents`], this skips the delta fragments and doesn't count the meta NCA itself.
+    pub fn total_size(&self) -> u64 {
+        self.contents_detailed()
+            .filter(|&(_, ty, _)| ty != NcmContentType::DeltaFragment)
+            .map(|(_, _, size)| size)
+            .sum()
+    }
+
+    /// Number of contents counted in [`ContentInfoCommon::total_size`]
+    pub fn content_count(&self) -> usize {
+        self.contents_detailed()
+            .filter(|&(_, ty, _)| ty != NcmContentType::DeltaFragment)
+            .count()
+    }
+
+    pub fn includes_exfat(&self) -> bool {
+        self.attributes().includes_exfat()
+    }
+
+    pub fn is_rebootless(&self) -> bool {
+        self.attributes().is_rebootless()
+    }
+
+    pub fn is_compacted(&self) -> bool {
+        self.attributes().is_compacted()
+    }
+
+    /// Serializes the metadata back to the binary `.cnmt` format
+    pub fn write_cnmt(&self, out: &impl Storage) -> Result<(), CnmtWriteError> {
+        let mut cnmt = std::io::Cursor::new(Vec::new());
+        self.metadata.write(&mut cnmt).context(CnmtSerializeSnafu)?;
+        let cnmt = cnmt.into_inner();
+
+        out.set_size(cnmt.len() as u64).context(CnmtStorageSnafu)?;
+        out.write(0, &cnmt).context(CnmtStorageSnafu)
+    }
 }
 
 #[derive(Debug)]
@@ -98,7 +181,8 @@ pub struct ProgramInfo {
     pub program_content_id: ContentId,
     pub control_content_id: ContentId,
     pub html_document_content_id: Option<ContentId>,
-    pub control: ApplicationControlProperty,
+    /// `None` if the content set was parsed without reading the control NCAs
+    pub control: Option<ApplicationControlProperty>,
 }
 
 /// Corresponds to [`ContentMetaType::Application`]
@@ -113,7 +197,9 @@ pub struct ApplicationInfo {
 
 impl ApplicationInfo {
     pub fn any_title(&self) -> Option<&ProgramTitle> {
-        self.programs.iter().find_map(|p| p.control.any_title())
+        self.programs
+            .iter()
+            .find_map(|p| p.control.as_ref()?.any_title())
     }
 }
 
@@ -129,7 +215,9 @@ pub struct PatchInfo {
 
 impl PatchInfo {
     pub fn any_title(&self) -> Option<&ProgramTitle> {
-        self.programs.iter().find_map(|p| p.control.any_title())
+        self.programs
+            .iter()
+            .find_map(|p| p.control.as_ref()?.any_title())
     }
 }
 
@@ -139,17 +227,77 @@ pub struct DataInfo {
     pub id: DataId,
     pub application_id: ApplicationId,
     pub data_patch_id: DataPatchId,
+    pub content_accessibilities: ContentAccessibilities,
     pub data_content: ContentId,
     pub common: ContentInfoCommon,
 }
 
+impl DataInfo {
+    /// Opens the RomFS of the data NCA, i.e. the add-on content itself
+    ///
+    /// `None` if the data NCA is not in `nca_set` or does not have a RomFS
+    pub fn data_fs<S: ReadableStorage>(
+        &self,
+        nca_set: &NcaSet<S>,
+        integrity_level: IntegrityCheckLevel,
+    ) -> Option<Result<SectionFileSystem<S>, NcaError>> {
+        nca_set
+            .get(&self.data_content)?
+            .get_fs(NcaSectionType::Data, integrity_level)
+    }
+}
+
 /// Corresponds to [`ContentMetaType::DataPatch`]
 #[derive(Debug)]
 pub struct DataPatchInfo {
     pub id: DataPatchId,
+    /// The add-on content this patch applies to
+    pub data_id: DataId,
+    pub application_id: ApplicationId,
+    pub data_content: ContentId,
     pub common: ContentInfoCommon,
 }
 
+impl DataPatchInfo {
+    /// Layers the data patch over the data NCA of `base`, giving the RomFS of the patched add-on content
+    ///
+    /// The result can be opened with [`RomFileSystem::new`](crate::formats::romfs::RomFileSystem::new)
+    pub fn apply<S: ReadableStorage>(
+        &self,
+        base: &DataInfo,
+        nca_set: &NcaSet<S>,
+        integrity_level: IntegrityCheckLevel,
+    ) -> Result<VerifiedPatchedSectionStorage<S>, DataPatchApplyError> {
+        ensure!(
+            base.id == self.data_id,
+            DataPatchWrongBaseSnafu {
+                expected: self.data_id,
+                actual: base.id,
+            }
+        );
+
+        let base_nca = nca_set
+            .get(&base.data_content)
+            .context(DataPatchMissingNcaSnafu {
+                nca_id: base.data_content,
+            })?;
+        let patch_nca = nca_set
+            .get(&self.data_content)
+            .context(DataPatchMissingNcaSnafu {
+                nca_id: self.data_content,
+            })?;
+
+        patch_nca
+            .get_patched_storage(NcaSectionType::Data, base_nca, integrity_level)
+            .context(DataPatchNoDataSectionSnafu {
+                nca_id: self.data_content,
+            })?
+            .context(DataPatchNcaSnafu {
+                nca_id: self.data_content,
+            })
+    }
+}
+
 #[derive(Debug)]
 pub enum AnyContentInfo {
     Application(ApplicationInfo),
@@ -171,23 +319,66 @@ impl AnyContentInfo {
     pub fn content_meta_key(&self) -> ContentMetaKey {
         self.common_info().content_meta_key()
     }
+
+    /// NACP of the main program (the first one) of an Application or a Patch
+    ///
+    /// `None` for the Data and DataPatch contents, contents without programs, or if the control NCAs were not read
+    pub fn control(&self) -> Option<&ApplicationControlProperty> {
+        let programs = match self {
+            AnyContentInfo::Application(info) => &info.programs,
+            AnyContentInfo::Patch(info) => &info.programs,
+            AnyContentInfo::Data(_) | AnyContentInfo::DataPatch(_) => return None,
+        };
+        programs.first()?.control.as_ref()
+    }
+
+    /// The content of the base title that the content `content_id` of a Patch or a DataPatch is layered over:
+    /// the base program of a patched program, or the data content of the add-on for a DataPatch
+    ///
+    /// `None` for the contents that are not patches, or if the base title is not in `content_set`
+    pub fn patch_base(&self, content_set: &ContentSet, content_id: ContentId) -> Option<ContentId> {
+        match self {
+            AnyContentInfo::Patch(patch) => {
+                let base_program_id = patch
+                    .programs
+                    .iter()
+                    .find(|p| p.program_content_id == content_id)?
+                    .base_program_id?;
+                content_set.values().find_map(|info| match info {
+                    AnyContentInfo::Application(app) if app.id == patch.application_id => app
+                        .programs
+                        .iter()
+                        .find(|p| p.id == base_program_id)
+                        .map(|p| p.program_content_id),
+                    _ => None,
+                })
+            }
+            AnyContentInfo::DataPatch(patch) if patch.data_content == content_id => {
+                content_set.values().find_map(|info| match info {
+                    AnyContentInfo::Data(data) if data.id == patch.data_id => {
+                        Some(data.data_content)
+                    }
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    }
 }
 
 fn find_content_of_type(meta: &PackagedContentMeta, ty: NcmContentType) -> Option<ContentId> {
-    meta.content_info
-        .iter()
-        .find(|ci| ci.content_info.ty == ty)
-        .map(|ci| ci.content_info.id)
+    meta.contents_detailed()
+        .find(|&(_, content_ty, _)| content_ty == ty)
+        .map(|(id, _, _)| id)
 }
 
-fn parse_content<S: ReadableStorage>(
-    meta_content_id: ContentId,
+pub(crate) fn read_meta<S: ReadableStorage>(
     meta_nca: &Nca<S>,
-    nca_set: &NcaSet<S>,
-) -> Result<AnyContentInfo, ContentParseError> {
+) -> Result<PackagedContentMeta, ContentParseError> {
     let fs = meta_nca
         .get_fs(NcaSectionType::Data, IntegrityCheckLevel::Full)
-        .context(MetaNoDataSectionSnafu)?;
+        .context(MetaNoDataSectionSnafu)?
+        .context(MetaDataSectionOpenSnafu)?;
     // find the cnmt file (its name changes, but always ends with .cnmt)
     let meta = fs
         .root()
@@ -203,12 +394,18 @@ fn parse_content<S: ReadableStorage>(
     let meta = meta
         .storage()
         .context(MetaCnmtOpenSnafu)?
-        .read_all()
+        .read_all_capped(MAX_CNMT_SIZE)
         .context(MetaCnmtReadSnafu)?;
     // and parse it!
-    let meta =
-        PackagedContentMeta::read(&mut std::io::Cursor::new(meta)).context(MetaCnmtParseSnafu)?;
+    PackagedContentMeta::read(&mut std::io::Cursor::new(meta)).context(MetaCnmtParseSnafu)
+}
 
+fn parse_content<S: ReadableStorage>(
+    meta_content_id: ContentId,
+    meta: PackagedContentMeta,
+    nca_set: &NcaSet<S>,
+    options: &ContentSetOptions,
+) -> Result<AnyContentInfo, ContentParseError> {
     // dbg!(&meta);
 
     #[allow(clippy::match_single_binding)]
@@ -226,10 +423,9 @@ fn parse_content<S: ReadableStorage>(
         //     .map(|v| v.nca_id_new)
         //     .collect(),
         _ => meta
-            .content_info
-            .iter()
-            .filter(|ci| ci.content_info.ty != NcmContentType::DeltaFragment)
-            .map(|v| v.content_info.id)
+            .contents_detailed()
+            .filter(|&(_, ty, _)| ty != NcmContentType::DeltaFragment)
+            .map(|(id, _, _)| id)
             .collect(),
     };
 
@@ -252,55 +448,52 @@ fn parse_content<S: ReadableStorage>(
         meta_content_id,
     };
 
-    Ok(match meta.ty {
-        ContentMetaType::SystemProgram
-        | ContentMetaType::SystemData
-        | ContentMetaType::SystemUpdate
-        | ContentMetaType::BootImagePackage
-        | ContentMetaType::BootImagePackageSafe
-        | ContentMetaType::Delta => {
+    Ok(match meta.id.typed(meta.ty) {
+        TypedId::Program(_) | TypedId::System(_) | TypedId::Delta(_) => {
             // these are not supported (at least yet)
             return Err(ContentParseError::MetaUnsupportedType { ty: meta.ty });
         }
 
-        ContentMetaType::Application => {
+        TypedId::Application(id) => {
             let ExtendedMetaHeader::Application { patch_id, .. } = meta.extended_header else {
                 unreachable!()
             };
 
-            let programs = program::parse_programs(&meta, nca_set).context(ProgramsParseSnafu)?;
+            let programs =
+                program::parse_programs(&meta, nca_set, options).context(ProgramsParseSnafu)?;
             let legal_information_content =
                 find_content_of_type(&meta, NcmContentType::LegalInformation)
                     .context(MissingLegalInformationNcaSnafu)?;
 
             AnyContentInfo::Application(ApplicationInfo {
-                id: meta.id.into(),
+                id,
                 patch_id,
                 legal_information_content,
                 programs,
                 common,
             })
         }
-        ContentMetaType::Patch => {
+        TypedId::Patch(id) => {
             let ExtendedMetaHeader::Patch { application_id, .. } = meta.extended_header else {
                 unreachable!()
             };
 
-            let programs = program::parse_programs(&meta, nca_set).context(ProgramsParseSnafu)?;
+            let programs =
+                program::parse_programs(&meta, nca_set, options).context(ProgramsParseSnafu)?;
             let legal_information_content =
                 find_content_of_type(&meta, NcmContentType::LegalInformation)
                     .context(MissingLegalInformationNcaSnafu)?;
 
             AnyContentInfo::Patch(PatchInfo {
-                id: meta.id.into(),
+                id,
                 application_id,
                 legal_information_content,
                 programs,
                 common,
             })
         }
-        ContentMetaType::AddOnContent => {
-            let ExtendedMetaHeader::AddOnContent { application_id, data_patch_id, .. } = meta.extended_header else {
+        TypedId::AddOnContent(id) => {
+            let ExtendedMetaHeader::AddOnContent { application_id, data_patch_id, content_accessibilities, .. } = meta.extended_header else {
                 unreachable!()
             };
 
@@ -308,14 +501,35 @@ fn parse_content<S: ReadableStorage>(
                 find_content_of_type(&meta, NcmContentType::Data).context(MissingDataNcaSnafu)?;
 
             AnyContentInfo::Data(DataInfo {
-                id: meta.id.into(),
+                id,
                 application_id,
                 data_patch_id,
+                content_accessibilities,
+                data_content,
+                common,
+            })
+        }
+        TypedId::DataPatch(id) => {
+            let ExtendedMetaHeader::DataPatch {
+                data_id,
+                application_id,
+                ..
+            } = meta.extended_header
+            else {
+                unreachable!()
+            };
+
+            let data_content =
+                find_content_of_type(&meta, NcmContentType::Data).context(MissingDataNcaSnafu)?;
+
+            AnyContentInfo::DataPatch(DataPatchInfo {
+                id,
+                data_id,
+                application_id,
                 data_content,
                 common,
             })
         }
-        ContentMetaType::DataPatch => todo!("Handling of DataPatch is not implemented yet"),
     })
 
     // now identify the main and control NCAs by their content type
@@ -341,22 +555,170 @@ fn parse_content<S: ReadableStorage>(
 
 pub type ContentSet = BTreeMap<ContentMetaKey, AnyContentInfo>;
 
+#[derive(Debug, Clone)]
+pub struct ContentSetOptions {
+    /// Whether to read the NACP from the control NCAs of the programs.
+    ///
+    /// Reading it requires decrypting and verifying the control RomFS, which can be skipped when only the CNMT data is needed.
+    pub read_control: bool,
+}
+
+impl Default for ContentSetOptions {
+    fn default() -> Self {
+        Self { read_control: true }
+    }
+}
+
 pub fn content_set_from_nca_set<S: ReadableStorage>(
     ncas: &NcaSet<S>,
 ) -> Result<ContentSet, ContentSetParseError> {
-    let mut titles = BTreeMap::new();
+    content_set_from_nca_set_with_options(ncas, &ContentSetOptions::default())
+}
 
-    for (&id, nca) in ncas {
-        if nca.content_type() == NcaContentType::Meta {
-            info!("Parsing title for meta nca {}", id);
-            let content =
-                parse_content(id, nca, ncas).context(ContentSetParseSnafu { meta_nca_id: id })?;
+pub fn content_set_from_nca_set_with_options<S: ReadableStorage>(
+    ncas: &NcaSet<S>,
+    options: &ContentSetOptions,
+) -> Result<ContentSet, ContentSetParseError> {
+    content_set_from_nca_set_impl(ncas, options, |_| true)
+}
 
-            // dbg!(&content);
+/// Parses only the contents with the meta types accepted by `filter`
+///
+/// Only the CNMTs of the other contents are read, their programs and control NCAs are skipped.
+pub fn content_set_from_nca_set_filtered<S: ReadableStorage>(
+    ncas: &NcaSet<S>,
+    filter: impl Fn(ContentMetaType) -> bool,
+) -> Result<ContentSet, ContentSetParseError> {
+    content_set_from_nca_set_impl(ncas, &ContentSetOptions::default(), filter)
+}
 
-            titles.insert(content.content_meta_key(), content);
+/// Same as [`content_set_from_nca_set`], but doesn't stop at the titles that fail to parse
+///
+/// Returns the titles that could be parsed, along with the errors for the others,
+/// so that e.g. one malformed add-on doesn't hide the base game of a multi-title NSP.
+pub fn content_set_from_nca_set_lenient<S: ReadableStorage>(
+    ncas: &NcaSet<S>,
+) -> (ContentSet, Vec<ContentSetParseError>) {
+    let options = ContentSetOptions::default();
+    let mut titles = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for content in parse_titles(ncas, &options, |_| true) {
+        match content {
+            Ok(content) => {
+                titles.insert(content.content_meta_key(), content);
+            }
+            Err(e) => {
+                warn!("Skipping a title: {}", e);
+                errors.push(e);
+            }
         }
     }
 
-    Ok(titles)
+    (titles, errors)
+}
+
+/// Parses the title of a meta NCA, `None` if it's skipped by the `filter`
+fn parse_title<S: ReadableStorage>(
+    id: ContentId,
+    nca: &Nca<S>,
+    ncas: &NcaSet<S>,
+    options: &ContentSetOptions,
+    filter: &impl Fn(ContentMetaType) -> bool,
+) -> Result<Option<AnyContentInfo>, ContentSetParseError> {
+    let meta = read_meta(nca).context(ContentSetParseSnafu { meta_nca_id: id })?;
+    if !filter(meta.ty) {
+        info!("Skipping {:?} title for meta nca {}", meta.ty, id);
+        return Ok(None);
+    }
+
+    info!("Parsing title for meta nca {}", id);
+    parse_content(id, meta, ncas, options)
+        .context(ContentSetParseSnafu { meta_nca_id: id })
+        .map(Some)
+}
+
+/// Parses the titles of all the meta NCAs, skipping the ones rejected by the `filter`
+fn parse_titles<'a, S: ReadableStorage>(
+    ncas: &'a NcaSet<S>,
+    options: &'a ContentSetOptions,
+    filter: impl Fn(ContentMetaType) -> bool + 'a,
+) -> impl Iterator<Item = Result<AnyContentInfo, ContentSetParseError>> + 'a {
+    ncas.iter()
+        .filter(|(_, nca)| nca.content_type() == NcaContentType::Meta)
+        .filter_map(move |(&id, nca)| parse_title(id, nca, ncas, options, &filter).transpose())
+}
+
+fn content_set_from_nca_set_impl<S: ReadableStorage>(
+    ncas: &NcaSet<S>,
+    options: &ContentSetOptions,
+    filter: impl Fn(ContentMetaType) -> bool,
+) -> Result<ContentSet, ContentSetParseError> {
+    parse_titles(ncas, options, filter)
+        .map(|content| content.map(|content| (content.content_meta_key(), content)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_set_from_nca_set, content_set_from_nca_set_lenient, ContentInfoCommon};
+    use crate::formats::cnmt::tests::make_application_cnmt;
+    use crate::formats::cnmt::PackagedContentMeta;
+    use crate::formats::nca::test_fixture::{empty_key_set, make_plaintext_nca};
+    use crate::formats::nca::{Nca, NcaContentType};
+    use crate::storage::VecStorage;
+    use crate::switch_fs::nca_set::NcaSet;
+    use binrw::BinRead;
+    use std::io::Cursor;
+
+    #[test]
+    fn write_cnmt_round_trip() {
+        let data = make_application_cnmt();
+        let metadata = PackagedContentMeta::read(&mut Cursor::new(&data)).unwrap();
+        let info = ContentInfoCommon {
+            metadata,
+            contents: Vec::new(),
+            meta_content_id: "00000000000000000000000000000000".parse().unwrap(),
+        };
+
+        let out = VecStorage::new(Vec::new());
+        info.write_cnmt(&out).unwrap();
+        assert_eq!(out.into_inner(), data);
+    }
+
+    #[test]
+    fn total_size() {
+        let data = make_application_cnmt();
+        let metadata = PackagedContentMeta::read(&mut Cursor::new(&data)).unwrap();
+        let info = ContentInfoCommon {
+            metadata,
+            contents: Vec::new(),
+            meta_content_id: "00000000000000000000000000000000".parse().unwrap(),
+        };
+
+        assert_eq!(info.total_size(), 0x1000);
+        assert_eq!(info.content_count(), 1);
+    }
+
+    #[test]
+    fn lenient() {
+        let meta_nca_id = "00000000000000000000000000000001".parse().unwrap();
+        let mut ncas = NcaSet::new();
+        // a meta NCA with a garbage CNMT
+        ncas.insert(
+            meta_nca_id,
+            Nca::new(
+                &empty_key_set(),
+                make_plaintext_nca(NcaContentType::Meta, &[("a.cnmt", b"hello")], 0x200),
+            )
+            .unwrap(),
+        );
+
+        assert!(content_set_from_nca_set(&ncas).is_err());
+
+        let (titles, errors) = content_set_from_nca_set_lenient(&ncas);
+        assert!(titles.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].meta_nca_id, meta_nca_id);
+    }
 }
@@ -1,17 +1,26 @@
 use crate::filesystem::{ReadableFile, ReadableFileSystem};
 use crate::formats::cnmt::{ExtendedMetaHeader, NcmContentType, PackagedContentMeta};
 use crate::formats::nacp::ApplicationControlProperty;
-use crate::formats::nca::{IntegrityCheckLevel, Nca, NcaSectionType};
+use crate::formats::nca::filesystem::NcaOpenError;
+use crate::formats::nca::{IntegrityCheckLevel, Nca, NcaError, NcaSectionType};
+use crate::formats::npdm::Npdm;
 use crate::ids::{ContentId, ProgramId};
-use crate::storage::{ReadableStorage, ReadableStorageExt};
+use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
 use crate::switch_fs::content_set::{
-    ControlNacpOpenSnafu, ControlNacpParseSnafu, ControlNacpReadSnafu, NoControlNacpSnafu,
-    NoDataSectionSnafu,
+    ContentSetOptions, ControlNacpOpenSnafu, ControlNacpParseSnafu, ControlNacpReadSnafu,
+    DataSectionOpenSnafu, NoControlNacpSnafu, NoDataSectionSnafu,
 };
+use crate::switch_fs::nca_set::{MissingNca, NcaSetExt};
 use crate::switch_fs::{ControlParseError, NcaSet, ProgramInfo};
 use binrw::BinRead;
 use snafu::{OptionExt, ResultExt, Snafu};
 use std::collections::BTreeMap;
+use tracing::warn;
+
+// limits on the sizes of the metadata files, so that a malformed NCA can't make us allocate all the memory
+// NACP is always 0x4000 bytes and NPDM is a few KiB at most, so these leave plenty of headroom
+const MAX_NACP_SIZE: u64 = 0x100000;
+const MAX_NPDM_SIZE: u64 = 0x100000;
 
 #[derive(Snafu, Debug)]
 pub enum ProgramParseError {
@@ -19,6 +28,8 @@ pub enum ProgramParseError {
     MissingProgramContent {},
     /// Program is missing the Control NCA
     MissingControlContent {},
+    /// The Control NCA of the program is not present
+    MissingControlNca { source: MissingNca },
     /// Could not parse the Control NCA {control_content_id} for the program
     ControlParse {
         control_content_id: ContentId,
@@ -26,6 +37,29 @@ pub enum ProgramParseError {
     },
 }
 
+#[derive(Snafu, Debug)]
+pub enum NpdmVerifyError {
+    /// The Program NCA of the program is not present
+    MissingProgramNca { source: MissingNca },
+    /// Program NCA does not have the code section
+    NoCodeSection {},
+    /// Failed to open the code section of the Program NCA
+    CodeSectionOpen { source: NcaError },
+    /// Program NCA does not have the main.npdm file
+    NoNpdm {},
+    /// Failed to open main.npdm
+    NpdmOpen { source: NcaOpenError },
+    /// Failed to read main.npdm
+    NpdmRead { source: StorageError },
+    /// Failed to parse main.npdm
+    NpdmParse { source: binrw::Error },
+    /// The program id in main.npdm ({actual}) does not match the one derived from the CNMT ({expected})
+    NpdmProgramIdMismatch {
+        expected: ProgramId,
+        actual: ProgramId,
+    },
+}
+
 /// Could not parse one of the programs
 #[derive(Snafu, Debug)]
 pub struct ProgramsParseError {
@@ -33,23 +67,78 @@ pub struct ProgramsParseError {
     source: ProgramParseError,
 }
 
-fn read_control<S: ReadableStorage>(
+pub(crate) fn read_control<S: ReadableStorage>(
     nca: &Nca<S>,
 ) -> Result<ApplicationControlProperty, ControlParseError> {
     let fs = nca
         .get_fs(NcaSectionType::Data, IntegrityCheckLevel::Full)
-        .context(NoDataSectionSnafu)?;
+        .context(NoDataSectionSnafu)?
+        .context(DataSectionOpenSnafu)?;
 
-    let file = fs.open_file("/control.nacp").context(NoControlNacpSnafu)?;
+    let file = match fs.open_file("/control.nacp") {
+        Some(file) => file,
+        None => {
+            // some control NCAs have a non-standard layout, try to find the nacp elsewhere
+            let (path, file) = fs
+                .file_map()
+                .into_iter()
+                .find(|(n, _)| n.ends_with(".nacp"))
+                .context(NoControlNacpSnafu)?;
+            warn!(
+                "No /control.nacp in the control NCA, using {} instead",
+                path
+            );
+            file
+        }
+    };
     let control = file
         .storage()
         .context(ControlNacpOpenSnafu)?
-        .read_all()
+        .read_all_capped(MAX_NACP_SIZE)
         .context(ControlNacpReadSnafu)?;
     ApplicationControlProperty::read(&mut std::io::Cursor::new(control))
         .context(ControlNacpParseSnafu)
 }
 
+impl ProgramInfo {
+    /// Checks that the program id in the `main.npdm` of the program NCA matches the one derived from the CNMT
+    ///
+    /// A mismatch indicates mislabeled or repacked content.
+    pub fn verify_npdm<S: ReadableStorage>(
+        &self,
+        nca_set: &NcaSet<S>,
+    ) -> Result<(), NpdmVerifyError> {
+        let nca = nca_set
+            .expect_content(self.program_content_id)
+            .context(MissingProgramNcaSnafu)?;
+        let fs = nca
+            .get_fs(NcaSectionType::Code, IntegrityCheckLevel::Full)
+            .context(NoCodeSectionSnafu)?
+            .context(CodeSectionOpenSnafu)?;
+
+        let file = fs.open_file("/main.npdm").context(NoNpdmSnafu)?;
+        let npdm = file
+            .storage()
+            .context(NpdmOpenSnafu)?
+            .read_all_capped(MAX_NPDM_SIZE)
+            .context(NpdmReadSnafu)?;
+        let npdm = Npdm::read(&mut std::io::Cursor::new(npdm)).context(NpdmParseSnafu)?;
+
+        // patches contain the program of the base application
+        let expected = self.base_program_id.unwrap_or(self.id);
+        let actual = npdm.aci.program_id;
+        if expected != actual {
+            warn!(
+                "Program {} has a main.npdm with a different program id {}",
+                expected, actual
+            );
+            return NpdmProgramIdMismatchSnafu { expected, actual }.fail();
+        }
+
+        Ok(())
+    }
+}
+
 struct ProgramInfoBuilder {
     id: ProgramId,
     base_program_id: Option<ProgramId>,
@@ -72,13 +161,20 @@ impl ProgramInfoBuilder {
     fn build<S: ReadableStorage>(
         self,
         nca_set: &NcaSet<S>,
+        options: &ContentSetOptions,
     ) -> Result<ProgramInfo, ProgramParseError> {
         let program_content_id = self.program_content.context(MissingProgramContentSnafu)?;
         let control_content_id = self.control_content.context(MissingControlContentSnafu)?;
         let html_document_content_id = self.html_document_content;
 
-        let control = nca_set.get(&control_content_id).unwrap();
-        let control = read_control(control).context(ControlParseSnafu { control_content_id })?;
+        let control = if options.read_control {
+            let control = nca_set
+                .expect_content(control_content_id)
+                .context(MissingControlNcaSnafu)?;
+            Some(read_control(control).context(ControlParseSnafu { control_content_id })?)
+        } else {
+            None
+        };
 
         Ok(ProgramInfo {
             id: self.id,
@@ -95,6 +191,7 @@ pub fn parse_programs<S: ReadableStorage>(
     meta: &PackagedContentMeta,
     // pre-condition: all the NCAs mentioned in the meta are in the NCA set
     nca_set: &NcaSet<S>,
+    options: &ContentSetOptions,
 ) -> Result<Vec<ProgramInfo>, ProgramsParseError> {
     let id_base = meta.id;
     let base_id_base =
@@ -116,7 +213,29 @@ pub fn parse_programs<S: ReadableStorage>(
             .or_insert_with(|| ProgramInfoBuilder::new(program_id, base_program_id));
 
         match content.ty {
-            NcmContentType::Program => builder.program_content = Some(content.id),
+            NcmContentType::Program => {
+                // a program can be split across multiple NCAs, the main one has the lowest content index
+                let content_index = |id| nca_set.get(id).map(|nca: &Nca<S>| nca.content_index());
+                let program_content = match builder.program_content {
+                    Some(existing) => {
+                        let (main, ignored) =
+                            if content_index(&existing) <= content_index(&content.id) {
+                                (existing, content.id)
+                            } else {
+                                (content.id, existing)
+                            };
+                        warn!(
+                            "Program {} has multiple program NCAs, ignoring {} (content index {:?})",
+                            program_id,
+                            ignored,
+                            content_index(&ignored)
+                        );
+                        main
+                    }
+                    None => content.id,
+                };
+                builder.program_content = Some(program_content);
+            }
             NcmContentType::Control => builder.control_content = Some(content.id),
             NcmContentType::HtmlDocument => builder.html_document_content = Some(content.id),
 
@@ -133,7 +252,7 @@ pub fn parse_programs<S: ReadableStorage>(
         .into_iter()
         .map(|(program, builder)| {
             builder
-                .build(nca_set)
+                .build(nca_set, options)
                 .context(ProgramsParseSnafu { program })
         })
         .collect()
@@ -1,20 +1,33 @@
 pub mod application_set;
 pub mod content_set;
 pub mod nca_set;
+pub mod nsp;
 mod tickets;
+pub mod verify;
 
 use crate::crypto::keyset::KeySet;
-use crate::filesystem::ReadableFileSystem;
-use snafu::{ResultExt, Snafu};
+use crate::filesystem::{ExtractError, ReadableDirectoryExt, ReadableFileSystem};
+use crate::formats::cnmt::ContentMetaKey;
+use crate::formats::nca::filesystem::{NcaFileSystem, NcaOpenError};
+use crate::formats::nca::{
+    IntegrityCheckLevel, NcaContentType, NcaError, NcaSectionType, SectionFileSystem,
+};
+use crate::formats::romfs::RomFsParseError;
+use crate::ids::{ApplicationId, ContentId, RightsId};
+use crate::storage::ReadableStorage;
+use snafu::{OptionExt, ResultExt, Snafu};
 use std::fmt::Debug;
+use std::path::Path;
 
 pub use crate::switch_fs::tickets::{import_tickets, TicketImportError};
-use application_set::{build_application_set, ApplicationSet};
+use application_set::{build_application_set, Application, ApplicationSet};
 use content_set::{
-    content_set_from_nca_set, AnyContentInfo, ContentSet, ContentSetParseError, ControlParseError,
+    content_set_from_nca_set_with_options, AnyContentInfo, ApplicationInfo, ContentSet,
+    ContentSetOptions, ContentSetParseError, ControlParseError, DataInfo, DataPatchInfo, PatchInfo,
     ProgramInfo,
 };
 use nca_set::{nca_set_from_fs, NcaSet, NcaSetParseError};
+use verify::{verify_content_set, VerificationReport};
 
 #[derive(Snafu, Debug)]
 pub enum NewSwitchFsError {
@@ -27,21 +40,135 @@ pub enum NewSwitchFsError {
     TitleSetParse { source: ContentSetParseError },
 }
 
+#[derive(Snafu, Debug)]
+pub enum ExtractTitleError {
+    /// Could not find title {key:?}
+    TitleNotFound { key: ContentMetaKey },
+    /// Title {key:?} does not have any programs
+    NoPrograms { key: ContentMetaKey },
+    /// Could not find the title that the patch {key:?} applies to
+    MissingBase { key: ContentMetaKey },
+    /// Could not find NCA {content_id}
+    MissingNca { content_id: ContentId },
+    /// NCA {content_id} does not have a RomFS section
+    NoRomfs { content_id: ContentId },
+    /// Failed to open the RomFS of NCA {content_id}
+    RomfsOpen {
+        content_id: ContentId,
+        source: NcaError,
+    },
+    /// Failed to parse the patched RomFS of NCA {content_id}
+    PatchedRomfsParse {
+        content_id: ContentId,
+        source: RomFsParseError,
+    },
+    /// Failed to extract the RomFS
+    Extract { source: ExtractError<NcaOpenError> },
+}
+
+#[derive(Snafu, Debug)]
+pub enum OpenManualError {
+    /// Could not find NCA {content_id}
+    ManualMissingNca { content_id: ContentId },
+    /// NCA {content_id} is a {content_type}, not a Manual one
+    NotManual {
+        content_id: ContentId,
+        content_type: NcaContentType,
+    },
+    /// Manual NCA {content_id} does not have a RomFS section
+    ManualNoRomfs { content_id: ContentId },
+    /// Failed to open the RomFS of the Manual NCA {content_id}
+    ManualRomfsOpen {
+        content_id: ContentId,
+        source: NcaError,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// The integrity check level used when reading the RomFS
+    pub integrity_level: IntegrityCheckLevel,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            integrity_level: IntegrityCheckLevel::Full,
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct SwitchFs<F: ReadableFileSystem> {
-    nca_set: NcaSet<F::Storage>,
+pub struct SwitchFs<S: ReadableStorage> {
+    nca_set: NcaSet<S>,
     title_set: ContentSet,
     application_set: ApplicationSet,
 }
 
-impl<F: ReadableFileSystem> SwitchFs<F> {
-    pub fn new(key_set: &KeySet, fs: &F) -> Result<Self, NewSwitchFsError> {
+impl<S: ReadableStorage> SwitchFs<S> {
+    pub fn new<F: ReadableFileSystem<Storage = S>>(
+        key_set: &KeySet,
+        fs: &F,
+    ) -> Result<Self, NewSwitchFsError> {
+        Self::new_with_options(key_set, fs, &ContentSetOptions::default())
+    }
+
+    /// Same as [`SwitchFs::new`], but does not read the control NCAs, leaving [`ProgramInfo::control`] as `None`.
+    ///
+    /// Much faster when only the CNMT data (ids, types and versions) is needed.
+    pub fn new_metadata_only<F: ReadableFileSystem<Storage = S>>(
+        key_set: &KeySet,
+        fs: &F,
+    ) -> Result<Self, NewSwitchFsError> {
+        Self::new_with_options(
+            key_set,
+            fs,
+            &ContentSetOptions {
+                read_control: false,
+            },
+        )
+    }
+
+    fn new_with_options<F: ReadableFileSystem<Storage = S>>(
+        key_set: &KeySet,
+        fs: &F,
+        options: &ContentSetOptions,
+    ) -> Result<Self, NewSwitchFsError> {
         let mut key_set = key_set.clone();
 
         import_tickets(&mut key_set, fs).context(TicketImportSnafu)?;
 
         let nca_set = nca_set_from_fs(&key_set, fs).context(NcaSetParseSnafu)?;
-        let title_set = content_set_from_nca_set(&nca_set).context(TitleSetParseSnafu)?;
+        Self::from_nca_set_with_options(nca_set, options)
+    }
+
+    /// Lists the RightsIds of the title keys that are needed to open `fs`, but are neither in the `key_set` nor in the tickets in `fs`
+    ///
+    /// Use it before [`SwitchFs::new`] to tell the user which tickets are missing.
+    pub fn missing_title_keys<F: ReadableFileSystem<Storage = S>>(
+        key_set: &KeySet,
+        fs: &F,
+    ) -> Result<Vec<RightsId>, NewSwitchFsError> {
+        let mut key_set = key_set.clone();
+
+        import_tickets(&mut key_set, fs).context(TicketImportSnafu)?;
+
+        nca_set::missing_title_keys(&key_set, fs).context(NcaSetParseSnafu)
+    }
+
+    /// Builds a [`SwitchFs`] from an already assembled NCA set, e.g. from loose NCA files
+    ///
+    /// No tickets are imported, so the NCAs must have been opened with the title keys already in the key set.
+    pub fn from_nca_set(nca_set: NcaSet<S>) -> Result<Self, NewSwitchFsError> {
+        Self::from_nca_set_with_options(nca_set, &ContentSetOptions::default())
+    }
+
+    fn from_nca_set_with_options(
+        nca_set: NcaSet<S>,
+        options: &ContentSetOptions,
+    ) -> Result<Self, NewSwitchFsError> {
+        let title_set =
+            content_set_from_nca_set_with_options(&nca_set, options).context(TitleSetParseSnafu)?;
         let application_set = build_application_set(&nca_set, &title_set);
 
         Ok(Self {
@@ -51,7 +178,7 @@ impl<F: ReadableFileSystem> SwitchFs<F> {
         })
     }
 
-    pub fn nca_set(&self) -> &NcaSet<F::Storage> {
+    pub fn nca_set(&self) -> &NcaSet<S> {
         &self.nca_set
     }
 
@@ -62,4 +189,125 @@ impl<F: ReadableFileSystem> SwitchFs<F> {
     pub fn application_set(&self) -> &ApplicationSet {
         &self.application_set
     }
+
+    /// Checks every title: all the NCAs listed in the CNMTs are present with the expected size and hash,
+    /// and all their sections pass the integrity verification
+    ///
+    /// Reads all the contents, so this is slow for large titles.
+    /// The hashes of NCZs are not checked, as that would require recompressing them.
+    pub fn verify(&self) -> VerificationReport {
+        verify_content_set(&self.nca_set, &self.title_set)
+    }
+
+    /// Finds an application along with all its versions (base & patches) and add-ons
+    pub fn application(&self, id: ApplicationId) -> Option<&Application> {
+        self.application_set.get(&id)
+    }
+
+    /// Opens the RomFS of a Manual NCA, like the one referenced by [`ProgramInfo::html_document_content_id`] (the offline manual)
+    /// or by [`ApplicationInfo::legal_information_content`](content_set::ApplicationInfo::legal_information_content)
+    pub fn manual_fs(
+        &self,
+        content_id: ContentId,
+    ) -> Result<SectionFileSystem<S>, OpenManualError> {
+        let nca = self
+            .nca_set
+            .get(&content_id)
+            .context(ManualMissingNcaSnafu { content_id })?;
+        let content_type = nca.content_type();
+        if content_type != NcaContentType::Manual {
+            return NotManualSnafu {
+                content_id,
+                content_type,
+            }
+            .fail();
+        }
+
+        nca.get_fs(NcaSectionType::Data, IntegrityCheckLevel::Full)
+            .context(ManualNoRomfsSnafu { content_id })?
+            .context(ManualRomfsOpenSnafu { content_id })
+    }
+
+    /// Extracts the RomFS of the title's main program (or of the data content for add-ons) to `dest`
+    ///
+    /// For patches and data patches the title they apply to must be present too, the extracted RomFS is the updated one.
+    pub fn extract_title(
+        &self,
+        key: &ContentMetaKey,
+        dest: &Path,
+    ) -> Result<(), ExtractTitleError> {
+        self.extract_title_with_options(key, dest, &ExtractOptions::default())
+    }
+
+    /// Same as [`SwitchFs::extract_title`], but with the integrity check level from `options`
+    pub fn extract_title_with_options(
+        &self,
+        key: &ContentMetaKey,
+        dest: &Path,
+        options: &ExtractOptions,
+    ) -> Result<(), ExtractTitleError> {
+        let title = self
+            .title_set
+            .get(key)
+            .context(TitleNotFoundSnafu { key: *key })?;
+
+        let content_id = match title {
+            AnyContentInfo::Application(ApplicationInfo { programs, .. })
+            | AnyContentInfo::Patch(PatchInfo { programs, .. }) => {
+                main_program(programs)
+                    .context(NoProgramsSnafu { key: *key })?
+                    .program_content_id
+            }
+            AnyContentInfo::Data(DataInfo { data_content, .. })
+            | AnyContentInfo::DataPatch(DataPatchInfo { data_content, .. }) => *data_content,
+        };
+        // patches only store the changes, so the RomFS is read over the one of the title they update
+        let base_content_id = match title {
+            AnyContentInfo::Application(_) | AnyContentInfo::Data(_) => None,
+            AnyContentInfo::Patch(_) | AnyContentInfo::DataPatch(_) => Some(
+                title
+                    .patch_base(&self.title_set, content_id)
+                    .context(MissingBaseSnafu { key: *key })?,
+            ),
+        };
+
+        let nca = self
+            .nca_set
+            .get(&content_id)
+            .context(MissingNcaSnafu { content_id })?;
+        let section_type = NcaSectionType::Data;
+        let integrity_level = options.integrity_level;
+
+        match base_content_id {
+            None => {
+                let fs = nca
+                    .get_fs(section_type, integrity_level)
+                    .context(NoRomfsSnafu { content_id })?
+                    .context(RomfsOpenSnafu { content_id })?;
+
+                fs.root().extract_to_parallel(dest).context(ExtractSnafu)
+            }
+            Some(base_content_id) => {
+                let base = self
+                    .nca_set
+                    .get(&base_content_id)
+                    .context(MissingNcaSnafu {
+                        content_id: base_content_id,
+                    })?;
+                let storage = nca
+                    .get_patched_storage(section_type, base, integrity_level)
+                    .context(NoRomfsSnafu { content_id })?
+                    .context(RomfsOpenSnafu { content_id })?;
+                let fs = NcaFileSystem::new_romfs(storage)
+                    .context(PatchedRomfsParseSnafu { content_id })?;
+
+                fs.root().extract_to_parallel(dest).context(ExtractSnafu)
+            }
+        }
+    }
+}
+
+/// The main program is the one with the lowest id, the others are e.g. the mini-games of a collection
+fn main_program(programs: &[ProgramInfo]) -> Option<&ProgramInfo> {
+    programs.iter().min_by_key(|p| p.id)
 }
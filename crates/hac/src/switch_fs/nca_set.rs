@@ -1,24 +1,105 @@
 use crate::crypto::keyset::KeySet;
-use crate::filesystem::{ReadableDirectoryExt, ReadableFile, ReadableFileSystem};
+use crate::filesystem::{ReadableFile, ReadableFileSystem};
 use crate::formats::nca::Nca;
-use crate::ids::ContentId;
-use snafu::{ResultExt, Snafu};
-use std::collections::BTreeMap;
-use tracing::info;
+use crate::formats::nca::{NcaError, Ncz, NczError};
+use crate::ids::{AnyId, ContentId, RightsId};
+use crate::parallel::parallel_chunks;
+use crate::storage::{FileRoStorage, ReadableStorage, ReadableStorageExt, StorageError};
+use crate::switch_fs::tickets::{import_ticket_from_storage, TicketImportError};
+use binrw::{BinRead, BinWrite};
+use digest::Digest;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 
 #[derive(Snafu, Debug)]
 pub enum NcaSetParseError {
     /// Failed to parse {nca_id}.nca
-    NcaParse {
+    NcaParse { nca_id: ContentId, source: NcaError },
+    /// Missing the title key for {nca_id}.nca of title {title_id} (rights id {rights_id}), import the ticket for it
+    MissingTitleKey {
         nca_id: ContentId,
-        source: crate::formats::nca::NcaError,
+        title_id: AnyId,
+        rights_id: RightsId,
     },
+    /// Failed to open {path}: {message}
+    NcaFileOpen { path: String, message: String },
     /// Failed to parse NCA filename
     NcaFilenameParse { source: crate::ids::IdParseError },
+    /// Failed to read the directory {path:?}
+    DirRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Failed to open {path:?}
+    FileOpen { path: PathBuf, source: StorageError },
+    /// Failed to import the ticket {path:?}
+    TicketImport {
+        path: PathBuf,
+        source: TicketImportError,
+    },
+}
+
+#[derive(Snafu, Debug)]
+pub enum OpenNcaCachedError {
+    /// Failed to open {path:?}
+    CachedFileOpen { path: PathBuf, source: StorageError },
+    /// Failed to open the NCZ {path:?}
+    CachedNczOpen { path: PathBuf, source: NczError },
+    /// Failed to create the cache directory {path:?}
+    CacheDirCreate {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Failed to write the decompressed NCA {path:?}
+    CacheSave { path: PathBuf, source: StorageError },
+    /// Failed to move the decompressed NCA to {path:?}
+    CacheRename {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Failed to parse the NCA {path:?}
+    CachedNcaParse { path: PathBuf, source: NcaError },
+}
+
+fn nca_parse_error(nca_id: ContentId, source: NcaError) -> NcaSetParseError {
+    match source {
+        NcaError::MissingTitleKey { title_id, source } => NcaSetParseError::MissingTitleKey {
+            nca_id,
+            title_id,
+            rights_id: source.rights_id,
+        },
+        source => NcaSetParseError::NcaParse { nca_id, source },
+    }
 }
 
 pub type NcaSet<S> = BTreeMap<ContentId, Nca<S>>;
 
+/// NCA {nca_id} is missing from the NCA set
+#[derive(Snafu, Debug)]
+pub struct MissingNca {
+    nca_id: ContentId,
+}
+
+impl MissingNca {
+    pub fn nca_id(&self) -> ContentId {
+        self.nca_id
+    }
+}
+
+pub trait NcaSetExt<S: ReadableStorage> {
+    /// Gets an NCA that is expected to be in the set, e.g. because it is referenced by a CNMT
+    fn expect_content(&self, nca_id: ContentId) -> Result<&Nca<S>, MissingNca>;
+}
+
+impl<S: ReadableStorage> NcaSetExt<S> for NcaSet<S> {
+    fn expect_content(&self, nca_id: ContentId) -> Result<&Nca<S>, MissingNca> {
+        self.get(&nca_id).context(MissingNcaSnafu { nca_id })
+    }
+}
+
 /// Parse an NCA filename
 /// Return value of Ok(None) means "doesn't look like an NCA filename"
 /// Return value of Err(E) means "looks like an NCA filename, but it's invalid (non-hex chars or wrong length)"
@@ -34,24 +115,407 @@ fn parse_nca_filename(filename: &str) -> Result<Option<ContentId>, NcaSetParseEr
         .context(NcaFilenameParseSnafu)
 }
 
+/// All the `.nca` and `.ncz` files in `fs` with their ids, in the filename order
+fn nca_files<F: ReadableFileSystem>(
+    fs: &F,
+) -> impl Iterator<Item = Result<(ContentId, F::Storage), NcaSetParseError>> + '_ {
+    fs.file_map().into_iter().filter_map(|(path, file)| {
+        parse_nca_filename(file.name()).transpose().map(|nca_id| {
+            let nca_id = nca_id?;
+            // the error type depends on the FS implementation, so only its message is kept
+            let storage = file.storage().map_err(|e| {
+                NcaFileOpenSnafu {
+                    path,
+                    message: e.to_string(),
+                }
+                .build()
+            })?;
+            Ok((nca_id, storage))
+        })
+    })
+}
+
 pub fn nca_set_from_fs<F: ReadableFileSystem>(
     key_set: &KeySet,
     fs: &F,
 ) -> Result<NcaSet<F::Storage>, NcaSetParseError> {
+    nca_set_from_fs_impl(key_set, fs, &mut BTreeMap::new())
+}
+
+/// Same as [`nca_set_from_fs`], but parses the NCAs on all the available cores
+///
+/// The storages are still opened sequentially, only the parsing (reading the headers and deriving the keys) is parallel.
+/// Worth it for big libraries with hundreds of NCAs, where the reads of the headers dominate.
+pub fn nca_set_from_fs_parallel<F: ReadableFileSystem>(
+    key_set: &KeySet,
+    fs: &F,
+) -> Result<NcaSet<F::Storage>, NcaSetParseError> {
+    let storages = nca_files(fs).collect::<Result<Vec<_>, _>>()?;
+
+    let chunks = parallel_chunks(storages, |chunk| {
+        chunk
+            .into_iter()
+            .map(|(nca_id, storage)| {
+                info!("Parsing NCA {}", nca_id);
+                Nca::new(key_set, storage)
+                    .and_then(|nca| nca.check_content_key().map(|_| nca))
+                    .map(|nca| (nca_id, nca))
+                    .map_err(|e| nca_parse_error(nca_id, e))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    // the chunks are in the filename order, so the error reported is the same as for `nca_set_from_fs`
+    chunks.into_iter().flatten().collect()
+}
+
+const NCA_SET_CACHE_VERSION: u32 = 2;
+/// Size of the main NCA header, which pins the FS headers through their hashes
+const MAIN_HEADER_SIZE: usize = 0x400;
+
+/// On-disk cache of the decrypted NCA headers, used by [`nca_set_from_fs_cached`]
+#[derive(BinRead, BinWrite)]
+#[brw(little, magic = b"HNSC")]
+struct NcaSetCache {
+    #[br(assert(version == NCA_SET_CACHE_VERSION))]
+    version: u32,
+    entry_count: u32,
+    #[br(count = entry_count)]
+    entries: Vec<NcaSetCacheEntry>,
+}
+
+#[derive(BinRead, BinWrite)]
+struct NcaSetCacheEntry {
+    nca_id: ContentId,
+    /// Size of the storage the headers were read from, used to detect changed files
+    storage_size: u64,
+    /// SHA-256 of the raw (still encrypted) main header, catching the files replaced by others of the same size
+    header_hash: [u8; 0x20],
+    #[br(parse_with = crate::brw_utils::read_bool)]
+    #[bw(write_with = crate::brw_utils::write_bool)]
+    is_plaintext: bool,
+    raw_headers: [u8; 0xc00],
+}
+
+/// `None` if the storage is too small to be an NCA
+fn main_header_hash(storage: &impl ReadableStorage) -> Option<[u8; 0x20]> {
+    let mut header = [0; MAIN_HEADER_SIZE];
+    storage.read(0, &mut header).ok()?;
+    Some(sha2::Sha256::digest(header).into())
+}
+
+fn read_nca_set_cache(path: &Path) -> BTreeMap<ContentId, NcaSetCacheEntry> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        // no cache yet, nothing to warn about
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return BTreeMap::new(),
+        Err(e) => {
+            warn!("Could not open the NCA set cache, ignoring it: {}", e);
+            return BTreeMap::new();
+        }
+    };
+
+    match NcaSetCache::read(&mut BufReader::new(file)) {
+        Ok(cache) => cache.entries.into_iter().map(|e| (e.nca_id, e)).collect(),
+        Err(e) => {
+            warn!("Could not read the NCA set cache, ignoring it: {}", e);
+            BTreeMap::new()
+        }
+    }
+}
+
+fn write_nca_set_cache(
+    entries: BTreeMap<ContentId, NcaSetCacheEntry>,
+    path: &Path,
+) -> Result<(), binrw::Error> {
+    let cache = NcaSetCache {
+        version: NCA_SET_CACHE_VERSION,
+        entry_count: entries.len() as u32,
+        entries: entries.into_values().collect(),
+    };
+
+    // write to a temporary file first, so that an interrupted write doesn't leave a truncated cache
+    let temp_path = path.with_extension("tmp");
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+    cache.write(&mut writer)?;
+    writer.into_inner().map_err(|e| e.into_error())?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Same as [`nca_set_from_fs`], but reuses the headers stored in the cache file at `cache_path`
+/// for all the NCAs whose size and main header did not change, avoiding reading and decrypting the rest of the headers again.
+///
+/// A missing or invalid cache is ignored. The cache is updated after the NCA set is parsed,
+/// failing to write it is only a warning, as the cache is just an optimisation.
+pub fn nca_set_from_fs_cached<F: ReadableFileSystem>(
+    key_set: &KeySet,
+    fs: &F,
+    cache_path: &Path,
+) -> Result<NcaSet<F::Storage>, NcaSetParseError> {
+    let mut cache = read_nca_set_cache(cache_path);
+    let ncas = nca_set_from_fs_impl(key_set, fs, &mut cache)?;
+
+    // drop the entries for the NCAs that are gone
+    cache.retain(|nca_id, _| ncas.contains_key(nca_id));
+    if let Err(e) = write_nca_set_cache(cache, cache_path) {
+        warn!("Could not write the NCA set cache, ignoring it: {}", e);
+    }
+
+    Ok(ncas)
+}
+
+fn nca_set_from_fs_impl<F: ReadableFileSystem>(
+    key_set: &KeySet,
+    fs: &F,
+    cache: &mut BTreeMap<ContentId, NcaSetCacheEntry>,
+) -> Result<NcaSet<F::Storage>, NcaSetParseError> {
+    let mut ncas = BTreeMap::new();
+
+    for nca_file in nca_files(fs) {
+        let (nca_id, storage) = nca_file?;
+        let storage_size = storage.get_size();
+        let header_hash = main_header_hash(&storage);
+        let nca = match cache.get(&nca_id) {
+            Some(entry)
+                if entry.storage_size == storage_size && Some(entry.header_hash) == header_hash =>
+            {
+                info!("Using cached headers for NCA {}", nca_id);
+                Nca::from_raw_headers(key_set, storage, &entry.raw_headers, entry.is_plaintext)
+            }
+            _ => {
+                info!("Parsing NCA {}", nca_id);
+                Nca::new(key_set, storage)
+            }
+        }
+        .and_then(|nca| nca.check_content_key().map(|_| nca))
+        .map_err(|e| nca_parse_error(nca_id, e))?;
+        if let Some(header_hash) = header_hash {
+            cache.insert(
+                nca_id,
+                NcaSetCacheEntry {
+                    nca_id,
+                    storage_size,
+                    header_hash,
+                    is_plaintext: nca.is_plaintext(),
+                    raw_headers: *nca.raw_headers(),
+                },
+            );
+        }
+        ncas.insert(nca_id, nca);
+    }
+
+    Ok(ncas)
+}
+
+/// Lists the RightsIds of all the NCAs in `fs` whose title keys are not in the `key_set`
+///
+/// Allows telling up front which tickets are needed, instead of failing at the first NCA without the title key.
+pub fn missing_title_keys<F: ReadableFileSystem>(
+    key_set: &KeySet,
+    fs: &F,
+) -> Result<Vec<RightsId>, NcaSetParseError> {
+    let mut missing = BTreeSet::new();
+
+    for nca_file in nca_files(fs) {
+        let (nca_id, storage) = nca_file?;
+        // only the header key is needed to read the rights id
+        let nca = Nca::new(key_set, storage).map_err(|e| nca_parse_error(nca_id, e))?;
+        if let Some(rights_id) = nca.required_rights_id() {
+            if !key_set.has_title_key(&rights_id) {
+                missing.insert(rights_id);
+            }
+        }
+    }
+
+    Ok(missing.into_iter().collect())
+}
+
+fn list_dir_recursive(path: &Path) -> Result<Vec<PathBuf>, NcaSetParseError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).context(DirReadSnafu { path: &dir })?;
+        for entry in entries {
+            let entry = entry.context(DirReadSnafu { path: &dir })?;
+            let file_type = entry.file_type().context(DirReadSnafu { path: &dir })?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Opens an `.nca` or an `.ncz` file, preferring the decompressed NCA for the latter
+///
+/// For an NCZ, a sibling `.nca` with the same name is used if there is one. Otherwise the NCZ is decompressed
+/// into `cache_dir` on the first use (which reads it whole), and the decompressed NCA is reused afterward.
+pub fn open_nca_cached(
+    path: &Path,
+    cache_dir: &Path,
+    key_set: &KeySet,
+) -> Result<Nca<FileRoStorage>, OpenNcaCachedError> {
+    let path = if path.extension() != Some("ncz".as_ref()) {
+        path.to_path_buf()
+    } else if path.with_extension("nca").is_file() {
+        path.with_extension("nca")
+    } else {
+        let file_name = Path::new(path.file_name().expect("BUG: NCZ path without a file name"));
+        let cache_path = cache_dir.join(file_name.with_extension("nca"));
+        if !cache_path.is_file() {
+            info!("Decompressing {:?} to {:?}", path, cache_path);
+            let storage = FileRoStorage::open(path).context(CachedFileOpenSnafu { path })?;
+            let nca = Ncz::open(storage).context(CachedNczOpenSnafu { path })?;
+            std::fs::create_dir_all(cache_dir).context(CacheDirCreateSnafu { path: cache_dir })?;
+
+            // write to a temporary file first, so that an interrupted decompression doesn't leave a broken cache
+            let temp_path = cache_path.with_extension("nca.tmp");
+            nca.save_to_file(&temp_path)
+                .context(CacheSaveSnafu { path: &temp_path })?;
+            std::fs::rename(&temp_path, &cache_path)
+                .context(CacheRenameSnafu { path: &cache_path })?;
+        }
+        cache_path
+    };
+
+    let storage = FileRoStorage::open(&path).context(CachedFileOpenSnafu { path: &path })?;
+    Nca::new(key_set, storage).context(CachedNcaParseSnafu { path })
+}
+
+/// Builds an NCA set from a directory of loose `.nca`/`.ncz` files (e.g. an extracted NSP)
+///
+/// The tickets (`.tik` files) found in the directory are imported into the `key_set` first.
+pub fn nca_set_from_dir(
+    path: &Path,
+    key_set: &mut KeySet,
+) -> Result<NcaSet<FileRoStorage>, NcaSetParseError> {
+    let files = list_dir_recursive(path)?;
+
+    let tickets = files
+        .iter()
+        .filter(|p| p.extension() == Some("tik".as_ref()));
+    for path in tickets {
+        let storage = FileRoStorage::open(path).context(FileOpenSnafu { path })?;
+        import_ticket_from_storage(key_set, &storage).context(TicketImportSnafu { path })?;
+    }
+
     let mut ncas = BTreeMap::new();
+    for path in files {
+        let Some(nca_id) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(parse_nca_filename)
+            .transpose()?
+            .flatten()
+        else {
+            continue;
+        };
 
-    for file in ReadableDirectoryExt::entries_recursive(&fs.root())
-        .filter(|(n, _)| n.ends_with(".nca") || n.ends_with(".ncz"))
-        .filter_map(|(_, e)| e.file())
-    {
-        // it's hard to report this error, as it depends on the FS implementation
-        // TODO: figure it out, without a panic
-        let storage = file.storage().expect("Malformed FS");
-        let nca_id = parse_nca_filename(file.name())?.expect("BUG: non-NCA filename not filtered");
         info!("Parsing NCA {}", nca_id);
-        let nca = Nca::new(key_set, storage).context(NcaParseSnafu { nca_id })?;
+        let storage = FileRoStorage::open(&path).context(FileOpenSnafu { path: &path })?;
+        let nca = Nca::new(key_set, storage)
+            .and_then(|nca| nca.check_content_key().map(|_| nca))
+            .map_err(|e| nca_parse_error(nca_id, e))?;
         ncas.insert(nca_id, nca);
     }
 
     Ok(ncas)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{nca_set_from_fs_cached, read_nca_set_cache, NcaSet};
+    use crate::formats::nca::test_fixture::{empty_key_set, make_pfs0, make_plaintext_nca};
+    use crate::formats::nca::{Nca, NcaContentType};
+    use crate::formats::pfs::PartitionFileSystem;
+    use crate::ids::ContentId;
+    use crate::storage::VecStorage;
+    use std::path::PathBuf;
+
+    const NCA_ID: &str = "00000000000000000000000000000001";
+
+    /// A PFS0 with a single NCA containing `data`
+    fn make_fs(data: &[u8]) -> PartitionFileSystem<VecStorage> {
+        let nca = make_plaintext_nca(NcaContentType::Meta, &[("a.cnmt", data)], 0x200);
+        let name = format!("{NCA_ID}.nca");
+        PartitionFileSystem::new(VecStorage::new(make_pfs0(&[(&name, &nca.into_inner())]))).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hac-nca-set-cache-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn headers(ncas: &NcaSet<impl crate::storage::ReadableStorage>) -> Vec<u8> {
+        let nca_id: ContentId = NCA_ID.parse().unwrap();
+        ncas[&nca_id].raw_headers().to_vec()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("round-trip");
+        let path = dir.join("cache");
+        let fs = make_fs(b"hello");
+
+        let ncas = nca_set_from_fs_cached(&empty_key_set(), &fs, &path).unwrap();
+        let cache = read_nca_set_cache(&path);
+        assert_eq!(cache.len(), 1);
+        let entry = &cache[&NCA_ID.parse().unwrap()];
+        assert_eq!(entry.raw_headers.to_vec(), headers(&ncas));
+        assert!(entry.is_plaintext);
+
+        // the second time the headers come from the cache
+        let cached = nca_set_from_fs_cached(&empty_key_set(), &fs, &path).unwrap();
+        assert_eq!(headers(&cached), headers(&ncas));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_cache() {
+        let dir = temp_dir("invalid");
+        let path = dir.join("cache");
+        let fs = make_fs(b"hello");
+        nca_set_from_fs_cached(&empty_key_set(), &fs, &path).unwrap();
+        let valid = std::fs::read(&path).unwrap();
+
+        let mut wrong_version = valid.clone();
+        wrong_version[4..8].copy_from_slice(&1u32.to_le_bytes());
+        for contents in [&valid[..valid.len() - 1], b"garbage", &wrong_version] {
+            std::fs::write(&path, contents).unwrap();
+            assert!(read_nca_set_cache(&path).is_empty());
+
+            // falls back to parsing the NCAs, and writes a valid cache again
+            let ncas = nca_set_from_fs_cached(&empty_key_set(), &fs, &path).unwrap();
+            assert_eq!(ncas.len(), 1);
+            assert_eq!(std::fs::read(&path).unwrap(), valid);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stale_entry() {
+        let dir = temp_dir("stale");
+        let path = dir.join("cache");
+        nca_set_from_fs_cached(&empty_key_set(), &make_fs(b"hello"), &path).unwrap();
+
+        // same id and size, but different contents
+        let fs = make_fs(b"HELLO");
+        let ncas = nca_set_from_fs_cached(&empty_key_set(), &fs, &path).unwrap();
+        let expected = Nca::new(
+            &empty_key_set(),
+            make_plaintext_nca(NcaContentType::Meta, &[("a.cnmt", b"HELLO")], 0x200),
+        )
+        .unwrap();
+        assert_eq!(headers(&ncas), expected.raw_headers().to_vec());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+//! Repacking titles into NSPs, the inverse of [`SwitchFs::new`](crate::switch_fs::SwitchFs::new) over an NSP
+
+use crate::formats::pfs::{PartitionFsBuilder, PfsBuildError};
+use crate::formats::ticket::Ticket;
+use crate::ids::{ContentId, RightsId};
+use crate::storage::{ReadableStorage, Storage, VecStorage};
+use crate::switch_fs::content_set::ContentInfoCommon;
+use crate::switch_fs::nca_set::NcaSet;
+use binrw::BinWrite;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::io::Cursor;
+
+#[derive(Snafu, Debug)]
+pub enum NspBuildError {
+    /// NCA {content_id} is missing from the NCA set
+    NspMissingNca { content_id: ContentId },
+    /// NCA {content_id} is an NCZ, it has to be decompressed before repacking
+    NspNczContent { content_id: ContentId },
+    /// Failed to serialize the ticket for {rights_id}
+    TicketSerialize {
+        rights_id: RightsId,
+        source: binrw::Error,
+    },
+    /// Failed to write the NSP
+    NspWrite { source: PfsBuildError },
+}
+
+/// Builds an installable NSP out of a title: its meta NCA, all the contents listed in the CNMT and the tickets
+pub struct NspBuilder<'a, S: ReadableStorage> {
+    nca_set: &'a NcaSet<S>,
+    meta_content_id: ContentId,
+    contents: Vec<ContentId>,
+    tickets: Vec<Ticket>,
+    cert: Option<Vec<u8>>,
+}
+
+impl<'a, S: ReadableStorage> NspBuilder<'a, S> {
+    /// Includes the title described by `content` (e.g. [`AnyContentInfo::common_info`](crate::switch_fs::content_set::AnyContentInfo::common_info)), taking the NCAs from `nca_set`
+    pub fn from_content(nca_set: &'a NcaSet<S>, content: &ContentInfoCommon) -> Self {
+        Self {
+            nca_set,
+            meta_content_id: content.meta_content_id,
+            contents: content.contents.clone(),
+            tickets: Vec::new(),
+            cert: None,
+        }
+    }
+
+    /// Includes a ticket, needed for the NCAs using the RightsId crypto
+    pub fn ticket(mut self, ticket: Ticket) -> Self {
+        self.tickets.push(ticket);
+        self
+    }
+
+    /// Includes the certificate chain the tickets are signed with, stored next to each ticket
+    pub fn cert(mut self, cert: Vec<u8>) -> Self {
+        self.cert = Some(cert);
+        self
+    }
+
+    /// Writes the NSP to `out`
+    pub fn build_into(&self, out: &impl Storage) -> Result<(), NspBuildError> {
+        let mut builder = PartitionFsBuilder::new();
+
+        let ncas = self
+            .contents
+            .iter()
+            .map(|&id| (id, false))
+            .chain(std::iter::once((self.meta_content_id, true)));
+        for (content_id, is_meta) in ncas {
+            let nca = self
+                .nca_set
+                .get(&content_id)
+                .context(NspMissingNcaSnafu { content_id })?;
+            let storage = nca
+                .nca_storage()
+                .context(NspNczContentSnafu { content_id })?;
+
+            let name = if is_meta {
+                format!("{}.cnmt.nca", content_id)
+            } else {
+                format!("{}.nca", content_id)
+            };
+            builder = builder.file(name, storage.clone());
+        }
+
+        for ticket in &self.tickets {
+            let rights_id = ticket.rights_id;
+            let mut data = Cursor::new(Vec::new());
+            ticket
+                .write(&mut data)
+                .context(TicketSerializeSnafu { rights_id })?;
+
+            let name = rights_id.to_string().to_lowercase();
+            builder = builder.file(format!("{}.tik", name), VecStorage::new(data.into_inner()));
+            if let Some(cert) = &self.cert {
+                builder = builder.file(format!("{}.cert", name), VecStorage::new(cert.clone()));
+            }
+        }
+
+        builder.build_into(out).context(NspWriteSnafu)
+    }
+}
@@ -1,10 +1,13 @@
 use crate::crypto::keyset::KeySet;
-use crate::filesystem::{ReadableDirectoryExt, ReadableFile, ReadableFileSystem};
+use crate::filesystem::{ReadableFile, ReadableFileSystem};
 use crate::formats::ticket::Ticket;
-use crate::storage::{ReadableStorageExt, StorageError};
+use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
 use binrw::BinRead;
 use snafu::{ResultExt, Snafu};
 
+/// Limit on the size of the ticket file, the real ones are under 1 KiB
+const MAX_TICKET_SIZE: u64 = 0x10000;
+
 #[derive(Snafu, Debug)]
 pub enum TicketImportError {
     /// Failed to read the ticket file
@@ -17,21 +20,23 @@ pub fn import_tickets<F: ReadableFileSystem>(
     key_set: &mut KeySet,
     fs: &F,
 ) -> Result<(), TicketImportError> {
-    ReadableDirectoryExt::entries_recursive(&fs.root())
+    fs.file_map()
+        .into_iter()
         .filter(|(n, _)| n.ends_with(".tik"))
-        .filter_map(|(_, e)| e.file())
-        .try_for_each(|file| {
+        .try_for_each(|(_, file)| {
             // it's hard to report this error, as it depends on the FS implementation
-            file.storage()
-                .expect("Malformed FS")
-                .read_all()
-                .context(ReadTicketFileSnafu)
-                .and_then(|data| {
-                    Ticket::read(&mut std::io::Cursor::new(data)).context(ParseTicketFileSnafu)
-                })
-                .and_then(|ticket| {
-                    key_set.import_ticket(&ticket);
-                    Ok(())
-                })
+            import_ticket_from_storage(key_set, &file.storage().expect("Malformed FS"))
         })
 }
+
+pub(crate) fn import_ticket_from_storage<S: ReadableStorage>(
+    key_set: &mut KeySet,
+    storage: &S,
+) -> Result<(), TicketImportError> {
+    let data = storage
+        .read_all_capped(MAX_TICKET_SIZE)
+        .context(ReadTicketFileSnafu)?;
+    let ticket = Ticket::read(&mut std::io::Cursor::new(data)).context(ParseTicketFileSnafu)?;
+    key_set.import_ticket(&ticket);
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use crate::formats::cnmt::{ContentMetaKey, NcmContentType};
+use crate::formats::nca::{IntegrityCheckLevel, Nca, NcaError};
+use crate::ids::ContentId;
+use crate::storage::{ReadableStorage, ReadableStorageExt, StorageError};
+use crate::switch_fs::content_set::ContentSet;
+use crate::switch_fs::nca_set::NcaSet;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Snafu, Debug)]
+pub enum ContentVerifyError {
+    /// The NCA is missing from the NCA set
+    ContentMissing {},
+    /// The CNMT says the NCA is {expected} bytes, but it is {actual}
+    ContentSizeMismatch { expected: u64, actual: u64 },
+    /// Failed to read the NCA to compute its hash
+    ContentRead { source: StorageError },
+    /// The hash of the NCA does not match the one in the CNMT
+    ContentHashMismatch {},
+    /// Failed to open section {index}
+    SectionOpen { index: usize, source: NcaError },
+    /// Section {index} failed the integrity verification
+    SectionVerify { index: usize, source: StorageError },
+}
+
+/// Verification result of a single content (NCA) of a title
+#[derive(Debug)]
+pub struct ContentVerification {
+    pub content_id: ContentId,
+    pub content_type: NcmContentType,
+    /// The first check that failed, if any
+    pub result: Result<(), ContentVerifyError>,
+    /// Patch sections that could not be checked, because the content they apply to is missing or the NCA is an NCZ
+    pub skipped_sections: Vec<usize>,
+}
+
+/// Verification results of all the contents of a title
+#[derive(Debug)]
+pub struct TitleVerification {
+    pub key: ContentMetaKey,
+    pub contents: Vec<ContentVerification>,
+}
+
+impl TitleVerification {
+    pub fn is_ok(&self) -> bool {
+        self.contents.iter().all(|c| c.result.is_ok())
+    }
+}
+
+/// Results of [`SwitchFs::verify`](crate::switch_fs::SwitchFs::verify)
+#[derive(Debug)]
+pub struct VerificationReport {
+    pub titles: Vec<TitleVerification>,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.titles.iter().all(|t| t.is_ok())
+    }
+
+    /// All the contents that failed verification, along with the title they belong to
+    pub fn failures(&self) -> impl Iterator<Item = (&ContentMetaKey, &ContentVerification)> {
+        self.titles.iter().flat_map(|t| {
+            t.contents
+                .iter()
+                .filter(|c| c.result.is_err())
+                .map(move |c| (&t.key, c))
+        })
+    }
+}
+
+/// Checks the NCA, patch sections are checked over the same sections of `base`
+///
+/// Returns the patch sections that were skipped
+fn verify_nca<S: ReadableStorage>(
+    nca: &Nca<S>,
+    base: Option<&Nca<S>>,
+    expected_size: u64,
+    expected_hash: &[u8; 0x20],
+) -> Result<Vec<usize>, ContentVerifyError> {
+    let actual = nca.header().nca_size;
+    if actual != expected_size {
+        return ContentSizeMismatchSnafu {
+            expected: expected_size,
+            actual,
+        }
+        .fail();
+    }
+
+    // NCZs store the sections decompressed and decrypted, so the hash of the original NCA can't be checked cheaply
+    if let Some(storage) = nca.nca_storage() {
+        let hash = storage.compute_sha256().context(ContentReadSnafu)?;
+        if &hash != expected_hash {
+            return ContentHashMismatchSnafu.fail();
+        }
+    }
+
+    let mut skipped_sections = Vec::new();
+    for index in 0..4 {
+        let Some(fs_header) = nca.fs_header(index) else {
+            continue;
+        };
+
+        // reading the whole section checks all the hashes
+        if fs_header.is_patch_section() {
+            match base {
+                Some(base) if nca.nca_storage().is_some() => {
+                    if let Some(storage) =
+                        nca.get_patched_section_storage(index, base, IntegrityCheckLevel::Full)
+                    {
+                        let storage = storage.context(SectionOpenSnafu { index })?;
+                        storage
+                            .compute_sha256()
+                            .context(SectionVerifySnafu { index })?;
+                    }
+                }
+                _ => skipped_sections.push(index),
+            }
+        } else if let Some(storage) = nca.get_section_storage(index, IntegrityCheckLevel::Full) {
+            let storage = storage.context(SectionOpenSnafu { index })?;
+            storage
+                .compute_sha256()
+                .context(SectionVerifySnafu { index })?;
+        }
+    }
+
+    Ok(skipped_sections)
+}
+
+pub(crate) fn verify_content_set<S: ReadableStorage>(
+    nca_set: &NcaSet<S>,
+    content_set: &ContentSet,
+) -> VerificationReport {
+    let titles = content_set
+        .iter()
+        .map(|(key, info)| {
+            let contents = info
+                .common_info()
+                .metadata
+                .content_info
+                .iter()
+                .filter(|content| content.content_info.ty != NcmContentType::DeltaFragment)
+                .map(|content| {
+                    let content_id = content.content_info.id;
+                    let base = info
+                        .patch_base(content_set, content_id)
+                        .and_then(|base_content_id| nca_set.get(&base_content_id));
+                    let result = match nca_set.get(&content_id) {
+                        None => ContentMissingSnafu.fail(),
+                        Some(nca) => {
+                            verify_nca(nca, base, content.content_info.size, &content.hash.0)
+                        }
+                    };
+                    let (result, skipped_sections) = match result {
+                        Ok(skipped_sections) => (Ok(()), skipped_sections),
+                        Err(e) => (Err(e), Vec::new()),
+                    };
+
+                    ContentVerification {
+                        content_id,
+                        content_type: content.content_info.ty,
+                        result,
+                        skipped_sections,
+                    }
+                })
+                .collect();
+
+            TitleVerification {
+                key: *key,
+                contents,
+            }
+        })
+        .collect();
+
+    VerificationReport { titles }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_nca, ContentVerifyError};
+    use crate::formats::nca::test_fixture::{
+        empty_key_set, make_plaintext_nca, make_plaintext_patch_nca,
+    };
+    use crate::formats::nca::{Nca, NcaContentType};
+    use crate::storage::{ReadableStorage, ReadableStorageExt};
+
+    #[test]
+    fn verify() {
+        let storage = make_plaintext_nca(NcaContentType::Meta, &[("a.cnmt", b"hello")], 0x200);
+        let size = storage.get_size();
+        let hash = storage.compute_sha256().unwrap();
+        let nca = Nca::new(&empty_key_set(), storage).unwrap();
+
+        assert_eq!(
+            verify_nca(&nca, None, size, &hash).unwrap(),
+            Vec::<usize>::new()
+        );
+        assert!(matches!(
+            verify_nca(&nca, None, size + 1, &hash),
+            Err(ContentVerifyError::ContentSizeMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_nca(&nca, None, size, &[0; 0x20]),
+            Err(ContentVerifyError::ContentHashMismatch {})
+        ));
+    }
+
+    #[test]
+    fn verify_patch() {
+        let base_files: &[(&str, &[u8])] = &[("a.bin", b"hello"), ("b.bin", &[0x42; 0x1000])];
+        let files: &[(&str, &[u8])] = &[("a.bin", b"HELLO"), ("b.bin", &[0x42; 0x1000])];
+        let base = Nca::new(
+            &empty_key_set(),
+            make_plaintext_nca(NcaContentType::Data, base_files, 0x200),
+        )
+        .unwrap();
+        let storage = make_plaintext_patch_nca(NcaContentType::Data, base_files, files, 0x200);
+        let size = storage.get_size();
+        let hash = storage.compute_sha256().unwrap();
+        let patch = Nca::new(&empty_key_set(), storage).unwrap();
+
+        assert_eq!(
+            verify_nca(&patch, Some(&base), size, &hash).unwrap(),
+            Vec::<usize>::new()
+        );
+        // without the base the patch section can't be checked, but it's not an error
+        assert_eq!(verify_nca(&patch, None, size, &hash).unwrap(), vec![0]);
+
+        // the patch is layered over the wrong base, so the hashes don't match
+        let wrong_files: &[(&str, &[u8])] = &[("a.bin", b"hello"), ("b.bin", &[0x43; 0x1000])];
+        let wrong_base = Nca::new(
+            &empty_key_set(),
+            make_plaintext_nca(NcaContentType::Data, wrong_files, 0x200),
+        )
+        .unwrap();
+        assert!(matches!(
+            verify_nca(&patch, Some(&wrong_base), size, &hash),
+            Err(ContentVerifyError::SectionVerify { index: 0, .. })
+        ));
+    }
+}
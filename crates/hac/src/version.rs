@@ -1,7 +1,10 @@
 use binrw::{BinRead, BinWrite};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use std::fmt;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, BinRead, BinWrite)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Version(u32);
 
 impl Version {